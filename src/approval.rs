@@ -0,0 +1,340 @@
+use crate::error::EllieError;
+
+/// Decides whether function calls that are not marked as safe may be
+/// executed.
+pub trait Approver: std::fmt::Debug + Send + Sync {
+    /// Approve a call to the given function with the given arguments,
+    /// asking the given question if the user is asked
+    /// (a generic one by default),
+    /// returning the arguments to execute the function with,
+    /// or [`None`] if denied.
+    ///
+    /// # Errors
+    /// If the user interaction failed.
+    fn approve(
+        &self,
+        name: &str,
+        arguments: &str,
+        prompt: Option<&str>,
+    ) -> dialoguer::Result<Option<String>>;
+}
+
+/// What to do with a call when its approval prompt times out.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum TimeoutAction {
+    /// Deny the call.
+    #[default]
+    Deny,
+    /// Approve the call.
+    Approve,
+}
+
+/// Wait up to the given duration for the user to press Enter in the
+/// terminal,
+/// returning whether they did.
+///
+/// The line is consumed,
+/// so that it is not taken as an answer to the prompt that follows.
+#[cfg(unix)]
+#[inline]
+pub(crate) fn wait_for_user(timeout: std::time::Duration) -> std::io::Result<bool> {
+    use std::io::BufRead as _;
+    use std::os::fd::AsRawFd as _;
+
+    let tty = std::fs::File::open("/dev/tty")?;
+    let mut fds = [libc::pollfd {
+        fd: tty.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    }];
+    let millis = libc::c_int::try_from(timeout.as_millis()).unwrap_or(libc::c_int::MAX);
+    // SAFETY: a single valid `pollfd` is passed,
+    // for a file descriptor that is open during the call.
+    let ready = unsafe { libc::poll(fds.as_mut_ptr(), 1, millis) };
+    match ready {
+        0 => Ok(false),
+        ready if ready < 0 => Err(std::io::Error::last_os_error()),
+        _ => {
+            std::io::BufReader::new(tty).read_line(&mut String::new())?;
+            Ok(true)
+        }
+    }
+}
+
+/// Waiting for the user is not supported on this platform,
+/// so prompts never time out.
+#[cfg(not(unix))]
+#[inline]
+pub(crate) fn wait_for_user(_timeout: std::time::Duration) -> std::io::Result<bool> {
+    log::warn!("approval timeouts are not supported on this platform");
+    Ok(true)
+}
+
+/// Ask the user in the terminal.
+///
+/// The user may also edit the arguments in their editor before approving.
+/// With a timeout,
+/// the user must first press Enter within it,
+/// otherwise the timeout action is taken.
+/// The timeout is capped at the deadline of the run
+/// (if any),
+/// past which calls are denied.
+#[derive(Debug, Default)]
+pub struct Interactive {
+    timeout: Option<(std::time::Duration, TimeoutAction)>,
+}
+
+impl Approver for Interactive {
+    #[inline]
+    fn approve(
+        &self,
+        name: &str,
+        arguments: &str,
+        prompt: Option<&str>,
+    ) -> dialoguer::Result<Option<String>> {
+        let deadline =
+            crate::functions::remaining().map(|remaining| (remaining, TimeoutAction::Deny));
+        let timeout = match (self.timeout, deadline) {
+            (Some(timeout), Some(deadline)) if deadline.0 < timeout.0 => Some(deadline),
+            (timeout, deadline) => timeout.or(deadline),
+        };
+        if let Some((timeout, action)) = timeout {
+            eprintln!(
+                "Press Enter within {secs}s to review the call to {name}, \
+                 or it will be {action}.",
+                secs = timeout.as_secs_f64().ceil(),
+                action = match action {
+                    TimeoutAction::Deny => "denied",
+                    TimeoutAction::Approve => "approved",
+                }
+            );
+            if !wait_for_user(timeout)? {
+                return Ok(match action {
+                    TimeoutAction::Deny => {
+                        log::warn!("{name} denied: approval timed out");
+                        None
+                    }
+                    TimeoutAction::Approve => {
+                        log::warn!("{name} approved: approval timed out");
+                        Some(arguments.to_owned())
+                    }
+                });
+            }
+        }
+        let mut arguments = arguments.to_owned();
+        let theme = dialoguer::theme::ColorfulTheme::default();
+        loop {
+            match dialoguer::Select::with_theme(&theme)
+                .with_prompt(prompt.unwrap_or("Do you approve command execution?"))
+                .items(&["Approve", "Edit arguments", "Deny"])
+                .default(0)
+                .interact()?
+            {
+                0 if crate::functions::remaining().is_some_and(|remaining| remaining.is_zero()) => {
+                    log::warn!("{name} denied: deadline exceeded");
+                    return Ok(None);
+                }
+                0 => return Ok(Some(arguments)),
+                1 => {
+                    if let Some(edited) = dialoguer::Editor::new()
+                        .extension(".json")
+                        .edit(&arguments)?
+                    {
+                        arguments = edited.trim().to_owned();
+                        log::warn!("{name}({arguments})");
+                    }
+                }
+                _ => return Ok(None),
+            }
+        }
+    }
+}
+
+/// Approve every call.
+#[derive(Debug)]
+pub struct Approve;
+
+impl Approver for Approve {
+    #[inline]
+    fn approve(
+        &self,
+        _name: &str,
+        arguments: &str,
+        _prompt: Option<&str>,
+    ) -> dialoguer::Result<Option<String>> {
+        Ok(Some(arguments.to_owned()))
+    }
+}
+
+/// Only approve calls to listed functions,
+/// denying all others.
+#[derive(Debug, Default)]
+pub struct Allowlist {
+    names: Vec<String>,
+}
+
+impl Allowlist {
+    /// Create an allowlist of the given function names
+    /// and those in the given file (one per line, `#` starts a comment).
+    ///
+    /// # Errors
+    /// If the file could not be read.
+    #[inline]
+    pub fn load(names: &[String], path: Option<&std::path::Path>) -> Result<Self, EllieError> {
+        let mut names = names.to_vec();
+        if let Some(path) = path {
+            let content =
+                std::fs::read_to_string(path).map_err(|err| EllieError::config(path, err))?;
+            names.extend(
+                content
+                    .lines()
+                    .map(|line| line.split('#').next().unwrap_or_default().trim())
+                    .filter(|name| !name.is_empty())
+                    .map(ToOwned::to_owned),
+            );
+        }
+        Ok(Self { names })
+    }
+}
+
+impl Approver for Allowlist {
+    #[inline]
+    fn approve(
+        &self,
+        name: &str,
+        arguments: &str,
+        _prompt: Option<&str>,
+    ) -> dialoguer::Result<Option<String>> {
+        if self.names.iter().any(|allowed| allowed == name) {
+            Ok(Some(arguments.to_owned()))
+        } else {
+            log::warn!("{name} denied: not in the approved functions");
+            Ok(None)
+        }
+    }
+}
+
+/// Pre-supplied answer to an approval prompt.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Answer {
+    /// Approve the call.
+    #[value(alias = "y")]
+    Yes,
+    /// Deny the call.
+    #[value(alias = "n")]
+    No,
+}
+
+/// Answer calls with pre-supplied answers in order,
+/// then defer to another approver once they run out.
+#[derive(Debug)]
+pub struct Scripted {
+    answers: std::sync::Mutex<std::collections::VecDeque<Answer>>,
+    fallback: Box<dyn Approver>,
+}
+
+impl Scripted {
+    /// Create a scripted approver with the given answers
+    /// and those in the given file (one per line, `#` starts a comment).
+    ///
+    /// # Errors
+    /// If the file could not be read or has an invalid answer.
+    #[inline]
+    pub fn load(
+        answers: &[Answer],
+        path: Option<&std::path::Path>,
+        fallback: Box<dyn Approver>,
+    ) -> Result<Self, EllieError> {
+        use clap::ValueEnum as _;
+
+        let mut answers: std::collections::VecDeque<_> = answers.iter().copied().collect();
+        if let Some(path) = path {
+            let content =
+                std::fs::read_to_string(path).map_err(|err| EllieError::config(path, err))?;
+            for answer in content
+                .lines()
+                .map(|line| line.split('#').next().unwrap_or_default().trim())
+                .filter(|answer| !answer.is_empty())
+            {
+                answers.push_back(
+                    Answer::from_str(answer, true).map_err(|err| EllieError::config(path, err))?,
+                );
+            }
+        }
+        Ok(Self {
+            answers: answers.into(),
+            fallback,
+        })
+    }
+}
+
+impl Approver for Scripted {
+    #[inline]
+    fn approve(
+        &self,
+        name: &str,
+        arguments: &str,
+        prompt: Option<&str>,
+    ) -> dialoguer::Result<Option<String>> {
+        let answer = self
+            .answers
+            .lock()
+            .expect("answers should never be poisoned")
+            .pop_front();
+        match answer {
+            Some(Answer::Yes) => {
+                log::info!("{name} approved by a pre-supplied answer");
+                Ok(Some(arguments.to_owned()))
+            }
+            Some(Answer::No) => {
+                log::warn!("{name} denied by a pre-supplied answer");
+                Ok(None)
+            }
+            None => self.fallback.approve(name, arguments, prompt),
+        }
+    }
+}
+
+/// How function calls get approved.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum ApprovalMode {
+    /// Ask in the terminal if there is one,
+    /// otherwise only approve listed functions.
+    #[default]
+    Auto,
+    /// Always ask in the terminal.
+    Prompt,
+    /// Approve every call.
+    Approve,
+    /// Deny every call.
+    Deny,
+    /// Only approve listed functions.
+    Allowlist,
+}
+
+impl ApprovalMode {
+    /// Create the approver of this mode,
+    /// with the given allowed function names and allowlist file,
+    /// and the given prompt timeout (if any) with its action.
+    ///
+    /// # Errors
+    /// If the allowlist file could not be read.
+    #[inline]
+    pub fn approver(
+        self,
+        names: &[String],
+        path: Option<&std::path::Path>,
+        timeout: Option<(std::time::Duration, TimeoutAction)>,
+    ) -> Result<Box<dyn Approver>, EllieError> {
+        use std::io::IsTerminal as _;
+
+        let approver: Box<dyn Approver> = match self {
+            Self::Auto if std::io::stderr().is_terminal() => Box::new(Interactive { timeout }),
+            Self::Prompt => Box::new(Interactive { timeout }),
+            Self::Approve => Box::new(Approve),
+            Self::Deny => Box::<Allowlist>::default(),
+            Self::Auto | Self::Allowlist => Box::new(Allowlist::load(names, path)?),
+        };
+        Ok(approver)
+    }
+}
@@ -0,0 +1,176 @@
+use async_openai::types as aot;
+
+use crate::models;
+
+/// Token usage of a single interaction
+/// (or of several, once added up).
+#[derive(Debug, Default, serde::Serialize)]
+pub struct Usage {
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+    pub total_tokens: usize,
+}
+
+impl std::ops::AddAssign for Usage {
+    #[inline]
+    fn add_assign(&mut self, other: Self) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+    }
+}
+
+impl Usage {
+    /// Count tokens of the given request and response messages
+    /// as the given model does.
+    ///
+    /// # Errors
+    /// If the tokenizer of the model could not be loaded.
+    #[inline]
+    pub fn new(
+        model: &models::Model,
+        request: &aot::CreateChatCompletionRequest,
+        response: &aot::ChatCompletionRequestMessage,
+    ) -> color_eyre::eyre::Result<Self> {
+        let prompt_tokens = model.count_tokens(&request.messages)?;
+        let completion_tokens = response
+            .content
+            .iter()
+            .chain(
+                response
+                    .function_call
+                    .iter()
+                    .flat_map(|function_call| [&function_call.name, &function_call.arguments]),
+            )
+            .map(|text| model.count_text_tokens(text))
+            .sum::<Result<_, _>>()?;
+        Ok(Self {
+            prompt_tokens,
+            completion_tokens,
+            total_tokens: prompt_tokens + completion_tokens,
+        })
+    }
+}
+
+/// Function call made in a single interaction.
+#[derive(Debug, serde::Serialize)]
+struct FunctionCall<'a> {
+    name: &'a str,
+    arguments: &'a str,
+    result: &'a str,
+}
+
+/// Line of the structured log file.
+#[derive(Debug, serde::Serialize)]
+struct Entry<'a> {
+    /// Seconds since the Unix epoch.
+    timestamp: u64,
+    request: &'a aot::CreateChatCompletionRequest,
+
+    /// Model snapshot that served the response
+    /// (e.g., `gpt-4-0613` for `gpt-4`),
+    /// unless it was cached.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    served_model: Option<&'a str>,
+
+    response: &'a aot::ChatCompletionRequestMessage,
+    usage: Usage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    function_call: Option<FunctionCall<'a>>,
+}
+
+/// Line of the structured log file,
+/// as read back.
+#[derive(Debug, serde::Deserialize)]
+struct LoggedEntry {
+    request: aot::CreateChatCompletionRequest,
+}
+
+/// Structured log file,
+/// where each interaction is appended as a JSON line.
+#[derive(Debug)]
+pub struct Log {
+    path: std::path::PathBuf,
+}
+
+impl Log {
+    #[inline]
+    pub fn new(path: impl Into<std::path::PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Read the request of the given entry (counted from one),
+    /// or of the last one.
+    ///
+    /// # Errors
+    /// If the file could not be read
+    /// or the entry is missing or could not be parsed.
+    #[inline]
+    pub fn request(
+        &self,
+        entry: Option<usize>,
+    ) -> color_eyre::eyre::Result<aot::CreateChatCompletionRequest> {
+        use color_eyre::eyre::Context as _;
+
+        let content = std::fs::read_to_string(&self.path)
+            .with_context(|| format!("reading log file '{}'", self.path.display()))?;
+        let mut lines = content.lines().filter(|line| !line.trim().is_empty());
+        let line = match entry {
+            Some(entry) => entry.checked_sub(1).and_then(|index| lines.nth(index)),
+            None => lines.next_back(),
+        }
+        .ok_or_else(|| match entry {
+            Some(entry) => color_eyre::eyre::eyre!(
+                "log file '{path}' has no entry {entry}",
+                path = self.path.display()
+            ),
+            None => {
+                color_eyre::eyre::eyre!("log file '{path}' is empty", path = self.path.display())
+            }
+        })?;
+        let LoggedEntry { request } = serde_json::from_str(line).context("parsing log entry")?;
+        Ok(request)
+    }
+
+    /// Append an interaction with the given model,
+    /// optionally followed by the function message it produced.
+    ///
+    /// # Errors
+    /// If tokens could not be counted or the file could not be written.
+    #[inline]
+    pub fn record(
+        &self,
+        model: &models::Model,
+        request: &aot::CreateChatCompletionRequest,
+        served_model: Option<&str>,
+        response: &aot::ChatCompletionRequestMessage,
+        function_message: Option<&aot::ChatCompletionRequestMessage>,
+    ) -> color_eyre::eyre::Result<()> {
+        use std::io::Write as _;
+
+        let function_call = response.function_call.as_ref().zip(function_message).map(
+            |(function_call, function_message)| FunctionCall {
+                name: &function_call.name,
+                arguments: &function_call.arguments,
+                result: function_message.content.as_deref().unwrap_or_default(),
+            },
+        );
+        let entry = Entry {
+            timestamp: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)?
+                .as_secs(),
+            request,
+            served_model,
+            response,
+            usage: Usage::new(model, request, response)?,
+            function_call,
+        };
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{entry}", entry = serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,245 @@
+use async_openai::types as aot;
+
+use crate::{error::EllieError, models};
+
+/// Part of the context whose contribution can be capped.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Source {
+    /// Previous messages of the session,
+    /// of which the newest are kept.
+    History,
+    /// Each system message
+    /// (e.g., the project system prompt or `--context-env`).
+    System,
+    /// Each snippet referenced in the input.
+    Snippets,
+    /// The user input.
+    Input,
+}
+
+impl std::fmt::Display for Source {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use clap::ValueEnum as _;
+
+        self.to_possible_value()
+            .expect("no source should be skipped")
+            .get_name()
+            .fmt(f)
+    }
+}
+
+/// Largest number of tokens a source may contribute to the context.
+#[derive(Clone, Copy, Debug)]
+pub struct Budget {
+    source: Source,
+    tokens: usize,
+}
+
+impl std::str::FromStr for Budget {
+    type Err = String;
+
+    /// Parse `<source>=<tokens>`,
+    /// e.g., `history=2000`.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        use clap::ValueEnum as _;
+
+        let (source, tokens) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected '<source>=<tokens>': '{s}'"))?;
+        Ok(Self {
+            source: Source::from_str(source.trim(), true)?,
+            tokens: tokens
+                .trim()
+                .parse()
+                .map_err(|err| format!("invalid number of tokens '{tokens}': {err}"))?,
+        })
+    }
+}
+
+/// Get the budget of the given source,
+/// if any,
+/// where the last given budget wins.
+#[inline]
+pub fn get(budgets: &[Budget], source: Source) -> Option<usize> {
+    budgets
+        .iter()
+        .rev()
+        .find(|budget| budget.source == source)
+        .map(|budget| budget.tokens)
+}
+
+/// Cut the given text down to the budget of the given source,
+/// if any,
+/// warning if it was cut.
+///
+/// # Errors
+/// If the tokenizer of the model could not be loaded,
+/// or the budget would leave nothing of a non-empty text.
+#[inline]
+pub fn truncate<'t>(
+    model: &models::Model,
+    budgets: &[Budget],
+    source: Source,
+    text: &'t str,
+) -> Result<&'t str, EllieError> {
+    let Some(tokens) = get(budgets, source) else {
+        return Ok(text);
+    };
+    let truncated = model.truncate(text, tokens)?;
+    if truncated.trim().is_empty() && !text.trim().is_empty() {
+        return Err(EllieError::BudgetExceeded {
+            budget: source.to_string(),
+            tokens,
+        });
+    }
+    if truncated.len() < text.len() {
+        log::warn!(
+            "truncated {source} text to {tokens} tokens ({dropped} bytes dropped)",
+            dropped = text.len() - truncated.len()
+        );
+    }
+    Ok(truncated)
+}
+
+/// Drop the oldest messages of the history beyond the history budget,
+/// if any,
+/// warning if any was dropped.
+///
+/// The history never starts with a function result
+/// whose call was dropped.
+///
+/// # Errors
+/// If the messages could not be counted.
+#[inline]
+pub fn trim_history(
+    model: &models::Model,
+    budgets: &[Budget],
+    history: &mut Vec<aot::ChatCompletionRequestMessage>,
+) -> Result<(), EllieError> {
+    let Some(tokens) = get(budgets, Source::History) else {
+        return Ok(());
+    };
+    let mut kept = 0;
+    let mut start = history.len();
+    while let Some(message) = start.checked_sub(1).and_then(|index| history.get(index)) {
+        kept += model.count_tokens(std::slice::from_ref(message))?;
+        if kept > tokens {
+            break;
+        }
+        start -= 1;
+    }
+    if start > 0 {
+        // Function results are meaningless without the calls producing them,
+        // so they are dropped along with them.
+        start = skip_results(history, start);
+        log::warn!(
+            "dropped the {start} oldest messages to fit the history budget of {tokens} tokens"
+        );
+        history.drain(..start);
+    }
+    Ok(())
+}
+
+/// Get the index of the first message at or after the given one
+/// that is not a function result.
+///
+/// Cutting the history there never separates a function call from its results.
+#[inline]
+#[must_use]
+pub fn skip_results(history: &[aot::ChatCompletionRequestMessage], index: usize) -> usize {
+    index
+        + history
+            .iter()
+            .skip(index)
+            .take_while(|message| matches!(message.role, aot::Role::Function))
+            .count()
+}
+
+/// Drop the message at the given index of the history
+/// along with the function results following it,
+/// returning how many messages were dropped.
+#[inline]
+pub fn drop_message(history: &mut Vec<aot::ChatCompletionRequestMessage>, index: usize) -> usize {
+    let end = skip_results(history, index + 1);
+    history.drain(index..end).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: aot::Role, content: &str) -> aot::ChatCompletionRequestMessage {
+        aot::ChatCompletionRequestMessageArgs::default()
+            .role(role)
+            .content(content)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn budgets_leaving_nothing_are_exceeded() {
+        let model: models::Model =
+            serde_json::from_value(serde_json::json!({"name": "test", "tokenizer": "chars"}))
+                .unwrap();
+        let budgets = ["input=0".parse().unwrap()];
+        assert!(matches!(
+            truncate(&model, &budgets, Source::Input, "Hello!"),
+            Err(EllieError::BudgetExceeded { tokens: 0, .. })
+        ));
+        assert_eq!(truncate(&model, &budgets, Source::Input, "").unwrap(), "");
+        assert_eq!(
+            truncate(&model, &budgets, Source::System, "Hello!").unwrap(),
+            "Hello!"
+        );
+    }
+
+    #[test]
+    fn trimmed_history_never_starts_with_a_function_result() {
+        let model: models::Model =
+            serde_json::from_value(serde_json::json!({"name": "test", "tokenizer": "chars"}))
+                .unwrap();
+        let long = "x".repeat(300);
+        let mut history = vec![
+            message(aot::Role::User, &long),
+            message(aot::Role::Assistant, ""),
+            message(aot::Role::Function, "result"),
+            message(aot::Role::Assistant, "It is sunny."),
+            message(aot::Role::User, "Thanks!"),
+        ];
+        // Enough for the function result and what follows,
+        // but not for the call.
+        let budget = model.count_tokens(&history[2..]).unwrap();
+        let budgets = [format!("history={budget}").parse().unwrap()];
+        trim_history(&model, &budgets, &mut history).unwrap();
+        assert_eq!(history.len(), 2);
+        assert!(matches!(history[0].role, aot::Role::Assistant));
+    }
+
+    #[test]
+    fn dropped_call_takes_its_function_results_along() {
+        let mut history = vec![
+            message(aot::Role::System, "You are helpful."),
+            message(aot::Role::Assistant, ""),
+            message(aot::Role::Function, "result"),
+            message(aot::Role::Assistant, "It is sunny."),
+        ];
+        assert_eq!(drop_message(&mut history, 1), 2);
+        assert_eq!(history.len(), 2);
+        assert!(matches!(history[1].role, aot::Role::Assistant));
+    }
+
+    #[test]
+    fn results_are_skipped_up_to_the_next_message() {
+        let history = [
+            message(aot::Role::User, "Weather?"),
+            message(aot::Role::Function, "result"),
+            message(aot::Role::Function, "result"),
+            message(aot::Role::Assistant, "It is sunny."),
+        ];
+        assert_eq!(skip_results(&history, 0), 0);
+        assert_eq!(skip_results(&history, 1), 3);
+        assert_eq!(skip_results(&history, 4), 4);
+    }
+}
@@ -0,0 +1,139 @@
+use async_openai::types as aot;
+
+/// Directory where cached data is stored.
+///
+/// # Errors
+/// If project directories could not be retrieved.
+#[inline]
+pub fn dir() -> color_eyre::eyre::Result<std::path::PathBuf> {
+    use color_eyre::eyre::ContextCompat as _;
+
+    Ok(crate::get_project_dirs()
+        .context("getting project directories")?
+        .cache_dir()
+        .to_owned())
+}
+
+/// Remove everything from the cache directory.
+///
+/// # Errors
+/// If the cache directory could not be removed.
+#[inline]
+pub fn clear() -> color_eyre::eyre::Result<()> {
+    let dir = dir()?;
+    if dir.exists() {
+        std::fs::remove_dir_all(&dir)?;
+    }
+    println!("cleared '{dir}'", dir = dir.display());
+    Ok(())
+}
+
+/// Seconds since the Unix epoch.
+#[inline]
+fn now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Cached response.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+struct Entry {
+    /// Seconds since the Unix epoch.
+    timestamp: u64,
+    message: aot::ChatCompletionRequestMessage,
+
+    /// Why the model stopped (e.g., `length` if truncated).
+    finish_reason: String,
+}
+
+/// Cache of assistant messages keyed by request content
+/// (model, messages, functions and parameters).
+#[derive(Debug)]
+pub struct Responses {
+    dir: std::path::PathBuf,
+
+    /// Maximum age of entries in seconds.
+    ttl: u64,
+
+    /// Whether cached entries are skipped (but still refreshed).
+    refresh: bool,
+}
+
+impl Responses {
+    /// Create a response cache with the given time to live in seconds.
+    ///
+    /// If `refresh` is set,
+    /// cached entries are ignored but still replaced.
+    ///
+    /// # Errors
+    /// If the cache directory could not be retrieved.
+    #[inline]
+    pub fn new(ttl: u64, refresh: bool) -> color_eyre::eyre::Result<Self> {
+        Ok(Self {
+            dir: dir()?.join("responses"),
+            ttl,
+            refresh,
+        })
+    }
+
+    /// Path of the entry for the given request,
+    /// named after the SHA-256 hash of the serialized request,
+    /// which is stable across builds.
+    #[inline]
+    fn path(&self, request: &aot::CreateChatCompletionRequest) -> std::path::PathBuf {
+        use std::fmt::Write as _;
+
+        let request =
+            serde_json::to_string(request).expect("serialization of requests should never fail");
+        let digest = ring::digest::digest(&ring::digest::SHA256, request.as_bytes());
+        let mut name = String::with_capacity(2 * digest.as_ref().len() + ".json".len());
+        for byte in digest.as_ref() {
+            write!(name, "{byte:02x}").expect("writing to a string should never fail");
+        }
+        name.push_str(".json");
+        self.dir.join(name)
+    }
+
+    /// Get the cached assistant message for the given request,
+    /// with its finish reason,
+    /// unless missing or expired.
+    #[inline]
+    pub fn get(
+        &self,
+        request: &aot::CreateChatCompletionRequest,
+    ) -> Option<(aot::ChatCompletionRequestMessage, String)> {
+        if self.refresh {
+            return None;
+        }
+        let content = std::fs::read_to_string(self.path(request)).ok()?;
+        let entry: Entry = serde_json::from_str(&content)
+            .map_err(|err| log::warn!("ignoring bad cache entry: {err}"))
+            .ok()?;
+        (now().saturating_sub(entry.timestamp) <= self.ttl)
+            .then_some((entry.message, entry.finish_reason))
+    }
+
+    /// Cache the assistant message for the given request,
+    /// with its finish reason.
+    ///
+    /// # Errors
+    /// If the entry could not be written.
+    #[inline]
+    pub fn put(
+        &self,
+        request: &aot::CreateChatCompletionRequest,
+        message: &aot::ChatCompletionRequestMessage,
+        finish_reason: &str,
+    ) -> color_eyre::eyre::Result<()> {
+        std::fs::create_dir_all(&self.dir)?;
+        let entry = Entry {
+            timestamp: now(),
+            message: message.clone(),
+            finish_reason: finish_reason.to_owned(),
+        };
+        std::fs::write(self.path(request), serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+}
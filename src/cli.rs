@@ -0,0 +1,550 @@
+/// Command-line interface.
+#[derive(Debug, clap::Parser)]
+#[command(author, version, about)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub options: Options,
+}
+
+/// Subcommands.
+#[derive(Debug, clap::Subcommand)]
+pub enum Command {
+    /// Diagnose common environment problems.
+    Doctor,
+
+    /// List the models available to the account,
+    /// flagging the ones ellie can select.
+    Models,
+
+    /// Inspect the configuration.
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+
+    /// Manage the on-disk cache.
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+
+    /// Manage saved sessions.
+    Sessions {
+        #[command(subcommand)]
+        command: SessionsCommand,
+    },
+
+    /// Export a saved session as a shareable transcript.
+    Export {
+        /// Name of the session.
+        session: String,
+
+        /// Format of the transcript.
+        #[arg(long, value_enum, default_value_t)]
+        format: crate::sessions::ExportFormat,
+    },
+
+    /// Send a request recorded in a `--log-file` again,
+    /// streaming the new answer.
+    Replay {
+        /// Log file written with `--log-file`.
+        log_file: std::path::PathBuf,
+
+        /// Entry to replay,
+        /// counted from one (the last one by default).
+        #[arg(long)]
+        entry: Option<usize>,
+
+        /// Model to send the request to instead of the recorded one,
+        /// e.g., to compare models on identical inputs.
+        #[arg(long)]
+        model: Option<String>,
+    },
+
+    /// Keep the standard input in the clipboard
+    /// until another program replaces it,
+    /// as run in the background by `--copy`.
+    #[cfg(target_os = "linux")]
+    #[command(hide = true)]
+    HoldClipboard,
+}
+
+/// Configuration subcommands.
+#[derive(Debug, clap::Subcommand)]
+pub enum ConfigCommand {
+    /// Print the effective configuration,
+    /// with the source of each value.
+    Show {
+        /// Format of the configuration.
+        #[arg(long, value_enum, default_value_t)]
+        format: crate::config::ConfigFormat,
+    },
+}
+
+/// Cache subcommands.
+#[derive(Debug, clap::Subcommand)]
+pub enum CacheCommand {
+    /// Remove all cached data.
+    Clear,
+}
+
+/// Session subcommands.
+#[derive(Debug, clap::Subcommand)]
+pub enum SessionsCommand {
+    /// List saved sessions with their message count and last-modified time.
+    List,
+
+    /// Remove a saved session.
+    Rm {
+        /// Name of the session.
+        name: String,
+    },
+}
+
+/// Options shared by all subcommands.
+#[derive(Debug, clap::Args)]
+pub struct Options {
+    /// Dotenv file to load environment variables from
+    /// (`.env` in the current directory by default, if any,
+    /// without variables that run commands, approve calls or redirect data).
+    ///
+    /// Variables already set in the environment take precedence.
+    #[arg(long, value_name = "PATH")]
+    pub env_file: Option<std::path::PathBuf>,
+
+    /// Base URL of the `OpenAI` API.
+    #[arg(
+        long,
+        env = "OPENAI_API_BASE",
+        default_value = async_openai::config::OPENAI_API_BASE,
+        global = true
+    )]
+    pub api_base: String,
+
+    /// Largest number of tokens a source may contribute to the context,
+    /// as `<source>=<tokens>`
+    /// (`history`, `system`, `snippets` or `input`; can be repeated).
+    #[arg(long = "budget", value_name = "SOURCE=TOKENS")]
+    pub budgets: Vec<crate::budget::Budget>,
+
+    /// Which function the model may call:
+    /// `auto` (whichever it prefers),
+    /// `none`,
+    /// or the name of a function to call first in every answer.
+    #[arg(long, default_value = "auto", value_name = "CHOICE")]
+    pub tool_choice: crate::ToolChoice,
+
+    /// Largest number of tokens to generate in each completion,
+    /// or `auto` to use whatever the chosen model's context leaves after
+    /// the prompt.
+    #[arg(long, value_name = "TOKENS")]
+    pub max_tokens: Option<crate::MaxTokens>,
+
+    /// How function arguments produced by the model are passed on to
+    /// providers.
+    #[arg(long, value_enum, default_value_t)]
+    pub arguments_format: crate::functions::ArgumentsFormat,
+
+    /// How function calls and results are given to the model,
+    /// for backends without function roles.
+    #[arg(long, value_enum, default_value_t)]
+    pub function_role: crate::functions::FunctionRole,
+
+    /// How models are chosen in each round of function calling.
+    #[arg(long, value_enum, default_value_t)]
+    pub model_policy: crate::models::ModelPolicy,
+
+    /// Maximum size of the standard input in bytes,
+    /// beyond which ellie stops reading and fails.
+    #[arg(long, default_value_t = crate::MAX_INPUT_BYTES)]
+    pub max_input_bytes: u64,
+
+    /// When to compose the user input in `$VISUAL` or `$EDITOR`
+    /// instead of reading the standard input.
+    #[arg(long, value_enum, default_value_t)]
+    pub edit_input: crate::EditInput,
+
+    /// Minimum number of tokens the chosen model must be able to generate
+    /// in the completion.
+    #[arg(long, default_value_t = crate::MIN_COMPLETION_TOKENS)]
+    pub min_completion_tokens: usize,
+
+    /// What to do when the conversation fits no model:
+    /// fail (the default),
+    /// drop the oldest messages of the history,
+    /// or summarize them.
+    #[arg(long, value_enum, default_value_t)]
+    pub on_overflow: crate::Overflow,
+
+    /// How the answer is streamed to the standard output.
+    #[arg(
+        long,
+        value_enum,
+        default_value_t,
+        conflicts_with_all = ["markdown", "wrap", "output_prefix", "output_suffix"]
+    )]
+    pub stream_format: crate::output::StreamFormat,
+
+    /// Only write the output of executed function calls,
+    /// not the assistant's answer
+    /// (as `function_result` events with `--stream-format jsonl`).
+    #[arg(
+        long,
+        conflicts_with_all = ["markdown", "wrap", "output_prefix", "output_suffix"]
+    )]
+    pub functions_only: bool,
+
+    /// Only write the assistant's final answer,
+    /// trimmed,
+    /// once every function call is done
+    /// (e.g., to capture it in a shell variable).
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "functions_only",
+            "stream_format",
+            "markdown",
+            "wrap",
+            "output_prefix",
+            "output_suffix",
+        ]
+    )]
+    pub answer_only: bool,
+
+    /// Render Markdown in the assistant's answer for the terminal,
+    /// block by block.
+    #[arg(long)]
+    pub markdown: bool,
+
+    /// Also copy the assistant's final answer to the system clipboard.
+    #[arg(long)]
+    pub copy: bool,
+
+    /// Regular expression to redact from answers before they are written,
+    /// saved or logged
+    /// (can be repeated),
+    /// matching within single lines.
+    #[arg(long = "redact", value_name = "REGEX")]
+    pub redactions: Vec<regex::Regex>,
+
+    /// Replacement of redacted matches.
+    #[arg(long, default_value = "[REDACTED]")]
+    pub redaction_placeholder: String,
+
+    /// Shell command to run once the final answer is complete,
+    /// e.g., to send a desktop notification.
+    ///
+    /// It gets the answer on its standard input,
+    /// and the model and token usage in the `ELLIE_MODEL`,
+    /// `ELLIE_PROMPT_TOKENS`,
+    /// `ELLIE_COMPLETION_TOKENS` and `ELLIE_TOTAL_TOKENS` environment
+    /// variables.
+    /// Its failures are only reported.
+    #[arg(long, value_name = "COMMAND")]
+    pub on_complete: Option<String>,
+
+    /// Word-wrap the assistant's answer to the given width,
+    /// or to the terminal width if the standard output is a terminal.
+    #[arg(long, value_name = "WIDTH", num_args = 0..=1)]
+    pub wrap: Option<Option<usize>>,
+
+    /// Text written to the standard output before the assistant's answer.
+    #[arg(long)]
+    pub output_prefix: Option<String>,
+
+    /// Text written to the standard output after the assistant's answer.
+    #[arg(long)]
+    pub output_suffix: Option<String>,
+
+    /// Unix domain socket or named pipe the output is written to
+    /// instead of the standard output
+    /// (e.g., for a front-end rendering it),
+    /// created as a named pipe if missing.
+    #[arg(long, value_name = "PATH", env = "ELLIE_OUTPUT_SOCKET")]
+    pub output_socket: Option<std::path::PathBuf>,
+
+    /// Ask the model to continue answers truncated at the token limit
+    /// (a few times at most),
+    /// joining the continuations seamlessly.
+    #[arg(long)]
+    pub auto_continue: bool,
+
+    /// Retry once with a nudge if the model returns an empty answer.
+    #[arg(long)]
+    pub retry_empty: bool,
+
+    /// Print how long each phase took (reading the standard input,
+    /// loading specifications, time to first token, streaming and each
+    /// function call) to the standard error at the end.
+    #[arg(long)]
+    pub time: bool,
+
+    /// Write a line to the standard error at each milestone
+    /// (request sent, response received, function called),
+    /// e.g., to follow long runs in CI logs,
+    /// where no progress is shown otherwise.
+    #[arg(long)]
+    pub progress_log: bool,
+
+    /// Print the number of requests,
+    /// prompt and completion tokens,
+    /// and estimated cost (from model prices) of the whole run,
+    /// per model,
+    /// to the standard error at the end
+    /// (and as a `stats` event with `--stream-format jsonl`).
+    #[arg(long)]
+    pub stats: bool,
+
+    /// Abort the whole run after this many seconds,
+    /// including every request and function call,
+    /// saving the session with the answers completed so far.
+    #[arg(long, value_name = "SECS")]
+    pub deadline: Option<u64>,
+
+    /// Print an equivalent `curl` command for every request sent
+    /// to the standard error,
+    /// reading the API key from `OPENAI_API_KEY`.
+    #[arg(long)]
+    pub dump_curl: bool,
+
+    /// Append every request and response as a JSON line to the given file.
+    #[arg(long)]
+    pub log_file: Option<std::path::PathBuf>,
+
+    /// Describe the current working directory, operating system and shell
+    /// to the model.
+    #[arg(long)]
+    pub context_env: bool,
+
+    /// Tell the model the current date and time.
+    #[arg(long)]
+    pub with_time: bool,
+
+    /// Format of the date and time given with `--with-time`
+    /// (`strftime`-like).
+    #[arg(long, default_value = "%A, %Y-%m-%d %H:%M %:z", requires = "with_time")]
+    pub time_format: String,
+
+    /// Time zone of the date and time given with `--with-time`:
+    /// `local`,
+    /// `utc`,
+    /// or an offset such as `+05:30`.
+    #[arg(long, default_value = "local", requires = "with_time")]
+    pub timezone: crate::Timezone,
+
+    /// Give the model the output of the last shell command,
+    /// as captured by the shell in `--last-output-file`
+    /// (e.g., to explain an error).
+    #[arg(long)]
+    pub last_output: bool,
+
+    /// File the shell captures the output of the last command into.
+    #[arg(long, env = "ELLIE_LAST_OUTPUT_FILE")]
+    pub last_output_file: Option<std::path::PathBuf>,
+
+    /// Shell command whose output replaces the user input
+    /// (e.g., to redact secrets),
+    /// run once before any request.
+    #[arg(long, env = "ELLIE_PRE_COMMAND")]
+    pub pre_command: Option<String>,
+
+    /// Use the first line of the input as a system prompt,
+    /// unless the input has a single line.
+    #[arg(long)]
+    pub first_line_system: bool,
+
+    /// Describe the available functions to the model in a system message,
+    /// which helps weaker models with tool use.
+    #[arg(long)]
+    pub describe_functions: bool,
+
+    /// Reject calls to functions whose required functions (`requires` in
+    /// their provider) did not succeed earlier in the conversation.
+    #[arg(long)]
+    pub enforce_requires: bool,
+
+    /// Treat the standard input as a batch of independent prompts,
+    /// separated by delimiter lines.
+    #[arg(long)]
+    pub batch: bool,
+
+    /// Line separating prompts in batch mode (and their answers in the
+    /// output),
+    /// where an empty delimiter means a blank line.
+    #[arg(long, default_value = "---", requires = "batch")]
+    pub delimiter: String,
+
+    /// Maximum number of prompts answered in parallel in batch mode.
+    ///
+    /// Answers are still printed in order.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..), requires = "batch")]
+    pub concurrency: u16,
+
+    /// Maximum number of provider commands running at once,
+    /// across the prompts answered in parallel in batch mode.
+    ///
+    /// Approval prompts are still asked one at a time.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    pub tool_concurrency: u16,
+
+    /// Execute function calls without approval when their arguments are
+    /// empty (or `{}`),
+    /// even if the function is not marked as safe.
+    #[arg(long)]
+    pub no_approval_on_empty_args: bool,
+
+    /// How function calls that are not marked as safe get approved.
+    #[arg(long, value_enum, default_value_t)]
+    pub approval: crate::approval::ApprovalMode,
+
+    /// Functions approved without asking in the `allowlist` approval mode
+    /// (and in the `auto` mode without a terminal).
+    #[arg(long, env = "ELLIE_APPROVED_FUNCTIONS", value_delimiter = ',')]
+    pub approved_functions: Vec<String>,
+
+    /// File listing more approved functions,
+    /// one per line.
+    #[arg(long)]
+    pub approved_functions_file: Option<std::path::PathBuf>,
+
+    /// Answers to approval prompts,
+    /// consumed in order as function calls occur
+    /// (the approval mode applies once they run out).
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub approvals: Vec<crate::approval::Answer>,
+
+    /// File with more answers to approval prompts,
+    /// one per line,
+    /// consumed after `--approvals`.
+    #[arg(long)]
+    pub approvals_file: Option<std::path::PathBuf>,
+
+    /// Seconds to wait for the user to start answering an approval prompt,
+    /// after which the `--approval-timeout-action` is taken.
+    #[arg(long, value_name = "SECS")]
+    pub approval_timeout: Option<u64>,
+
+    /// What to do with a call when its approval prompt times out.
+    #[arg(long, value_enum, default_value_t, requires = "approval_timeout")]
+    pub approval_timeout_action: crate::approval::TimeoutAction,
+
+    /// Only run providers whose command resolves to the given absolute path,
+    /// disabling all others (can be repeated).
+    #[arg(long = "allow-command", value_name = "PATH")]
+    pub allow_commands: Vec<std::path::PathBuf>,
+
+    /// What to do with providers whose command cannot be found.
+    #[arg(long, value_enum, default_value_t)]
+    pub missing_command: crate::functions::MissingCommand,
+
+    /// Fail if a provider specification names a function other than the
+    /// provider,
+    /// instead of warning and using the provider name.
+    #[arg(long)]
+    pub strict_function_names: bool,
+
+    /// Do not execute a function call identical to a previous one in the
+    /// same answer,
+    /// giving the previous result to the model instead.
+    #[arg(long)]
+    pub reuse_function_results: bool,
+
+    /// Stop with an error as soon as a function call fails
+    /// (e.g., its command exits unsuccessfully),
+    /// instead of giving the failure to the model.
+    #[arg(long)]
+    pub fail_fast_functions: bool,
+
+    /// Log function calls that need no approval as information rather than
+    /// warnings,
+    /// keeping warnings for calls that need approval.
+    #[arg(long)]
+    pub quiet_functions: bool,
+
+    /// Record function calls on the standard error without executing them,
+    /// answering the model as if they ran.
+    #[arg(long)]
+    pub simulate_functions: bool,
+
+    /// Strip ANSI escape sequences and control characters from provider
+    /// output before giving it to the model.
+    #[arg(long)]
+    pub strip_ansi: bool,
+
+    /// What happens to the standard error of provider commands.
+    #[arg(long, value_enum, default_value_t)]
+    pub provider_stderr: crate::functions::ProviderStderr,
+
+    /// Give JSON function results to the model indented
+    /// instead of compacted.
+    #[arg(long)]
+    pub pretty_json_results: bool,
+
+    /// After a failed function call
+    /// (e.g., a provider command exiting unsuccessfully),
+    /// nudge the model to correct the arguments and retry,
+    /// unless the provider sets `retry_guidance`.
+    #[arg(long)]
+    pub retry_guidance: bool,
+
+    /// Show function calls, their output and metadata on the standard error.
+    #[arg(long)]
+    pub show_functions: bool,
+
+    /// Show at most this many lines of each function output with
+    /// `--show-functions` and in logs,
+    /// while the model still gets the full output.
+    #[arg(long, value_name = "N")]
+    pub function_output_lines: Option<usize>,
+
+    /// Read and write cached responses
+    /// (of requests with a zero temperature),
+    /// answering identical requests without calling the API.
+    #[arg(long)]
+    pub cache: bool,
+
+    /// Ignore cached responses,
+    /// replacing them with fresh ones.
+    #[arg(long, requires = "cache")]
+    pub refresh: bool,
+
+    /// Slow down requests adaptively when they get rate limited,
+    /// speeding up again as they succeed,
+    /// and pause them when rate limit headers report the limit is near.
+    #[arg(long)]
+    pub adaptive_throttle: bool,
+
+    /// API keys used in turn instead of `OPENAI_API_KEY`
+    /// (comma-separated in the environment).
+    #[arg(
+        long = "api-key",
+        value_name = "KEY",
+        env = "ELLIE_API_KEYS",
+        value_delimiter = ',',
+        hide_env_values = true
+    )]
+    pub api_keys: Vec<String>,
+
+    /// How requests choose among several API keys.
+    #[arg(long, value_enum, default_value_t)]
+    pub key_rotation: crate::keys::KeyRotation,
+
+    /// Maximum age of cached responses in seconds.
+    #[arg(long, default_value_t = 24 * 60 * 60)]
+    pub cache_ttl: u64,
+
+    /// Project file (e.g., `ellie.toml`) defining the system prompt,
+    /// model tier,
+    /// temperature and functions,
+    /// overriding the global configuration.
+    #[arg(long)]
+    pub project: Option<std::path::PathBuf>,
+
+    /// Continue the named conversation,
+    /// saving it afterwards.
+    #[arg(long, conflicts_with = "batch")]
+    pub session: Option<String>,
+}
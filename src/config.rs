@@ -0,0 +1,178 @@
+use crate::{cli, functions, models, project, snippets};
+
+/// Format of the printed configuration.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum ConfigFormat {
+    /// TOML document.
+    #[default]
+    Toml,
+    /// JSON document.
+    Json,
+}
+
+/// Value of a command-line option,
+/// with where it came from.
+#[derive(Debug, serde::Serialize)]
+struct Setting {
+    value: serde_json::Value,
+
+    /// `default`, `environment` or `command line`.
+    source: &'static str,
+}
+
+/// Configuration loaded from a file,
+/// with where it came from.
+#[derive(Debug, serde::Serialize)]
+struct Section<T> {
+    /// Path of the file,
+    /// or `built-in` if none was loaded.
+    source: String,
+
+    #[serde(flatten)]
+    config: T,
+}
+
+impl<T> Section<T> {
+    #[inline]
+    fn new(path: &std::path::Path, config: T) -> Self {
+        Self {
+            source: path.display().to_string(),
+            config,
+        }
+    }
+
+    #[inline]
+    fn built_in(config: T) -> Self {
+        Self {
+            source: "built-in".to_owned(),
+            config,
+        }
+    }
+}
+
+/// Effective configuration,
+/// as the assistant would see it.
+#[derive(Debug, serde::Serialize)]
+struct Config {
+    options: std::collections::BTreeMap<String, Setting>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    project: Option<Section<project::Project>>,
+
+    functions: Section<functions::Functions>,
+    models: Section<models::Models>,
+    snippets: Section<snippets::Snippets>,
+}
+
+/// Get the values of all command-line options that are set,
+/// keyed by their long name.
+#[inline]
+fn settings() -> std::collections::BTreeMap<String, Setting> {
+    use clap::CommandFactory as _;
+
+    let command = cli::Cli::command();
+    let matches = command.clone().get_matches();
+    command
+        .get_arguments()
+        .filter_map(|arg| {
+            let name = arg.get_long()?;
+            let id = arg.get_id().as_str();
+            let source = match matches.value_source(id)? {
+                clap::parser::ValueSource::DefaultValue => "default",
+                clap::parser::ValueSource::EnvVariable => "environment",
+                _ => "command line",
+            };
+            let mut values: Vec<_> = matches
+                .get_raw(id)?
+                .map(|value| serde_json::Value::String(value.to_string_lossy().into_owned()))
+                .collect();
+            if arg.is_hide_env_values_set() {
+                values.fill(serde_json::Value::String("<redacted>".to_owned()));
+            }
+            let value = if matches!(arg.get_action(), clap::ArgAction::Append) {
+                serde_json::Value::Array(values)
+            } else {
+                values.pop()?
+            };
+            Some((name.to_owned(), Setting { value, source }))
+        })
+        .collect()
+}
+
+/// Print the effective configuration:
+/// command-line options (with their source),
+/// the project file,
+/// and the function, model and snippet configuration files.
+///
+/// Files are loaded as they would be for a conversation,
+/// except that no provider command is spawned.
+///
+/// # Errors
+/// If a configuration file could not be loaded,
+/// or if the configuration could not be serialized in the given format.
+#[inline]
+pub fn show(options: &cli::Options, format: ConfigFormat) -> color_eyre::eyre::Result<()> {
+    use color_eyre::eyre::Context as _;
+
+    let project = options
+        .project
+        .as_deref()
+        .map(|path| project::Project::load(path).map(|project| Section::new(path, project)))
+        .transpose()?;
+
+    let functions = match &options.project {
+        Some(path) => Section::new(path, functions::Functions::load_from(path)?),
+        None => {
+            let path = functions::Functions::path()?;
+            if path.exists() {
+                Section::new(&path, functions::Functions::load_from(&path)?)
+            } else {
+                Section::built_in(functions::Functions::default())
+            }
+        }
+    };
+
+    let (tier, allowed_models) = project.as_ref().map_or_else(
+        || (models::Tier::default(), &[][..]),
+        |project| (project.config.tier, &project.config.allowed_models[..]),
+    );
+    let path = models::Models::path()?;
+    let models = if path.exists() {
+        Section::new(
+            &path,
+            models::Models::load()?
+                .with_tier(tier)?
+                .with_allowed(allowed_models)?,
+        )
+    } else {
+        Section::built_in(
+            models::Models::default()
+                .with_tier(tier)?
+                .with_allowed(allowed_models)?,
+        )
+    };
+
+    let path = snippets::Snippets::path()?;
+    let snippets = if path.exists() {
+        Section::new(&path, snippets::Snippets::load()?)
+    } else {
+        Section::built_in(snippets::Snippets::default())
+    };
+
+    let config = Config {
+        options: settings(),
+        project,
+        functions,
+        models,
+        snippets,
+    };
+    match format {
+        ConfigFormat::Toml => print!(
+            "{}",
+            toml::to_string_pretty(&config)
+                .context("serializing configuration (try '--format json')")?
+        ),
+        ConfigFormat::Json => println!("{}", serde_json::to_string_pretty(&config)?),
+    }
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use crate::{error, functions, models};
+
+/// Outcome of a single diagnostic check.
+enum Check {
+    Pass(String),
+    Warn(String),
+    Fail(String),
+}
+
+impl Check {
+    #[inline]
+    const fn is_failure(&self) -> bool {
+        matches!(self, Self::Fail(_))
+    }
+}
+
+impl std::fmt::Display for Check {
+    #[inline]
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Pass(message) => write!(f, "[pass] {message}"),
+            Self::Warn(message) => write!(f, "[warn] {message}"),
+            Self::Fail(message) => write!(f, "[FAIL] {message}"),
+        }
+    }
+}
+
+#[inline]
+fn check_api_key() -> Check {
+    match std::env::var("OPENAI_API_KEY") {
+        Ok(key) if !key.trim().is_empty() => Check::Pass("OPENAI_API_KEY is set".to_owned()),
+        Ok(_) => Check::Fail("OPENAI_API_KEY is empty".to_owned()),
+        Err(_) => Check::Fail("OPENAI_API_KEY is not set".to_owned()),
+    }
+}
+
+/// Check the configuration file,
+/// returning the loaded functions if it could be parsed.
+#[inline]
+fn check_config() -> (Check, Option<functions::Functions>) {
+    let path = match functions::Functions::path() {
+        Ok(path) => path,
+        Err(err) => return (Check::Fail(format!("config path: {err}")), None),
+    };
+    if !path.exists() {
+        return (
+            Check::Warn(format!(
+                "config file '{path}' not found: no functions available",
+                path = path.display()
+            )),
+            None,
+        );
+    }
+    match functions::Functions::load() {
+        Ok(functions) => (
+            Check::Pass(format!("config file '{path}' parsed", path = path.display())),
+            Some(functions),
+        ),
+        Err(err) => (
+            Check::Fail(format!(
+                "config file '{path}' could not be parsed: {err}",
+                path = path.display()
+            )),
+            None,
+        ),
+    }
+}
+
+#[inline]
+fn check_models() -> Check {
+    let path = match models::Models::path() {
+        Ok(path) => path,
+        Err(err) => return Check::Fail(format!("models path: {err}")),
+    };
+    if !path.exists() {
+        return Check::Pass("using default models".to_owned());
+    }
+    match models::Models::load() {
+        Ok(models) => {
+            let deprecated = models
+                .iter()
+                .filter(|model| model.deprecated)
+                .map(|model| model.name.as_str())
+                .collect::<Vec<_>>();
+            if deprecated.is_empty() {
+                Check::Pass(format!(
+                    "models file '{path}' parsed",
+                    path = path.display()
+                ))
+            } else {
+                Check::Warn(format!(
+                    "models file '{path}' lists deprecated models: {deprecated}",
+                    path = path.display(),
+                    deprecated = deprecated.join(", ")
+                ))
+            }
+        }
+        Err(err) => Check::Fail(format!(
+            "models file '{path}' could not be parsed: {err}",
+            path = path.display()
+        )),
+    }
+}
+
+#[inline]
+fn check_commands(functions: &functions::Functions) -> impl Iterator<Item = Check> + '_ {
+    functions
+        .commands()
+        .map(|(name, command)| match which::which(command) {
+            Ok(path) => Check::Pass(format!(
+                "provider '{name}' command '{command}' found at '{path}'",
+                path = path.display()
+            )),
+            Err(err) => Check::Fail(format!(
+                "provider '{name}' command '{command}' not found: {err}"
+            )),
+        })
+}
+
+/// Check that the API base can be reached with the API key,
+/// telling rejected credentials apart from network failures.
+#[inline]
+async fn check_connectivity<C: async_openai::config::Config + Sync>(
+    client: &async_openai::Client<C>,
+    api_base: &str,
+) -> Check {
+    match client.models().list().await {
+        Ok(_) => Check::Pass(format!("API base '{api_base}' reachable")),
+        Err(err) => match error::api_code(&err) {
+            Some(error::code::AUTH) => Check::Fail(format!(
+                "API base '{api_base}' reachable, but the API key was rejected: {err}"
+            )),
+            Some(error::code::NETWORK) => {
+                Check::Fail(format!("API base '{api_base}' unreachable: {err}"))
+            }
+            _ => Check::Fail(format!(
+                "API base '{api_base}' reachable, but listing models failed: {err}"
+            )),
+        },
+    }
+}
+
+/// Diagnose common environment problems,
+/// printing a pass/fail report.
+///
+/// # Errors
+/// If any check failed.
+#[inline]
+pub(super) async fn run<C: async_openai::config::Config + Sync>(
+    client: &async_openai::Client<C>,
+    api_base: &str,
+) -> color_eyre::eyre::Result<()> {
+    let mut checks = vec![check_api_key()];
+    let (check, functions) = check_config();
+    checks.push(check);
+    checks.push(check_models());
+    if let Some(functions) = functions {
+        checks.extend(check_commands(&functions));
+    }
+    checks.push(check_connectivity(client, api_base).await);
+
+    for check in &checks {
+        println!("{check}");
+    }
+    let failures = checks.iter().filter(|check| check.is_failure()).count();
+    color_eyre::eyre::ensure!(failures == 0, "{failures} check(s) failed");
+    Ok(())
+}
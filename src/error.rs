@@ -0,0 +1,219 @@
+use async_openai::error::OpenAIError;
+
+/// Boxed source of an error.
+type Source = Box<dyn std::error::Error + Send + Sync>;
+
+/// Errors produced by ellie,
+/// which can be matched on programmatically.
+#[derive(Debug, thiserror::Error)]
+pub enum EllieError {
+    /// Project directories could not be retrieved.
+    #[error("project directories could not be retrieved")]
+    ProjectDirs,
+
+    /// A configuration file could not be read or parsed.
+    #[error("loading configuration file '{path}'")]
+    Config {
+        path: std::path::PathBuf,
+        #[source]
+        source: Source,
+    },
+
+    /// The tokenizer does not know the given model.
+    #[error("model '{model}' could not be retrieved: {message}")]
+    UnknownModel { model: String, message: String },
+
+    /// The user input alone does not fit the cheapest model.
+    #[error("user input does not fit model '{model}'")]
+    InputTooLong { model: String },
+
+    /// The standard input exceeds the maximum size.
+    #[error("input exceeds {limit} bytes")]
+    InputTooLarge { limit: u64 },
+
+    /// A `--budget` is too small to keep anything of a non-empty text.
+    #[error("{budget} budget of {tokens} tokens leaves nothing to send")]
+    BudgetExceeded { budget: String, tokens: usize },
+
+    /// No model has a large enough context length for the messages.
+    #[error("no model has a large enough context length for the given messages")]
+    NoModel,
+
+    /// A function provider could not be executed.
+    #[error("executing provider '{name}'")]
+    Provider {
+        name: String,
+        #[source]
+        source: Source,
+    },
+
+    /// The command preprocessing the user input failed.
+    #[error("preprocessing input with '{command}'")]
+    PreCommand {
+        command: String,
+        #[source]
+        source: Source,
+    },
+
+    /// A provider specification names another function,
+    /// with `--strict-function-names`.
+    #[error("provider '{name}' specification names function '{other}'")]
+    NameMismatch { name: String, other: String },
+
+    /// A provider command could not be found.
+    #[error("provider '{name}' command '{command}' not found")]
+    MissingCommand { name: String, command: String },
+
+    /// The user input references a snippet that is not defined.
+    #[error("unknown snippet '{name}'")]
+    UnknownSnippet { name: String },
+
+    /// The user interaction (e.g., approval) failed.
+    #[error("interacting with the user")]
+    Prompt(#[from] dialoguer::Error),
+
+    /// The user denied the execution of a function.
+    #[error("function call aborted by the user")]
+    FunctionAborted,
+
+    /// A function call failed with `--fail-fast-functions`.
+    #[error("function '{name}' failed: {reason}")]
+    FunctionFailed { name: String, reason: String },
+
+    /// The run took longer than `--deadline`.
+    #[error("deadline exceeded")]
+    DeadlineExceeded,
+
+    /// The run was interrupted (e.g., with Ctrl-C).
+    #[error("interrupted")]
+    Interrupted,
+
+    /// The `OpenAI` API returned an error.
+    #[error(transparent)]
+    Api(#[from] OpenAIError),
+}
+
+impl EllieError {
+    /// Create a [`EllieError::Config`] error for the given path.
+    #[inline]
+    pub fn config(path: impl Into<std::path::PathBuf>, source: impl Into<Source>) -> Self {
+        Self::Config {
+            path: path.into(),
+            source: source.into(),
+        }
+    }
+
+    /// Create a [`EllieError::Provider`] error for the given provider.
+    #[inline]
+    pub fn provider(name: impl Into<String>, source: impl Into<Source>) -> Self {
+        Self::Provider {
+            name: name.into(),
+            source: source.into(),
+        }
+    }
+
+    /// Exit code of this error,
+    /// if it belongs to a category with a distinct exit code.
+    #[inline]
+    pub fn code(&self) -> Option<u8> {
+        match self {
+            Self::InputTooLong { .. } | Self::InputTooLarge { .. } | Self::NoModel => {
+                Some(code::CONTEXT_OVERFLOW)
+            }
+            Self::BudgetExceeded { .. } => Some(code::BUDGET),
+            Self::FunctionAborted => Some(code::FUNCTION_ABORTED),
+            Self::FunctionFailed { .. } => Some(code::FUNCTION_FAILED),
+            Self::DeadlineExceeded => Some(code::DEADLINE),
+            Self::Interrupted => Some(code::INTERRUPTED),
+            Self::Api(err) => api_code(err),
+            _ => None,
+        }
+    }
+}
+
+/// Exit code of an `OpenAI` error,
+/// if it belongs to a category with a distinct exit code.
+#[inline]
+pub fn api_code(err: &OpenAIError) -> Option<u8> {
+    match err {
+        OpenAIError::Reqwest(_) => Some(code::NETWORK),
+        OpenAIError::ApiError(err)
+            if err
+                .code
+                .as_ref()
+                .and_then(serde_json::Value::as_str)
+                .is_some_and(|code| code == "invalid_api_key") =>
+        {
+            Some(code::AUTH)
+        }
+        OpenAIError::StreamError(message) if message.contains("Invalid status code: 401") => {
+            Some(code::AUTH)
+        }
+        OpenAIError::StreamError(message) if message.contains("error sending request") => {
+            Some(code::NETWORK)
+        }
+        _ => None,
+    }
+}
+
+/// Distinct exit codes per category of failure,
+/// so that scripts can react appropriately.
+pub mod code {
+    /// Any other failure.
+    pub const OTHER: u8 = 1;
+
+    /// The API rejected the credentials.
+    pub const AUTH: u8 = 3;
+
+    /// No model has a large enough context length.
+    pub const CONTEXT_OVERFLOW: u8 = 4;
+
+    /// The user denied the execution of a function.
+    pub const FUNCTION_ABORTED: u8 = 5;
+
+    /// The API could not be reached.
+    pub const NETWORK: u8 = 6;
+
+    /// A function call failed with `--fail-fast-functions`.
+    pub const FUNCTION_FAILED: u8 = 7;
+
+    /// The run took longer than `--deadline`.
+    pub const DEADLINE: u8 = 8;
+
+    /// A `--budget` could not be met.
+    pub const BUDGET: u8 = 9;
+
+    /// The run was interrupted,
+    /// as shells report a `SIGINT`.
+    pub const INTERRUPTED: u8 = 130;
+}
+
+/// Find the exit code of an error report,
+/// looking for known errors in its context and chain.
+#[inline]
+pub fn exit_code(report: &color_eyre::eyre::Report) -> u8 {
+    // Errors used as context (e.g., an exceeded deadline) are not part of
+    // the chain.
+    report
+        .downcast_ref::<EllieError>()
+        .and_then(EllieError::code)
+        .or_else(|| {
+            report.chain().find_map(|err| {
+                err.downcast_ref::<EllieError>().map_or_else(
+                    || err.downcast_ref::<OpenAIError>().and_then(api_code),
+                    EllieError::code,
+                )
+            })
+        })
+        .unwrap_or(code::OTHER)
+}
+
+/// Check if an error report was caused by writing to a closed pipe,
+/// e.g., when piping into `head`.
+#[inline]
+pub fn is_broken_pipe(report: &color_eyre::eyre::Report) -> bool {
+    report.chain().any(|err| {
+        err.downcast_ref::<std::io::Error>()
+            .is_some_and(|err| err.kind() == std::io::ErrorKind::BrokenPipe)
+    })
+}
@@ -1,13 +1,14 @@
 use async_openai::types::ChatCompletionFunctions;
 
-#[inline]
-fn get_project_dirs() -> Option<directories::ProjectDirs> {
-    directories::ProjectDirs::from("io.github", "schneiderfelipe", "ellie")
-}
+use crate::{approval, error::EllieError, openapi};
 
 /// Trim text
 /// and try to produce a compact JSON string out of it,
 /// returning an owned trimmed string if serialization fails.
+///
+/// Note that valid JSON is round-tripped through [`serde_json::Value`],
+/// so object keys get sorted
+/// and only the last of any duplicate keys is kept.
 #[inline]
 pub fn try_compact_json(maybe_json: &str) -> String {
     let maybe_json = maybe_json.trim();
@@ -16,34 +17,336 @@ pub fn try_compact_json(maybe_json: &str) -> String {
         .unwrap_or_else(|_| maybe_json.to_owned())
 }
 
+/// Trim text
+/// and try to produce an indented JSON string out of it,
+/// returning an owned trimmed string if serialization fails.
+///
+/// As with [`try_compact_json`],
+/// object keys get sorted
+/// and only the last of any duplicate keys is kept.
+#[inline]
+pub fn try_pretty_json(maybe_json: &str) -> String {
+    let maybe_json = maybe_json.trim();
+    serde_json::from_str::<serde_json::Value>(maybe_json)
+        .and_then(|value| serde_json::to_string_pretty(&value))
+        .unwrap_or_else(|_| maybe_json.to_owned())
+}
+
+/// Format a function result for the model,
+/// compacting valid JSON unless it should be pretty-printed.
 #[inline]
-fn merge(spec: &mut ChatCompletionFunctions, patch: &ChatCompletionFunctions) {
-    let ChatCompletionFunctions {
-        name: _,
-        description,
-        parameters,
+fn format_result(content: &str, pretty_json: bool) -> String {
+    if pretty_json {
+        try_pretty_json(content)
+    } else {
+        try_compact_json(content)
+    }
+}
+
+/// How function arguments produced by the model are passed on to providers.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum ArgumentsFormat {
+    /// Compact valid JSON (sorts keys and drops duplicate keys).
+    #[default]
+    Compact,
+    /// Keep the exact bytes the model produced, only trimming whitespace.
+    Preserve,
+}
+
+impl ArgumentsFormat {
+    /// Format function arguments according to this mode.
+    #[inline]
+    pub fn apply(self, arguments: &str) -> String {
+        match self {
+            Self::Compact => try_compact_json(arguments),
+            Self::Preserve => {
+                let arguments = arguments.trim();
+                if serde_json::from_str::<serde::de::IgnoredAny>(arguments).is_err() {
+                    log::warn!("function arguments are not valid JSON: {arguments}");
+                }
+                arguments.to_owned()
+            }
+        }
+    }
+}
+
+/// How function calls and results are given to the model.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum FunctionRole {
+    /// Native function calls and `function` messages.
+    #[default]
+    Function,
+    /// Plain assistant messages for calls
+    /// and user messages labeled with the function name for results,
+    /// for backends without function roles.
+    User,
+}
+
+impl FunctionRole {
+    /// Map a message to the roles of this mode.
+    #[inline]
+    pub fn apply(
+        self,
+        message: async_openai::types::ChatCompletionRequestMessage,
+    ) -> async_openai::types::ChatCompletionRequestMessage {
+        use async_openai::types::{ChatCompletionRequestMessage, FunctionCall, Role};
+
+        match (self, message) {
+            (
+                Self::User,
+                ChatCompletionRequestMessage {
+                    role: Role::Assistant,
+                    function_call: Some(FunctionCall { name, arguments }),
+                    ..
+                },
+            ) => ChatCompletionRequestMessage {
+                role: Role::Assistant,
+                content: Some(format!("Function call: {name}({arguments})")),
+                name: None,
+                function_call: None,
+            },
+            (
+                Self::User,
+                ChatCompletionRequestMessage {
+                    role: Role::Function,
+                    name,
+                    content,
+                    ..
+                },
+            ) => ChatCompletionRequestMessage {
+                role: Role::User,
+                content: Some(format!(
+                    "Function {name} returned:\n{content}",
+                    name = name.unwrap_or_default(),
+                    content = content.unwrap_or_default()
+                )),
+                name: None,
+                function_call: None,
+            },
+            (_, message) => message,
+        }
+    }
+}
+
+/// What to do with providers whose command cannot be found.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum MissingCommand {
+    /// Fail the whole configuration.
+    #[default]
+    Fail,
+    /// Skip the provider with a warning.
+    Skip,
+}
+
+/// Function specification override from a `[[function]]` section.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+struct Override {
+    #[serde(flatten)]
+    spec: ChatCompletionFunctions,
+
+    /// Maximum depth at which `parameters` are merged recursively,
+    /// see [`merge_to_depth`].
+    #[serde(default)]
+    merge_depth: Option<usize>,
+}
+
+/// Message appended to failed calls with retry guidance.
+const RETRY_GUIDANCE: &str =
+    "The tool failed with the above output; consider correcting the arguments and retrying.";
+
+/// Most times a provider command is run again with user input it
+/// requested,
+/// so that a provider that never accepts an answer cannot loop forever.
+const MAX_INPUT_REQUESTS: usize = 3;
+
+/// Deepest nesting of objects and arrays in `parameters` overrides,
+/// beyond which merging them could overflow the stack.
+const MAX_PARAMETERS_DEPTH: usize = 64;
+
+/// Get how deeply the given value nests objects and arrays,
+/// without recursing,
+/// where scalars have depth zero.
+#[inline]
+fn nesting_depth(value: &serde_json::Value) -> usize {
+    let mut deepest = 0;
+    let mut pending = vec![(value, 0)];
+    while let Some((value, depth)) = pending.pop() {
+        deepest = deepest.max(depth);
+        match value {
+            serde_json::Value::Object(object) => {
+                pending.extend(object.values().map(|value| (value, depth + 1)));
+            }
+            serde_json::Value::Array(array) => {
+                pending.extend(array.iter().map(|value| (value, depth + 1)));
+            }
+            _ => {}
+        }
+    }
+    deepest
+}
+
+/// Apply a JSON merge patch (RFC 7396),
+/// but only recurse into objects down to the given depth.
+///
+/// Objects are merged key by key,
+/// `null` removes a key,
+/// and anything else (including arrays) replaces the target value.
+/// At depth zero,
+/// the patch replaces the target wholesale,
+/// so a depth of one only merges top-level keys (a shallow merge).
+/// No depth means unlimited recursion,
+/// as in [`json_patch::merge`].
+#[inline]
+fn merge_to_depth(target: &mut serde_json::Value, patch: &serde_json::Value, depth: Option<usize>) {
+    match (patch, depth) {
+        (serde_json::Value::Object(patch), depth) if depth != Some(0) => {
+            if !target.is_object() {
+                *target = serde_json::Value::Object(serde_json::Map::new());
+            }
+            let target = target
+                .as_object_mut()
+                .expect("target should have just been made an object");
+            for (key, value) in patch {
+                if value.is_null() {
+                    target.remove(key);
+                } else {
+                    merge_to_depth(
+                        target.entry(key).or_insert(serde_json::Value::Null),
+                        value,
+                        depth.map(|depth| depth - 1),
+                    );
+                }
+            }
+        }
+        (patch, _) => *target = patch.clone(),
+    }
+}
+
+#[inline]
+fn merge(spec: &mut ChatCompletionFunctions, patch: &Override) {
+    let Override {
+        spec:
+            ChatCompletionFunctions {
+                name: _,
+                description,
+                parameters,
+            },
+        merge_depth,
     } = patch;
 
     if let Some(description) = description {
         spec.description = Some(description.clone());
     }
     if let (Some(spec_parameters), Some(parameters)) = (&mut spec.parameters, &parameters) {
-        json_patch::merge(spec_parameters, parameters);
+        match merge_depth {
+            None => json_patch::merge(spec_parameters, parameters),
+            Some(depth) => merge_to_depth(spec_parameters, parameters, Some(*depth)),
+        }
     } else if let Some(parameters) = parameters {
         spec.parameters = Some(parameters.clone());
     }
 }
 
+/// Format of the content produced by a provider.
+#[derive(Clone, Copy, Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum OutputFormat {
+    Json,
+    Text,
+    Yaml,
+    Csv,
+}
+
+impl OutputFormat {
+    /// Format content for the model,
+    /// labeling non-JSON structured text with a fenced block.
+    #[inline]
+    fn apply(self, content: &str, pretty_json: bool) -> String {
+        match self {
+            Self::Json => format_result(content, pretty_json),
+            Self::Text => content.trim().to_owned(),
+            Self::Yaml => format!("```yaml\n{content}\n```", content = content.trim()),
+            Self::Csv => format!("```csv\n{content}\n```", content = content.trim()),
+        }
+    }
+}
+
+/// Part of a command-line argument.
+#[derive(Debug, PartialEq, Eq)]
+enum Segment<'a> {
+    Text(&'a str),
+
+    /// `{field}` placeholder.
+    Field(&'a str),
+}
+
+/// Split a command-line argument into literal text and `{field}`
+/// placeholders,
+/// where fields are made of alphanumeric characters and underscores.
+///
+/// `{{` and `}}` are escaped braces,
+/// and other braces not forming a placeholder are kept as is.
+#[inline]
+fn segments(arg: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut rest = arg;
+    while let Some(start) = rest.find(['{', '}']) {
+        if start > 0 {
+            segments.push(Segment::Text(&rest[..start]));
+        }
+        let brace = &rest[start..=start];
+        rest = &rest[start + 1..];
+        if let Some(escaped) = rest.strip_prefix(brace) {
+            segments.push(Segment::Text(brace));
+            rest = escaped;
+            continue;
+        }
+        let field = rest.find('}').map(|end| &rest[..end]).filter(|field| {
+            brace == "{"
+                && !field.is_empty()
+                && field.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+        match field {
+            Some(field) => {
+                segments.push(Segment::Field(field));
+                rest = &rest[field.len() + 1..];
+            }
+            None => segments.push(Segment::Text(brace)),
+        }
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Text(rest));
+    }
+    segments
+}
+
+/// Kind of a function provider.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum ProviderKind {
+    /// Execute a command.
+    #[default]
+    Command,
+    /// Send a request to an HTTP endpoint.
+    Http,
+}
+
 /// Function provider.
-#[derive(Debug, serde::Deserialize)]
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
 struct Provider {
     /// Function provider name.
     name: String,
 
-    /// Command to execute.
+    /// Kind of provider.
+    #[serde(default, rename = "type")]
+    kind: ProviderKind,
+
+    /// Command to execute (command providers only).
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     command: String,
 
-    /// Command-line arguments to pass to command execution.
+    /// Command-line arguments to pass to command execution,
+    /// where `{field}` placeholders are filled from the function arguments.
     #[serde(default)]
     args: Vec<String>,
 
@@ -51,46 +354,972 @@ struct Provider {
     /// approval*.
     #[serde(default)]
     safe: bool,
+
+    /// Whether calls with empty arguments can be executed *without user
+    /// approval*.
+    #[serde(default)]
+    approve_empty_args: bool,
+
+    /// Question asked when approving calls,
+    /// where `{arguments}` is replaced with the function arguments
+    /// and `{field}` placeholders with their fields,
+    /// instead of a generic one.
+    #[serde(default)]
+    confirm_prompt: Option<String>,
+
+    /// Format of the produced content,
+    /// which controls how it is given to the model.
+    #[serde(default)]
+    output_format: Option<OutputFormat>,
+
+    /// Whether to strip ANSI escape sequences and control characters from
+    /// the produced content,
+    /// overriding the command-line default.
+    #[serde(default)]
+    strip_ansi: Option<bool>,
+
+    /// Whether to pretty-print JSON content instead of compacting it,
+    /// overriding the command-line default.
+    #[serde(default)]
+    pretty_json: Option<bool>,
+
+    /// Whether to nudge the model to correct the arguments and retry after
+    /// a failed call,
+    /// overriding the command-line default.
+    #[serde(default)]
+    retry_guidance: Option<bool>,
+
+    /// Endpoint to send the function arguments to (HTTP providers only).
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    url: String,
+
+    /// HTTP method of requests to the endpoint,
+    /// `POST` by default.
+    #[serde(default)]
+    method: Option<String>,
+
+    /// HTTP headers of requests to the endpoint and to `spec_url`.
+    #[serde(
+        default,
+        serialize_with = "serialize_redacted",
+        skip_serializing_if = "std::collections::BTreeMap::is_empty"
+    )]
+    headers: std::collections::BTreeMap<String, String>,
+
+    /// Where to get the function specification from (HTTP providers only).
+    ///
+    /// Without it,
+    /// the specification is expected to come from a `[[function]]` section.
+    #[serde(default)]
+    spec_url: Option<String>,
+
+    /// Minimum number of seconds between the starts of successive calls,
+    /// e.g., for providers using rate-limited services.
+    #[serde(default)]
+    min_interval: Option<f64>,
+
+    /// Whether to start the command as soon as the model starts producing
+    /// arguments,
+    /// writing them to its standard input as they arrive
+    /// (safe command providers only).
+    #[serde(default)]
+    stream_arguments: bool,
+
+    /// Functions that should be called before this one,
+    /// e.g., reading a file before editing it.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    requires: Vec<String>,
+
+    /// Start of the last call,
+    /// if any.
+    #[serde(skip)]
+    last_call: std::sync::Mutex<Option<std::time::Instant>>,
+}
+
+/// Serialize header names only,
+/// since values often hold credentials.
+#[inline]
+fn serialize_redacted<S: serde::Serializer>(
+    headers: &std::collections::BTreeMap<String, String>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(headers.keys().map(|name| (name, "<redacted>")))
+}
+
+/// Provider command started while the model is still producing the function
+/// arguments,
+/// which are written to its standard input as they arrive.
+///
+/// The command is killed if the call is dropped before being finished
+/// (e.g., if the answer fails midway).
+#[derive(Debug)]
+pub struct StreamingCall {
+    name: String,
+    stdin: Option<os_pipe::PipeWriter>,
+    handle: Option<duct::Handle>,
+
+    /// Held until the call is finished or dropped,
+    /// see [`permit`].
+    permit: Option<tokio::sync::SemaphorePermit<'static>>,
+}
+
+impl StreamingCall {
+    /// Write a fragment of the arguments to the command.
+    #[inline]
+    pub fn write(&mut self, fragment: &str) {
+        use std::io::Write as _;
+
+        if let Some(stdin) = &mut self.stdin {
+            if let Err(err) = stdin.write_all(fragment.as_bytes()) {
+                // The command may stop reading early on purpose.
+                log::debug!("{name} stopped reading arguments: {err}", name = self.name);
+                self.stdin = None;
+            }
+        }
+    }
+}
+
+impl Drop for StreamingCall {
+    #[inline]
+    fn drop(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            log::debug!("killing unfinished call to {name}", name = self.name);
+            if let Err(err) = handle.kill() {
+                log::warn!("could not kill {name}: {err}", name = self.name);
+            }
+        }
+    }
+}
+
+/// How function calls are handled,
+/// as requested on the command line.
+#[derive(Clone, Copy, Debug)]
+pub struct CallPolicy<'a> {
+    /// Approves calls that are not marked as safe.
+    pub approver: &'a dyn approval::Approver,
+
+    /// Execute calls with empty arguments *without user approval*.
+    pub approve_empty_args: bool,
+
+    /// Record calls without executing them.
+    pub simulate: bool,
+
+    /// Strip ANSI escape sequences and control characters from provider
+    /// output.
+    pub strip_ansi: bool,
+
+    /// Log calls executed *without user approval* as information rather
+    /// than warnings.
+    pub quiet: bool,
+
+    /// Pretty-print JSON results instead of compacting them.
+    pub pretty_json: bool,
+
+    /// Nudge the model to correct the arguments and retry after a failed
+    /// call.
+    pub retry_guidance: bool,
+
+    /// What happens to the standard error of provider commands.
+    pub stderr: ProviderStderr,
+
+    /// Reject calls to functions whose required functions did not succeed
+    /// earlier in the conversation.
+    pub enforce_requires: bool,
+}
+
+/// What happens to the standard error of provider commands.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum ProviderStderr {
+    /// Capture it separately and log it.
+    Capture,
+    /// Let it flow to the terminal as it is written.
+    Inherit,
+    /// Merge it into the output given to the model.
+    #[default]
+    Merge,
+}
+
+impl ProviderStderr {
+    /// Redirect the standard error of the given command accordingly.
+    #[inline]
+    fn apply(self, expression: duct::Expression) -> duct::Expression {
+        match self {
+            Self::Capture => expression.stderr_capture(),
+            Self::Inherit => expression,
+            Self::Merge => expression.stderr_to_stdout(),
+        }
+    }
+}
+
+/// Remove ANSI escape sequences (e.g., colors)
+/// and control characters other than newlines and tabs.
+#[inline]
+pub fn strip_ansi(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{1b}' => match chars.next() {
+                // Control sequence: parameters up to a final byte.
+                Some('[') => {
+                    for c in chars.by_ref() {
+                        if ('\u{40}'..='\u{7e}').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+                // Operating system command: up to BEL or ST.
+                Some(']') => {
+                    while let Some(c) = chars.next() {
+                        if c == '\u{7}' || (c == '\u{1b}' && chars.next_if_eq(&'\\').is_some()) {
+                            break;
+                        }
+                    }
+                }
+                _ => {}
+            },
+            '\n' | '\t' => stripped.push(c),
+            c if c.is_control() => {}
+            c => stripped.push(c),
+        }
+    }
+    stripped
+}
+
+/// Check if the given function arguments are empty (or an empty JSON
+/// object).
+#[inline]
+fn is_empty_arguments(arguments: &str) -> bool {
+    let arguments = arguments.trim();
+    arguments.is_empty()
+        || serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(arguments)
+            .is_ok_and(|arguments| arguments.is_empty())
+}
+
+/// Log a function call,
+/// as a warning unless it is safe and calls are quiet.
+#[inline]
+fn log_call(name: &str, arguments: &str, safe: bool, policy: CallPolicy<'_>) {
+    let level = if safe && policy.quiet {
+        log::Level::Info
+    } else {
+        log::Level::Warn
+    };
+    log::log!(level, "{name}({arguments})");
+}
+
+/// Check whether a function call can be executed,
+/// asking the approver of the given policy
+/// (with the given question, if any)
+/// unless it is marked as safe.
+///
+/// This function returns the arguments to execute the function with,
+/// or [`None`] if denied.
+#[inline]
+fn approve(
+    name: &str,
+    arguments: &str,
+    safe: bool,
+    prompt: Option<&str>,
+    policy: CallPolicy<'_>,
+) -> dialoguer::Result<Option<String>> {
+    /// Held while asking for approval,
+    /// so that prompts never interleave.
+    static APPROVAL: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+    log_call(name, arguments, safe, policy);
+    if safe {
+        return Ok(Some(arguments.to_owned()));
+    }
+    let _approval = APPROVAL
+        .lock()
+        .expect("approval lock should never be poisoned");
+    policy.approver.approve(name, arguments, prompt)
+}
+
+/// Get the prompt of a user input request,
+/// i.e., provider output that is exactly a JSON object `{"input": "..."}`.
+#[inline]
+fn input_request(output: &str) -> Option<String> {
+    let mut request =
+        serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(output).ok()?;
+    if request.len() != 1 {
+        return None;
+    }
+    match request.remove("input")? {
+        serde_json::Value::String(prompt) => Some(prompt),
+        _ => None,
+    }
+}
+
+/// Ask the user for input requested by a provider.
+///
+/// With a deadline,
+/// the user must first press Enter before it passes.
+///
+/// # Errors
+/// If the user interaction failed
+/// or the deadline passed.
+#[inline]
+fn ask_input(name: &str, prompt: &str) -> dialoguer::Result<String> {
+    if let Some(remaining) = remaining() {
+        eprintln!(
+            "Press Enter within {secs}s to answer {name}, or the deadline will be exceeded.",
+            secs = remaining.as_secs_f64().ceil()
+        );
+        if !crate::approval::wait_for_user(remaining)? {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                "input request timed out at the deadline",
+            )
+            .into());
+        }
+    }
+    let theme = dialoguer::theme::ColorfulTheme::default();
+    dialoguer::Input::with_theme(&theme)
+        .with_prompt(format!("{name}: {prompt}"))
+        .allow_empty(true)
+        .interact_text()
+}
+
+/// Why a provider command stopped being waited for.
+#[derive(Debug)]
+enum Wake {
+    Exited,
+    Interrupted,
+    Deadline,
+}
+
+/// Provider commands currently running,
+/// each woken up to be killed on interrupt.
+static RUNNING: std::sync::Mutex<Vec<std::sync::mpsc::Sender<Wake>>> =
+    std::sync::Mutex::new(Vec::new());
+
+/// Cancelled on interrupt while no provider command runs,
+/// see [`interrupted`].
+static INTERRUPT: std::sync::OnceLock<tokio_util::sync::CancellationToken> =
+    std::sync::OnceLock::new();
+
+/// When running provider commands must be killed,
+/// if ever.
+static DEADLINE: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+
+/// Bounds how many provider commands run at once,
+/// see [`set_concurrency`].
+static PERMITS: std::sync::OnceLock<tokio::sync::Semaphore> = std::sync::OnceLock::new();
+
+/// Let at most the given number of provider commands run at once
+/// (one by default).
+///
+/// Only the first limit of a run is kept.
+#[inline]
+pub fn set_concurrency(concurrency: usize) {
+    if PERMITS
+        .set(tokio::sync::Semaphore::new(concurrency.max(1)))
+        .is_err()
+    {
+        log::debug!("provider concurrency already set");
+    }
+}
+
+#[inline]
+fn permits() -> &'static tokio::sync::Semaphore {
+    PERMITS.get_or_init(|| tokio::sync::Semaphore::new(1))
+}
+
+/// Wait until another provider command may run,
+/// returning a permit to hold while it runs.
+#[inline]
+pub async fn permit() -> tokio::sync::SemaphorePermit<'static> {
+    permits()
+        .acquire()
+        .await
+        .expect("provider permits should never be closed")
+}
+
+/// Kill provider commands still running at the given instant
+/// (and make HTTP requests time out by then).
+///
+/// Only the first deadline of a run is kept.
+#[inline]
+pub fn set_deadline(deadline: std::time::Instant) {
+    if DEADLINE.set(deadline).is_err() {
+        log::debug!("deadline already set");
+    }
+}
+
+/// Time left until the deadline,
+/// if any.
+#[inline]
+pub(crate) fn remaining() -> Option<std::time::Duration> {
+    DEADLINE
+        .get()
+        .map(|deadline| deadline.saturating_duration_since(std::time::Instant::now()))
+}
+
+#[inline]
+fn running() -> std::sync::MutexGuard<'static, Vec<std::sync::mpsc::Sender<Wake>>> {
+    RUNNING
+        .lock()
+        .expect("running providers should never be poisoned")
+}
+
+#[inline]
+fn interrupt() -> &'static tokio_util::sync::CancellationToken {
+    INTERRUPT.get_or_init(tokio_util::sync::CancellationToken::new)
+}
+
+/// Wait until the run is interrupted,
+/// i.e., until Ctrl-C is pressed while no provider command runs
+/// (running provider commands are killed instead).
+///
+/// Interrupts are only handled once a provider command ran,
+/// they terminate ellie as usual before.
+#[inline]
+pub async fn interrupted() {
+    interrupt().cancelled().await;
+}
+
+/// Start listening for interrupts,
+/// which kill running provider commands,
+/// or interrupt the run otherwise.
+#[inline]
+fn install_interrupt_handler() {
+    static INSTALL: std::sync::Once = std::sync::Once::new();
+    INSTALL.call_once(|| {
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            log::warn!("could not install interrupt handler outside of the async runtime");
+            return;
+        };
+        runtime.spawn(async {
+            loop {
+                if let Err(err) = tokio::signal::ctrl_c().await {
+                    log::warn!("could not listen for interrupts: {err}");
+                    return;
+                }
+                // Commands already done are forgotten here.
+                let mut running = running();
+                running.retain(|wake| wake.send(Wake::Interrupted).is_ok());
+                if running.is_empty() {
+                    interrupt().cancel();
+                }
+            }
+        });
+    });
+}
+
+/// Output of a provider call,
+/// with the reason it failed,
+/// if it did.
+#[derive(Debug)]
+struct CallOutput {
+    output: String,
+    failure: Option<String>,
+}
+
+/// Run the given expression to completion,
+/// returning its standard output.
+///
+/// On interrupt,
+/// the process is killed and this function returns [`None`].
+///
+/// # Errors
+/// If the process could not be started or waited for,
+/// or if the deadline passed.
+#[inline]
+fn run_interruptible(
+    name: &str,
+    expression: &duct::Expression,
+) -> std::io::Result<Option<CallOutput>> {
+    wait_interruptible(name, &expression.start()?)
+}
+
+/// Wait for the given process to complete,
+/// returning its standard output,
+/// which failed if the process exited unsuccessfully.
+///
+/// On interrupt,
+/// the process is killed and this function returns [`None`].
+///
+/// # Errors
+/// If the process could not be waited for,
+/// or if the deadline passed
+/// (in which case the process is killed).
+#[inline]
+fn wait_interruptible(name: &str, handle: &duct::Handle) -> std::io::Result<Option<CallOutput>> {
+    install_interrupt_handler();
+    let (wake, woken) = std::sync::mpsc::channel();
+    running().push(wake.clone());
+    let (woken, output) = std::thread::scope(|scope| {
+        let killer = scope.spawn(move || {
+            let woken = match remaining() {
+                Some(remaining) => match woken.recv_timeout(remaining) {
+                    Ok(woken) => woken,
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => Wake::Deadline,
+                    Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => Wake::Exited,
+                },
+                None => woken.recv().unwrap_or(Wake::Exited),
+            };
+            if !matches!(woken, Wake::Exited) {
+                handle.kill()?;
+            }
+            std::io::Result::Ok(woken)
+        });
+        let output = handle.wait();
+        // The killer is already gone if it killed the command.
+        let _ = wake.send(Wake::Exited);
+        let woken = killer
+            .join()
+            .expect("waiting for a provider command should never panic");
+        (woken, output)
+    });
+    match woken? {
+        Wake::Interrupted => Ok(None),
+        Wake::Deadline => Err(std::io::Error::new(
+            std::io::ErrorKind::TimedOut,
+            "deadline exceeded",
+        )),
+        Wake::Exited => {
+            let output = output?;
+            // Only captured if it is neither merged nor inherited.
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if !stderr.trim().is_empty() {
+                log::info!(
+                    "{name} standard error: {stderr}",
+                    stderr = stderr.trim_end()
+                );
+            }
+            let mut stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+            stdout.truncate(stdout.trim_end_matches(['\n', '\r']).len());
+            let failure = (!output.status.success()).then(|| {
+                output.status.code().map_or_else(
+                    || "killed by a signal".to_owned(),
+                    |code| format!("exit code {code}"),
+                )
+            });
+            Ok(Some(CallOutput {
+                output: stdout,
+                failure,
+            }))
+        }
+    }
 }
 
 impl Provider {
     #[inline]
-    fn is_approved(&self, arguments: &str) -> dialoguer::Result<bool> {
-        log::warn!("{name}({arguments})", name = self.name);
-        let is_approved = self.safe
-            || dialoguer::Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default())
-                .with_prompt("Do you approve command execution?")
-                .interact()?;
-        Ok(is_approved)
+    fn approve(
+        &self,
+        arguments: &str,
+        policy: CallPolicy<'_>,
+    ) -> dialoguer::Result<Option<String>> {
+        let safe = self.safe
+            || ((policy.approve_empty_args || self.approve_empty_args)
+                && is_empty_arguments(arguments));
+        let prompt = self.confirm_prompt(arguments);
+        approve(&self.name, arguments, safe, prompt.as_deref(), policy)
+    }
+
+    /// Fill the question asked when approving a call with the given JSON
+    /// arguments,
+    /// if the provider has one.
+    ///
+    /// `{arguments}` is replaced with the arguments themselves
+    /// and other `{field}` placeholders with their fields,
+    /// keeping placeholders of missing fields as they are.
+    #[inline]
+    fn confirm_prompt(&self, arguments: &str) -> Option<String> {
+        let template = self.confirm_prompt.as_deref()?;
+        let fields: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(arguments).unwrap_or_default();
+        Some(
+            segments(template)
+                .into_iter()
+                .map(|segment| match segment {
+                    Segment::Text(text) => text.to_owned(),
+                    Segment::Field("arguments") => arguments.to_owned(),
+                    Segment::Field(field) => fields
+                        .get(field)
+                        .map_or_else(|| format!("{{{field}}}"), openapi::to_text),
+                })
+                .collect(),
+        )
+    }
+
+    /// Get the fields of `{field}` placeholders in command-line arguments,
+    /// in order of appearance and without duplicates.
+    #[inline]
+    fn fields(&self) -> Vec<&str> {
+        let mut fields = Vec::new();
+        for segment in self.args.iter().flat_map(|arg| segments(arg)) {
+            if let Segment::Field(field) = segment {
+                if !fields.contains(&field) {
+                    fields.push(field);
+                }
+            }
+        }
+        fields
+    }
+
+    /// Check if any command-line argument has `{field}` placeholders.
+    #[inline]
+    fn is_templated(&self) -> bool {
+        !self.fields().is_empty()
+    }
+
+    /// Fill `{field}` placeholders in command-line arguments from the given
+    /// JSON arguments.
+    ///
+    /// This function returns a message for the model if the arguments are
+    /// not a JSON object or a field is missing.
+    #[inline]
+    fn fill_args(&self, arguments: &str) -> Result<Vec<String>, String> {
+        if !self.is_templated() {
+            return Ok(self.args.clone());
+        }
+        let arguments: serde_json::Map<String, serde_json::Value> = serde_json::from_str(arguments)
+            .map_err(|err| format!("expected JSON object: {err}"))?;
+        self.args
+            .iter()
+            .map(|arg| {
+                segments(arg)
+                    .into_iter()
+                    .map(|segment| match segment {
+                        Segment::Text(text) => Ok(text.to_owned()),
+                        Segment::Field(field) => arguments
+                            .get(field)
+                            .map(openapi::to_text)
+                            .ok_or_else(|| format!("missing field '{field}'")),
+                    })
+                    .collect()
+            })
+            .collect()
     }
 
     /// Call provider with the given standard input arguments,
     /// returning the output produced by command execution.
     ///
     /// If denied by the user,
-    /// command execution is aborted,
-    /// and this function returns [`None`].
-    #[inline]
-    fn call(&self, arguments: &str) -> dialoguer::Result<Option<String>> {
-        let response = if self.is_approved(arguments)? {
-            let response = duct::cmd(&self.command, &self.args)
-                .stdin_bytes(arguments)
-                .stderr_to_stdout()
-                .unchecked()
-                .read()
-                .expect("unchecked command execution should never fail");
-            Some(response)
-        } else {
-            None
+    /// command execution is aborted.
+    /// If interrupted while running,
+    /// the command is killed and execution is aborted as well.
+    ///
+    /// If the command requests user input (see [`input_request`]),
+    /// the user is prompted and the command is run again with the answer
+    /// in the `ELLIE_INPUT` environment variable,
+    /// at most [`MAX_INPUT_REQUESTS`] times and only for safe providers
+    /// (the call fails otherwise).
+    ///
+    /// # Errors
+    /// If approval failed or the command could not be started.
+    #[inline]
+    fn call(
+        &self,
+        arguments: &str,
+        policy: CallPolicy<'_>,
+    ) -> Result<FunctionResponse, EllieError> {
+        let Some(arguments) = self.approve(arguments, policy)? else {
+            return Ok(FunctionResponse::Aborted);
         };
-        Ok(response)
+        self.throttle();
+        if self.kind == ProviderKind::Http {
+            return Ok(self.process(self.request(&arguments), policy));
+        }
+        let args = match self.fill_args(&arguments) {
+            Ok(args) => args,
+            Err(reason) => return Ok(FunctionResponse::Invalid(reason)),
+        };
+        self.run(&args, &arguments, None, policy)
+    }
+
+    /// Run the command with the given command-line and standard input
+    /// arguments until it no longer requests user input,
+    /// starting from the output of an earlier run,
+    /// if any.
+    ///
+    /// # Errors
+    /// If asking for input failed or the command could not be started.
+    #[inline]
+    fn run(
+        &self,
+        args: &[String],
+        arguments: &str,
+        mut output: Option<CallOutput>,
+        policy: CallPolicy<'_>,
+    ) -> Result<FunctionResponse, EllieError> {
+        let mut input = None;
+        let mut requests = 0;
+        let response = loop {
+            let response = match output.take() {
+                Some(output) => output,
+                None => {
+                    let mut expression = policy
+                        .stderr
+                        .apply(duct::cmd(&self.command, args).stdin_bytes(arguments.as_bytes()))
+                        .stdout_capture()
+                        .unchecked();
+                    if let Some(input) = &input {
+                        expression = expression.env("ELLIE_INPUT", input);
+                    }
+                    let Some(response) = run_interruptible(&self.name, &expression)
+                        .map_err(|err| EllieError::provider(&self.name, err))?
+                    else {
+                        log::warn!("{name} was interrupted", name = self.name);
+                        return Ok(FunctionResponse::Aborted);
+                    };
+                    response
+                }
+            };
+            let Some(prompt) = input_request(&response.output) else {
+                break response;
+            };
+            // Unsafe commands were only approved to run once.
+            let reason = if !self.safe {
+                Some("requested input, but only safe providers are run again".to_owned())
+            } else if requests == MAX_INPUT_REQUESTS {
+                Some(format!(
+                    "requested input more than {MAX_INPUT_REQUESTS} times"
+                ))
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                log::warn!("{name} {reason}", name = self.name);
+                return Ok(FunctionResponse::Failed {
+                    content: response.output,
+                    reason,
+                    guidance: false,
+                });
+            }
+            requests += 1;
+            input = Some(ask_input(&self.name, &prompt)?);
+        };
+        Ok(self.process(response, policy))
+    }
+
+    /// Start the command with its standard input left open for streamed
+    /// arguments.
+    ///
+    /// # Errors
+    /// If the command could not be started.
+    #[inline]
+    fn start_streaming(&self, stderr: ProviderStderr) -> std::io::Result<StreamingCall> {
+        self.throttle();
+        let (reader, writer) = os_pipe::pipe()?;
+        let handle = stderr
+            .apply(duct::cmd(&self.command, &self.args).stdin_file(reader))
+            .stdout_capture()
+            .unchecked()
+            .start()?;
+        Ok(StreamingCall {
+            name: self.name.clone(),
+            stdin: Some(writer),
+            handle: Some(handle),
+            permit: None,
+        })
     }
 
+    /// Close the standard input of a streaming call,
+    /// given its complete arguments,
+    /// and wait for its output.
+    ///
+    /// # Errors
+    /// If asking for input failed or the command could not be waited for.
     #[inline]
-    fn specification(
+    fn finish_streaming(
         &self,
-    ) -> Result<ChatCompletionFunctions, either::Either<serde_json::Error, std::io::Error>> {
-        let spec = duct::cmd(
+        mut call: StreamingCall,
+        arguments: &str,
+        policy: CallPolicy<'_>,
+    ) -> Result<FunctionResponse, EllieError> {
+        log_call(&self.name, arguments, true, policy);
+        call.stdin = None;
+        let handle = call
+            .handle
+            .take()
+            .expect("streaming calls should only be finished once");
+        let Some(output) = wait_interruptible(&self.name, &handle)
+            .map_err(|err| EllieError::provider(&self.name, err))?
+        else {
+            log::warn!("{name} was interrupted", name = self.name);
+            return Ok(FunctionResponse::Aborted);
+        };
+        self.run(&self.args, arguments, Some(output), policy)
+    }
+
+    /// Wait until at least `min_interval` has passed since the start of the
+    /// last call,
+    /// then record the start of a new one.
+    #[inline]
+    fn throttle(&self) {
+        let mut last_call = self
+            .last_call
+            .lock()
+            .expect("last call should never be poisoned");
+        if let (Some(min_interval), Some(last_call)) = (self.min_interval, *last_call) {
+            let wait = std::time::Duration::from_secs_f64(min_interval)
+                .saturating_sub(last_call.elapsed());
+            // Sleeping past the deadline would overrun it.
+            let wait = remaining().map_or(wait, |remaining| wait.min(remaining));
+            if !wait.is_zero() {
+                log::info!("{name} throttled for {wait:.1?}", name = self.name);
+                std::thread::sleep(wait);
+            }
+        }
+        *last_call = Some(std::time::Instant::now());
+    }
+
+    /// Turn the output of a call into a function response.
+    #[inline]
+    fn process(&self, output: CallOutput, policy: CallPolicy<'_>) -> FunctionResponse {
+        let CallOutput { output, failure } = output;
+        let output = if self.strip_ansi.unwrap_or(policy.strip_ansi) {
+            strip_ansi(&output)
+        } else {
+            output
+        };
+        let mut response = FunctionResponse::from_output(output);
+        if let Some(reason) = failure {
+            // Structured errors are more useful than the exit status.
+            return match response {
+                FunctionResponse::Executed { content, .. } => FunctionResponse::Failed {
+                    content,
+                    reason,
+                    guidance: self.retry_guidance.unwrap_or(policy.retry_guidance),
+                },
+                response => response,
+            };
+        }
+        if let FunctionResponse::Executed { content, .. } = &mut response {
+            let pretty_json = self.pretty_json.unwrap_or(policy.pretty_json);
+            *content = match self.output_format {
+                Some(format) => format.apply(content, pretty_json),
+                None => format_result(content, pretty_json),
+            };
+        }
+        response
+    }
+
+    /// HTTP method of requests to the endpoint.
+    #[inline]
+    fn method(&self) -> String {
+        self.method.as_deref().unwrap_or("POST").to_uppercase()
+    }
+
+    /// Send the given JSON arguments to the endpoint,
+    /// returning the response body.
+    ///
+    /// Error responses are returned as failed output,
+    /// so that the model can act on them.
+    #[inline]
+    fn request(&self, arguments: &str) -> CallOutput {
+        log::info!("{method} {url}", method = self.method(), url = self.url);
+        let mut request = ureq::request(&self.method(), &self.url);
+        for (name, value) in &self.headers {
+            request = request.set(name, value);
+        }
+        if let Some(remaining) = remaining() {
+            request = request.timeout(remaining);
+        }
+        match request
+            .set("Content-Type", "application/json")
+            .send_string(arguments)
+        {
+            Ok(response) => {
+                let mut body = response
+                    .into_string()
+                    .unwrap_or_else(|err| format!("unreadable response: {err}"));
+                body.truncate(body.trim_end_matches(['\n', '\r']).len());
+                CallOutput {
+                    output: body,
+                    failure: None,
+                }
+            }
+            Err(ureq::Error::Status(status, response)) => CallOutput {
+                output: response.into_string().unwrap_or_default(),
+                failure: Some(format!("HTTP {status}")),
+            },
+            Err(err) => CallOutput {
+                output: err.to_string(),
+                failure: Some("request failed".to_owned()),
+            },
+        }
+    }
+
+    /// Get the function specification of the provider,
+    /// usually produced by the provider command.
+    ///
+    /// Templated providers usually wrap conventional command-line tools,
+    /// so their parameters are the string fields of their placeholders,
+    /// all required,
+    /// which a `[[function]]` section can describe further.
+    ///
+    /// HTTP providers get theirs from `spec_url`,
+    /// if any,
+    /// or from a `[[function]]` section as well.
+    #[inline]
+    fn specification(&self, strict_names: bool) -> Result<ChatCompletionFunctions, EllieError> {
+        let spec = match (self.kind, &self.spec_url) {
+            (ProviderKind::Http, Some(spec_url)) => {
+                let mut request = ureq::get(spec_url);
+                for (name, value) in &self.headers {
+                    request = request.set(name, value);
+                }
+                request
+                    .call()
+                    .map_err(|err| EllieError::provider(&self.name, err))?
+                    .into_string()
+                    .map_err(|err| EllieError::provider(&self.name, err))?
+            }
+            (ProviderKind::Command, _) if !self.is_templated() => self.command_specification()?,
+            (ProviderKind::Command, _) => {
+                let fields = self.fields();
+                let properties: serde_json::Map<_, _> = fields
+                    .iter()
+                    .map(|&field| (field.to_owned(), serde_json::json!({"type": "string"})))
+                    .collect();
+                return Ok(ChatCompletionFunctions {
+                    name: self.name.clone(),
+                    description: None,
+                    parameters: Some(serde_json::json!({
+                        "type": "object",
+                        "properties": properties,
+                        "required": fields,
+                    })),
+                });
+            }
+            _ => {
+                return Ok(ChatCompletionFunctions {
+                    name: self.name.clone(),
+                    description: None,
+                    parameters: None,
+                })
+            }
+        };
+
+        let mut spec: ChatCompletionFunctions =
+            serde_json::from_str(&spec).map_err(|err| EllieError::provider(&self.name, err))?;
+        if spec.name != self.name {
+            if strict_names {
+                return Err(EllieError::NameMismatch {
+                    name: self.name.clone(),
+                    other: spec.name,
+                });
+            }
+            log::warn!(
+                "provider '{name}' specification names function '{other}', using '{name}'",
+                name = self.name,
+                other = spec.name
+            );
+            spec.name = self.name.clone();
+        }
+        Ok(spec)
+    }
+
+    /// Get the function specification produced by the provider command,
+    /// as text.
+    #[inline]
+    fn command_specification(&self) -> Result<String, EllieError> {
+        duct::cmd(
             &self.command,
             self.args
                 .iter()
@@ -98,39 +1327,62 @@ impl Provider {
                 .chain(std::iter::once("spec")),
         )
         .read()
-        .map_err(either::Either::Right)?;
-
-        let mut spec: ChatCompletionFunctions =
-            serde_json::from_str(&spec).map_err(either::Either::Left)?;
-        if spec.name != self.name {
-            log::warn!("'{name}' != '{other}'", name = self.name, other = spec.name);
-            spec.name = self.name.clone();
-        }
-        Ok(spec)
+        .map_err(|err| EllieError::provider(&self.name, err))
     }
 }
 
-#[derive(Debug, Default, serde::Deserialize)]
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
 pub struct Functions {
     #[serde(default)]
     provider: Vec<Provider>,
     #[serde(default)]
-    function: Vec<ChatCompletionFunctions>,
+    function: Vec<Override>,
+    #[serde(default)]
+    openapi: Vec<openapi::Source>,
+
+    /// Operations generated from `OpenAPI` documents.
+    #[serde(skip)]
+    operations: Vec<openapi::Operation>,
 }
 
 impl Functions {
+    /// Path to the `functions.toml` configuration file.
+    ///
+    /// # Errors
+    /// If project directories could not be retrieved.
+    #[inline]
+    pub(super) fn path() -> Result<std::path::PathBuf, EllieError> {
+        Ok(crate::get_project_dirs()
+            .ok_or(EllieError::ProjectDirs)?
+            .config_dir()
+            .join("functions.toml"))
+    }
+
+    /// Load functions from the `functions.toml` configuration file.
+    ///
+    /// # Errors
+    /// If the configuration file could not be read or parsed.
     #[inline]
-    pub(super) fn load() -> color_eyre::eyre::Result<Self> {
-        use color_eyre::eyre::ContextCompat as _;
+    pub(super) fn load() -> Result<Self, EllieError> {
+        Self::load_from(&Self::path()?)
+    }
+
+    /// Load functions from the given configuration file,
+    /// e.g., a project file.
+    ///
+    /// # Errors
+    /// If the configuration file could not be read or parsed.
+    #[inline]
+    pub(super) fn load_from(path: &std::path::Path) -> Result<Self, EllieError> {
         use itertools::Itertools as _;
 
-        let content = std::fs::read_to_string(
-            get_project_dirs()
-                .context("getting project directories")?
-                .config_dir()
-                .join("functions.toml"),
-        )?;
-        let Self { provider, function } = toml::from_str(&content)?;
+        let content = std::fs::read_to_string(path).map_err(|err| EllieError::config(path, err))?;
+        let Self {
+            provider,
+            function,
+            openapi,
+            ..
+        } = toml::from_str(&content).map_err(|err| EllieError::config(path, err))?;
 
         let provider: Vec<_> = provider
             .into_iter()
@@ -148,43 +1400,161 @@ impl Functions {
             .map(
                 |Provider {
                      name,
+                     kind,
                      command,
                      args,
                      safe,
+                     approve_empty_args,
+                     confirm_prompt,
+                     output_format,
+                     strip_ansi,
+                     pretty_json,
+                     retry_guidance,
+                     url,
+                     method,
+                     headers,
+                     spec_url,
+                     min_interval,
+                     stream_arguments,
+                     requires,
+                     last_call,
                  }| {
-                    args.into_iter()
+                    if min_interval.is_some_and(|min_interval| {
+                        std::time::Duration::try_from_secs_f64(min_interval).is_err()
+                    }) {
+                        return Err(format!("provider '{name}' has an invalid min_interval").into());
+                    }
+                    match kind {
+                        ProviderKind::Command if command.is_empty() => {
+                            return Err(format!("provider '{name}' has no command").into());
+                        }
+                        ProviderKind::Http if url.is_empty() => {
+                            return Err(format!("provider '{name}' has no url").into());
+                        }
+                        _ => {}
+                    }
+                    let args = args
+                        .into_iter()
                         .map(|arg| shellexpand::full(&arg).map(Into::into))
-                        .collect::<Result<_, _>>()
-                        .map(|args| Provider {
-                            name,
-                            command,
-                            args,
-                            safe,
+                        .collect::<Result<_, _>>()?;
+                    let headers = headers
+                        .into_iter()
+                        .map(|(name, value)| {
+                            shellexpand::full(&value).map(|value| (name, value.into()))
                         })
+                        .collect::<Result<_, _>>()?;
+                    let provider = Provider {
+                        name,
+                        kind,
+                        command,
+                        args,
+                        safe,
+                        approve_empty_args,
+                        confirm_prompt,
+                        output_format,
+                        strip_ansi,
+                        pretty_json,
+                        retry_guidance,
+                        url: shellexpand::full(&url)?.into(),
+                        method,
+                        headers,
+                        spec_url,
+                        min_interval,
+                        stream_arguments,
+                        requires,
+                        last_call,
+                    };
+                    if provider.stream_arguments
+                        && (provider.kind == ProviderKind::Http || provider.is_templated())
+                    {
+                        return Err(format!(
+                            "provider '{name}' can only stream arguments to an untemplated \
+                             command",
+                            name = provider.name
+                        )
+                        .into());
+                    }
+                    Ok(provider)
                 },
             )
-            .collect::<Result<_, _>>()?;
+            .collect::<Result<_, Box<dyn std::error::Error + Send + Sync>>>()
+            .map_err(|err| EllieError::config(path, err))?;
+        let operations: Vec<_> = openapi
+            .iter()
+            .filter_map(|source| {
+                source
+                    .operations()
+                    .map_err(|err| log::warn!("skipping {source:?}: {err}"))
+                    .ok()
+            })
+            .flatten()
+            .filter(|operation| {
+                let is_unique = !provider
+                    .iter()
+                    .any(|provider| provider.name == operation.name());
+                if !is_unique {
+                    log::warn!(
+                        "operation '{name}' shadowed by provider",
+                        name = operation.name()
+                    );
+                }
+                is_unique
+            })
+            .collect();
         let function = function
             .into_iter()
-            .sorted_by(|f, g| f.name.cmp(&g.name))
-            .dedup_by_with_count(|f, g| f.name == g.name)
+            .sorted_by(|f, g| f.spec.name.cmp(&g.spec.name))
+            .dedup_by_with_count(|f, g| f.spec.name == g.spec.name)
             .inspect(|(count, function)| {
+                let name = &function.spec.name;
                 if *count > 1 {
-                    log::warn!(
-                        "function '{name}' defined {count} times",
-                        name = function.name
-                    );
+                    log::warn!("function '{name}' defined {count} times");
                 }
-                if !provider
-                    .iter()
-                    .any(|provider| provider.name == function.name)
+                if !provider.iter().any(|provider| &provider.name == name)
+                    && !operations.iter().any(|operation| operation.name() == name)
                 {
-                    log::warn!("function '{name}' has no provider", name = function.name);
+                    log::warn!("function '{name}' has no provider");
                 }
             })
             .map(|(_, function)| function)
-            .collect();
-        Ok(Self { provider, function })
+            .collect::<Vec<_>>();
+        for function in &function {
+            if function
+                .spec
+                .parameters
+                .as_ref()
+                .is_some_and(|parameters| nesting_depth(parameters) > MAX_PARAMETERS_DEPTH)
+            {
+                return Err(EllieError::config(
+                    path,
+                    format!(
+                        "function '{name}' has parameters nested deeper than \
+                         {MAX_PARAMETERS_DEPTH} levels",
+                        name = function.spec.name
+                    ),
+                ));
+            }
+        }
+        for (name, required) in provider.iter().flat_map(|provider| {
+            provider
+                .requires
+                .iter()
+                .map(|required| (&provider.name, required))
+        }) {
+            if !provider.iter().any(|provider| &provider.name == required)
+                && !operations
+                    .iter()
+                    .any(|operation| operation.name() == required)
+            {
+                log::warn!("provider '{name}' requires unknown function '{required}'");
+            }
+        }
+        Ok(Self {
+            provider,
+            function,
+            openapi,
+            operations,
+        })
     }
 
     #[inline]
@@ -193,68 +1563,724 @@ impl Functions {
     }
 
     #[inline]
-    fn functions(&self) -> impl Iterator<Item = &ChatCompletionFunctions> {
+    fn functions(&self) -> impl Iterator<Item = &Override> {
         self.function.iter()
     }
 
+    /// Iterate over the names and commands of all command providers.
+    #[inline]
+    pub(super) fn commands(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.providers()
+            .filter(|provider| provider.kind == ProviderKind::Command)
+            .map(|provider| (provider.name.as_ref(), provider.command.as_ref()))
+    }
+
+    /// Remove command providers whose command does not resolve to one of the
+    /// given paths,
+    /// so that they are neither offered to the model nor ever executed.
+    ///
+    /// HTTP providers execute nothing locally,
+    /// so they are kept.
+    #[inline]
+    fn restrict_commands(&mut self, allowed_commands: &[std::path::PathBuf]) {
+        let canonicalize = |path: &std::path::Path| {
+            std::fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())
+        };
+        let allowed_commands: Vec<_> = allowed_commands
+            .iter()
+            .map(|path| canonicalize(path))
+            .collect();
+        self.provider.retain(|provider| {
+            if provider.kind == ProviderKind::Http {
+                return true;
+            }
+            let is_allowed = which::which(&provider.command)
+                .is_ok_and(|command| allowed_commands.contains(&canonicalize(&command)));
+            if !is_allowed {
+                log::warn!(
+                    "provider '{name}' disabled: command '{command}' is not allowed",
+                    name = provider.name,
+                    command = provider.command
+                );
+            }
+            is_allowed
+        });
+    }
+
+    /// Check that every command provider's command can be found,
+    /// handling missing ones according to the given policy.
+    ///
+    /// # Errors
+    /// If a command is missing and the policy is to fail.
+    #[inline]
+    fn check_commands(&mut self, policy: MissingCommand) -> Result<(), EllieError> {
+        let mut missing = None;
+        self.provider.retain(|provider| {
+            let is_found =
+                provider.kind == ProviderKind::Http || which::which(&provider.command).is_ok();
+            if !is_found {
+                match policy {
+                    MissingCommand::Fail => {
+                        missing.get_or_insert_with(|| EllieError::MissingCommand {
+                            name: provider.name.clone(),
+                            command: provider.command.clone(),
+                        });
+                    }
+                    MissingCommand::Skip => log::warn!(
+                        "skipping provider '{name}': command '{command}' not found",
+                        name = provider.name,
+                        command = provider.command
+                    ),
+                }
+            }
+            is_found
+        });
+        missing.map_or(Ok(()), Err)
+    }
+
     #[inline]
     fn get_provider(&self, name: &str) -> Option<&Provider> {
         self.providers().find(|provider| provider.name == name)
     }
 
     #[inline]
-    fn get_function(&self, name: &str) -> Option<&ChatCompletionFunctions> {
-        self.functions().find(|function| function.name == name)
+    fn get_operation(&self, name: &str) -> Option<&openapi::Operation> {
+        self.operations
+            .iter()
+            .find(|operation| operation.name() == name)
+    }
+
+    #[inline]
+    fn get_function(&self, name: &str) -> Option<&Override> {
+        self.functions().find(|function| function.spec.name == name)
     }
 
+    /// Get the functions the given function requires,
+    /// i.e., that should be called before it.
     #[inline]
-    pub(super) fn call(&self, name: &str, arguments: &str) -> dialoguer::Result<FunctionResponse> {
+    pub(super) fn requirements(&self, name: &str) -> &[String] {
+        self.get_provider(name)
+            .map_or(&[], |provider| provider.requires.as_slice())
+    }
+
+    /// Call the given function with the given arguments,
+    /// handled according to the given policy.
+    #[inline]
+    pub(super) fn call(
+        &self,
+        name: &str,
+        arguments: &str,
+        policy: CallPolicy<'_>,
+    ) -> Result<FunctionResponse, EllieError> {
+        if policy.simulate {
+            self.simulate(name, arguments);
+            return Ok(FunctionResponse::Simulated);
+        }
         let response = if let Some(provider) = self.get_provider(name) {
-            provider
-                .call(arguments)?
-                .map_or(FunctionResponse::Aborted, FunctionResponse::Executed)
+            provider.call(arguments, policy)?
+        } else if let Some(operation) = self.get_operation(name) {
+            let safe =
+                operation.is_safe() || (policy.approve_empty_args && is_empty_arguments(arguments));
+            approve(name, arguments, safe, None, policy)?.map_or(
+                FunctionResponse::Aborted,
+                |arguments| FunctionResponse::Executed {
+                    content: format_result(&operation.call(&arguments), policy.pretty_json),
+                    metadata: None,
+                },
+            )
         } else {
             FunctionResponse::NotFound
         };
         Ok(response)
     }
 
+    /// Start a call to the given function before its arguments are complete,
+    /// if its provider streams arguments
+    /// and the call needs no approval.
+    #[inline]
+    pub(super) fn start_streaming(
+        &self,
+        name: &str,
+        policy: CallPolicy<'_>,
+    ) -> Option<StreamingCall> {
+        if policy.simulate {
+            return None;
+        }
+        let provider = self
+            .get_provider(name)
+            .filter(|provider| provider.stream_arguments)?;
+        if !provider.safe {
+            log::info!("not streaming arguments to {name}, which needs approval");
+            return None;
+        }
+        if policy.enforce_requires && !provider.requires.is_empty() {
+            log::info!("not streaming arguments to {name}, which requires other functions");
+            return None;
+        }
+        // Waiting here would hold up the answers that other commands wait for.
+        let Ok(permit) = permits().try_acquire() else {
+            log::info!("not streaming arguments to {name} while other providers run");
+            return None;
+        };
+        let mut call = provider
+            .start_streaming(policy.stderr)
+            .map_err(|err| log::warn!("could not start streaming to {name}: {err}"))
+            .ok()?;
+        call.permit = Some(permit);
+        Some(call)
+    }
+
+    /// Finish a call started with [`Self::start_streaming`],
+    /// given its complete arguments.
     #[inline]
-    pub(super) fn specifications(
+    pub(super) fn finish_streaming(
         &self,
-    ) -> impl Iterator<Item = color_eyre::eyre::Result<ChatCompletionFunctions>> + '_ {
-        use color_eyre::eyre::Context as _;
-
-        self.providers().map(|provider| {
-            let mut spec = provider
-                .specification()
-                .with_context(|| format!("getting function specification for {provider:?}"))?;
-            if let Some(function) = self.get_function(&spec.name) {
-                merge(&mut spec, function);
+        call: StreamingCall,
+        arguments: &str,
+        policy: CallPolicy<'_>,
+    ) -> Result<FunctionResponse, EllieError> {
+        match self.get_provider(&call.name) {
+            Some(provider) => provider.finish_streaming(call, arguments, policy),
+            None => Ok(FunctionResponse::NotFound),
+        }
+    }
+
+    /// Record what a call to the given function would execute,
+    /// without executing it.
+    #[inline]
+    fn simulate(&self, name: &str, arguments: &str) {
+        let target = if let Some(provider) = self.get_provider(name) {
+            if provider.kind == ProviderKind::Http {
+                format!(
+                    "{method} {url}",
+                    method = provider.method(),
+                    url = provider.url
+                )
+            } else {
+                let args = provider
+                    .fill_args(arguments)
+                    .unwrap_or_else(|_| provider.args.clone());
+                format!("{command} {args:?}", command = provider.command)
             }
-            Ok(spec)
+        } else if let Some(operation) = self.get_operation(name) {
+            operation.describe()
+        } else {
+            "not found".to_owned()
+        };
+        eprintln!("simulated {name}({arguments}): {target}");
+    }
+
+    /// Get all function specifications,
+    /// calling the given function with the index and name of each provider
+    /// before spawning it.
+    ///
+    /// Providers whose specification names another function
+    /// are an error if names are strict.
+    #[inline]
+    fn specifications_with<'s>(
+        &'s self,
+        strict_names: bool,
+        mut on_provider: impl FnMut(usize, &str) + 's,
+    ) -> impl Iterator<Item = Result<ChatCompletionFunctions, EllieError>> + 's {
+        self.providers()
+            .enumerate()
+            .map(move |(index, provider)| {
+                on_provider(index, &provider.name);
+                provider.specification(strict_names)
+            })
+            .chain(
+                self.operations
+                    .iter()
+                    .map(|operation| Ok(operation.specification())),
+            )
+            .map(|spec| {
+                let mut spec = spec?;
+                if let Some(function) = self.get_function(&spec.name) {
+                    merge(&mut spec, function);
+                }
+                Ok(spec)
+            })
+    }
+}
+
+/// Functions together with their specifications,
+/// loaded once and shared by all requests.
+#[derive(Debug, Default)]
+pub struct Toolset {
+    pub functions: Functions,
+    pub specifications: Vec<ChatCompletionFunctions>,
+}
+
+impl Toolset {
+    /// Load functions (if configured) and get all their specifications,
+    /// which spawns provider commands.
+    ///
+    /// Functions in the given project file replace the global ones.
+    /// If any allowed commands are given,
+    /// providers with other commands are disabled before anything is
+    /// spawned.
+    /// Providers whose command cannot be found are handled according to the
+    /// given policy.
+    ///
+    /// # Errors
+    /// If the project file could not be loaded,
+    /// if a command is missing and the policy is to fail,
+    /// if a function specification could not be retrieved,
+    /// or if names are strict and a specification names another function.
+    #[inline]
+    pub fn load(
+        project: Option<&std::path::Path>,
+        allowed_commands: &[std::path::PathBuf],
+        missing_command: MissingCommand,
+        strict_names: bool,
+    ) -> Result<Self, EllieError> {
+        let mut functions = match project {
+            Some(project) => Functions::load_from(project)?,
+            None => Functions::load().unwrap_or_else(|err| {
+                // A missing configuration file just means no functions.
+                if Functions::path().is_ok_and(|path| path.exists()) {
+                    log::warn!(
+                        "ignoring functions: {err:#}",
+                        err = color_eyre::eyre::Report::new(err)
+                    );
+                }
+                Functions::default()
+            }),
+        };
+        if !allowed_commands.is_empty() {
+            functions.restrict_commands(allowed_commands);
+        }
+        functions.check_commands(missing_command)?;
+        let progress = crate::progress::Progress::start();
+        let count = functions.provider.len();
+        let specifications = functions
+            .specifications_with(strict_names, |index, name| {
+                let status = format!(
+                    "loading function specifications ({index}/{count}): {name}",
+                    index = index + 1
+                );
+                crate::progress::log(&status);
+                progress.update(status);
+            })
+            .collect::<Result<_, _>>()?;
+        drop(progress);
+        Ok(Self {
+            functions,
+            specifications,
         })
     }
 }
 
 #[derive(Debug)]
 pub enum FunctionResponse {
-    Executed(String),
+    Executed {
+        content: String,
+
+        /// Structured metadata (e.g., status, timing or a resource URL),
+        /// which is not sent to the model.
+        metadata: Option<serde_json::Value>,
+    },
     Aborted,
     NotFound,
+
+    /// The function arguments could not be used,
+    /// for the given reason.
+    Invalid(String),
+
+    /// The call was recorded but not executed.
+    Simulated,
+
+    /// The call is identical to a previous one,
+    /// whose result (as given to the model) is reused.
+    Repeated(String),
+
+    /// The provider failed
+    /// (e.g., its command exited unsuccessfully),
+    /// producing the given content.
+    Failed {
+        content: String,
+        reason: String,
+
+        /// Whether the model is nudged to correct the arguments and retry.
+        guidance: bool,
+    },
+
+    /// The provider reported an error of the given kind
+    /// (e.g., `not_found` or `permission_denied`),
+    /// so that the model can act on it.
+    Error {
+        kind: String,
+        message: String,
+    },
+}
+
+impl FunctionResponse {
+    /// Why the call failed,
+    /// if it did.
+    #[inline]
+    pub fn failure(&self) -> Option<String> {
+        match self {
+            Self::Failed { reason, .. } => Some(reason.clone()),
+            Self::Invalid(reason) => Some(format!("invalid arguments: {reason}")),
+            Self::NotFound => Some("not found".to_owned()),
+            Self::Error { kind, .. } => Some(format!("{kind} error")),
+            Self::Executed { .. } | Self::Aborted | Self::Simulated | Self::Repeated(_) => None,
+        }
+    }
+
+    /// Whether the given result of a call,
+    /// as given to the model,
+    /// comes from a successful execution
+    /// (or from reusing one).
+    #[inline]
+    pub fn executed(content: &str) -> bool {
+        /// Prefixes of the results of calls that failed or were not executed,
+        /// as displayed.
+        const UNEXECUTED: [&str; 6] = [
+            "function call aborted:",
+            "function not found:",
+            "invalid function arguments:",
+            "simulated:",
+            "function failed (",
+            "function error (",
+        ];
+        !UNEXECUTED.iter().any(|prefix| content.starts_with(prefix))
+    }
+
+    /// Create a response out of provider output,
+    /// which is either plain content,
+    /// a JSON envelope `{"content": ..., "metadata": {...}}`,
+    /// or a JSON error envelope `{"error": {"kind": ..., "message": ...}}`.
+    #[inline]
+    fn from_output(output: String) -> Self {
+        let envelope = serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(&output)
+            .ok()
+            .filter(|envelope| {
+                (envelope.contains_key("content") != envelope.contains_key("error"))
+                    && envelope
+                        .keys()
+                        .all(|key| key == "content" || key == "error" || key == "metadata")
+            });
+        match envelope {
+            Some(mut envelope) if envelope.contains_key("error") => {
+                if let Some(metadata) = envelope.remove("metadata") {
+                    log::info!("metadata: {metadata}");
+                }
+                match envelope.remove("error") {
+                    Some(serde_json::Value::Object(mut error)) => {
+                        let mut field = |key| match error.remove(key) {
+                            Some(serde_json::Value::String(value)) => Some(value),
+                            Some(value) => Some(value.to_string()),
+                            None => None,
+                        };
+                        Self::Error {
+                            kind: field("kind").unwrap_or_else(|| "unknown".to_owned()),
+                            message: field("message").unwrap_or_default(),
+                        }
+                    }
+                    Some(serde_json::Value::String(message)) => Self::Error {
+                        kind: "unknown".to_owned(),
+                        message,
+                    },
+                    _ => Self::Executed {
+                        content: output,
+                        metadata: None,
+                    },
+                }
+            }
+            Some(mut envelope) => {
+                let content = match envelope.remove("content") {
+                    Some(serde_json::Value::String(content)) => content,
+                    Some(content) => content.to_string(),
+                    None => String::new(),
+                };
+                let metadata = envelope.remove("metadata");
+                if let Some(metadata) = &metadata {
+                    log::info!("metadata: {metadata}");
+                }
+                Self::Executed { content, metadata }
+            }
+            None => Self::Executed {
+                content: output,
+                metadata: None,
+            },
+        }
+    }
 }
 
 impl std::fmt::Display for FunctionResponse {
     #[inline]
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Self::Executed(output) => write!(f, "{output}", output = try_compact_json(output)),
+            Self::Executed { content, .. } => write!(f, "{content}"),
             Self::Aborted => write!(f, "function call aborted: user denied command execution"),
             Self::NotFound => write!(
                 f,
                 "function not found: the requested function is currently unavailable or not \
                  implemented yet"
             ),
+            Self::Invalid(reason) => write!(f, "invalid function arguments: {reason}"),
+            Self::Simulated => write!(f, "simulated: not executed"),
+            Self::Repeated(content) => write!(
+                f,
+                "{content}\n\n(identical to a previous call, not executed again)"
+            ),
+            Self::Failed {
+                content,
+                reason,
+                guidance,
+            } => {
+                write!(f, "function failed ({reason}): {content}")?;
+                if *guidance {
+                    write!(f, "\n\n{RETRY_GUIDANCE}")?;
+                }
+                Ok(())
+            }
+            Self::Error { kind, message } => write!(f, "function error ({kind}): {message}"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parameters with nested objects and an array.
+    fn parameters() -> serde_json::Value {
+        serde_json::json!({
+            "type": "object",
+            "properties": {
+                "location": {"type": "string", "description": "City"},
+                "unit": {"type": "string", "enum": ["celsius", "fahrenheit"]},
+            },
+            "required": ["location", "unit"],
+        })
+    }
+
+    /// Patch of a nested object and an array.
+    fn patch() -> serde_json::Value {
+        serde_json::json!({
+            "properties": {
+                "location": {"description": "City and state"},
+            },
+            "required": ["location"],
+        })
+    }
+
+    #[test]
+    fn unlimited_merge_recurses_into_nested_objects() {
+        let mut target = parameters();
+        merge_to_depth(&mut target, &patch(), None);
+        assert_eq!(
+            target,
+            serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "location": {"type": "string", "description": "City and state"},
+                    "unit": {"type": "string", "enum": ["celsius", "fahrenheit"]},
+                },
+                "required": ["location"],
+            })
+        );
+
+        let mut expected = parameters();
+        json_patch::merge(&mut expected, &patch());
+        assert_eq!(target, expected);
+    }
+
+    #[test]
+    fn shallow_merge_replaces_nested_objects() {
+        let mut target = parameters();
+        merge_to_depth(&mut target, &patch(), Some(1));
+        assert_eq!(
+            target,
+            serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "location": {"description": "City and state"},
+                },
+                "required": ["location"],
+            })
+        );
+    }
+
+    #[test]
+    fn merge_to_depth_two_replaces_deeper_objects() {
+        let mut target = parameters();
+        merge_to_depth(&mut target, &patch(), Some(2));
+        assert_eq!(
+            target,
+            serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "location": {"description": "City and state"},
+                    "unit": {"type": "string", "enum": ["celsius", "fahrenheit"]},
+                },
+                "required": ["location"],
+            })
+        );
+    }
+
+    #[test]
+    fn merge_to_depth_zero_replaces_the_target() {
+        let mut target = parameters();
+        merge_to_depth(&mut target, &patch(), Some(0));
+        assert_eq!(target, patch());
+    }
+
+    #[test]
+    fn arrays_are_replaced_rather_than_merged() {
+        let mut target = serde_json::json!({"enum": ["a", "b"], "items": [{"x": 1}]});
+        let patch = serde_json::json!({"enum": ["c"], "items": [{"y": 2}]});
+        merge_to_depth(&mut target, &patch, None);
+        assert_eq!(target, patch);
+    }
+
+    #[test]
+    fn null_removes_keys() {
+        let mut target = parameters();
+        let patch = serde_json::json!({
+            "properties": {"unit": null},
+            "required": null,
+        });
+        merge_to_depth(&mut target, &patch, None);
+        assert_eq!(
+            target,
+            serde_json::json!({
+                "type": "object",
+                "properties": {
+                    "location": {"type": "string", "description": "City"},
+                },
+            })
+        );
+
+        // A shallow merge still removes top-level keys.
+        let mut target = parameters();
+        merge_to_depth(&mut target, &serde_json::json!({"required": null}), Some(1));
+        assert!(target.get("required").is_none());
+    }
+
+    /// Load a `[[function]]` override whose parameters nest objects to the
+    /// given depth.
+    fn load_nested(depth: usize) -> Result<Functions, EllieError> {
+        let keys: Vec<_> = (1..depth).map(|level| format!("k{level}")).collect();
+        let content = format!(
+            "[[function]]\nname = \"nested\"\n\n[function.parameters.{keys}]\nvalue = 1\n",
+            keys = keys.join(".")
+        );
+        let path = std::env::temp_dir().join(format!(
+            "ellie-nested-{pid}-{depth}.toml",
+            pid = std::process::id()
+        ));
+        std::fs::write(&path, content).unwrap();
+        let functions = Functions::load_from(&path);
+        std::fs::remove_file(&path).unwrap();
+        functions
+    }
+
+    #[test]
+    fn overrides_nested_too_deeply_are_rejected() {
+        let err = load_nested(MAX_PARAMETERS_DEPTH + 1).unwrap_err();
+        assert!(matches!(err, EllieError::Config { .. }));
+        assert!(format!("{:#}", color_eyre::eyre::Report::new(err))
+            .contains("function 'nested' has parameters nested deeper than 64 levels"));
+    }
+
+    #[test]
+    fn overrides_nested_to_the_limit_are_accepted() {
+        let functions = load_nested(MAX_PARAMETERS_DEPTH).unwrap();
+        let parameters = functions
+            .get_function("nested")
+            .and_then(|function| function.spec.parameters.as_ref())
+            .unwrap();
+        assert_eq!(nesting_depth(parameters), MAX_PARAMETERS_DEPTH);
+    }
+
+    #[test]
+    fn placeholders_are_split_from_text() {
+        assert_eq!(
+            segments("--city={city}"),
+            [Segment::Text("--city="), Segment::Field("city")]
+        );
+        assert_eq!(
+            segments("{from}..{to}"),
+            [
+                Segment::Field("from"),
+                Segment::Text(".."),
+                Segment::Field("to")
+            ]
+        );
+    }
+
+    #[test]
+    fn escaped_braces_are_literal() {
+        assert_eq!(
+            segments("{{city}}"),
+            [
+                Segment::Text("{"),
+                Segment::Text("city"),
+                Segment::Text("}")
+            ]
+        );
+        assert_eq!(
+            segments("{{{city}}}"),
+            [
+                Segment::Text("{"),
+                Segment::Field("city"),
+                Segment::Text("}")
+            ]
+        );
+    }
+
+    #[test]
+    fn braces_not_forming_placeholders_are_kept() {
+        assert_eq!(
+            segments("{city"),
+            [Segment::Text("{"), Segment::Text("city")]
+        );
+        assert_eq!(
+            segments("city}"),
+            [Segment::Text("city"), Segment::Text("}")]
+        );
+        assert_eq!(segments("{}"), [Segment::Text("{"), Segment::Text("}")]);
+        assert_eq!(
+            segments("{the city}"),
+            [
+                Segment::Text("{"),
+                Segment::Text("the city"),
+                Segment::Text("}")
+            ]
+        );
+    }
+
+    #[test]
+    fn templated_providers_require_their_fields() {
+        let provider: Provider = toml::from_str(
+            r#"
+            name = "get_weather"
+            command = "weather"
+            args = ["--city", "{city}", "--units={unit}", "{{{city}}}"]
+            "#,
+        )
+        .unwrap();
+        let spec = provider.specification(false).unwrap();
+        assert_eq!(
+            spec.parameters,
+            Some(serde_json::json!({
+                "type": "object",
+                "properties": {"city": {"type": "string"}, "unit": {"type": "string"}},
+                "required": ["city", "unit"],
+            }))
+        );
+        assert_eq!(
+            provider
+                .fill_args(r#"{"city": "Paris", "unit": "celsius"}"#)
+                .unwrap(),
+            ["--city", "Paris", "--units=celsius", "{Paris}"]
+        );
+        assert_eq!(
+            provider.fill_args(r#"{"city": "Paris"}"#).unwrap_err(),
+            "missing field 'unit'"
+        );
+    }
+}
@@ -0,0 +1,104 @@
+/// How requests choose among several API keys.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum KeyRotation {
+    /// Use the next key for every request.
+    #[default]
+    RoundRobin,
+    /// Keep using the same key until it gets rate limited.
+    Failover,
+}
+
+/// Several API keys,
+/// each with its own client,
+/// used in turn to raise throughput and survive rate limits.
+///
+/// Keys are only identified by their position in logs.
+#[derive(Debug)]
+pub struct Keys {
+    configs: Vec<async_openai::config::OpenAIConfig>,
+    clients: Vec<async_openai::Client<async_openai::config::OpenAIConfig>>,
+    rotation: KeyRotation,
+
+    /// Index of the key to use next.
+    current: std::sync::atomic::AtomicUsize,
+}
+
+impl Keys {
+    /// Create clients for the given keys,
+    /// or nothing if there are no keys.
+    #[inline]
+    pub fn new(keys: &[String], api_base: &str, rotation: KeyRotation) -> Option<Self> {
+        let configs: Vec<_> = keys
+            .iter()
+            .map(|key| key.trim())
+            .filter(|key| !key.is_empty())
+            .map(|key| {
+                async_openai::config::OpenAIConfig::new()
+                    .with_api_base(api_base)
+                    .with_api_key(key)
+            })
+            .collect();
+        let clients = configs
+            .iter()
+            .cloned()
+            .map(async_openai::Client::with_config)
+            .collect();
+        (!configs.is_empty()).then(|| Self {
+            configs,
+            clients,
+            rotation,
+            current: std::sync::atomic::AtomicUsize::new(0),
+        })
+    }
+
+    /// Number of keys.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.clients.len()
+    }
+
+    /// Get the client for the next request,
+    /// with the index of its key.
+    #[inline]
+    pub fn next(
+        &self,
+    ) -> (
+        usize,
+        &async_openai::Client<async_openai::config::OpenAIConfig>,
+    ) {
+        use std::sync::atomic::Ordering;
+
+        let index = match self.rotation {
+            KeyRotation::RoundRobin => self.current.fetch_add(1, Ordering::Relaxed),
+            KeyRotation::Failover => self.current.load(Ordering::Relaxed),
+        } % self.len();
+        log::debug!("using API key #{key}", key = index + 1);
+        (index, &self.clients[index])
+    }
+
+    /// Get the configuration of the client of the key with the given index.
+    #[inline]
+    pub fn config(&self, index: usize) -> &async_openai::config::OpenAIConfig {
+        &self.configs[index]
+    }
+
+    /// Move on from the key with the given index,
+    /// which got rate limited.
+    #[inline]
+    pub fn rate_limited(&self, index: usize) {
+        use std::sync::atomic::Ordering;
+
+        log::warn!("API key #{key} rate limited", key = index + 1);
+        if let KeyRotation::Failover = self.rotation {
+            // Concurrent requests may have moved on already.
+            self.current
+                .compare_exchange(
+                    index,
+                    (index + 1) % self.len(),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                )
+                .ok();
+        }
+    }
+}
@@ -1,132 +1,1018 @@
 use async_openai::types as aot;
 
+use crate::error::EllieError;
+
+mod approval;
+mod audit;
+mod budget;
+mod cache;
+mod cli;
+mod config;
+mod doctor;
+mod error;
 mod functions;
+mod keys;
+mod markdown;
+mod models;
+mod openapi;
+mod output;
+mod progress;
+mod project;
+mod redact;
+mod sessions;
+mod snippets;
+mod stats;
+mod telemetry;
+mod throttle;
+mod timing;
+mod wrap;
 
-/// Temperature used in all requests.
+/// Default temperature used in all requests.
 const TEMPERATURE: f32 = 0.0;
 
-/// Minimum number of tokens to be able to generate in the completion.
+/// Default minimum number of tokens to be able to generate in the
+/// completion.
 const MIN_COMPLETION_TOKENS: usize = 512;
 
-/// Available `OpenAI` models sorted by price.
-const MODELS: [&str; 4] = [
-    "gpt-3.5-turbo",     // $0.0015 / 1K tokens
-    "gpt-3.5-turbo-16k", // $0.003  / 1K tokens
-    "gpt-4",             // $0.03   / 1K tokens
-    "gpt-4-32k",         // $0.06   / 1K tokens
-];
+/// Maximum number of retries of a rate-limited request.
+const MAX_RATE_LIMIT_RETRIES: u32 = 5;
+
+/// Message sent to the model when it returns an empty answer.
+const EMPTY_ANSWER_NUDGE: &str = "Please provide a response.";
+
+/// Default maximum size of the standard input in bytes.
+const MAX_INPUT_BYTES: u64 = 10 * 1024 * 1024;
+
+/// Size of the chunks the standard input is read in.
+const INPUT_CHUNK_BYTES: usize = 64 * 1024;
+
+/// Tokens left out of the completion budget with `--max-tokens auto`,
+/// since function specifications are only estimated.
+const MAX_TOKENS_MARGIN: usize = 64;
+
+/// Maximum number of continuations of a truncated answer.
+const MAX_CONTINUATIONS: usize = 5;
+
+/// Message sent to the model to continue a truncated answer.
+const CONTINUE_NUDGE: &str = "Continue exactly where you left off, without repeating anything.";
+
+/// Instruction to condense earlier messages with `--on-overflow summarize`.
+const SUMMARY_PROMPT: &str = "Summarize the following conversation concisely, keeping every fact, \
+                              decision and open question needed to continue it.";
+
+/// Qualifier of ellie's directories,
+/// overridable at compile time with `ELLIE_QUALIFIER`.
+const QUALIFIER: &str = match option_env!("ELLIE_QUALIFIER") {
+    Some(qualifier) => qualifier,
+    None => "io.github",
+};
+
+/// Organization of ellie's directories,
+/// overridable at compile time with `ELLIE_ORGANIZATION`.
+const ORGANIZATION: &str = match option_env!("ELLIE_ORGANIZATION") {
+    Some(organization) => organization,
+    None => "schneiderfelipe",
+};
+
+/// Application name of ellie's directories,
+/// overridable at compile time with `ELLIE_APP_NAME`
+/// (e.g., so that forks don't share configuration with upstream).
+const APP_NAME: &str = match option_env!("ELLIE_APP_NAME") {
+    Some(app_name) => app_name,
+    None => "ellie",
+};
+
+#[inline]
+fn get_project_dirs() -> Option<directories::ProjectDirs> {
+    directories::ProjectDirs::from(QUALIFIER, ORGANIZATION, APP_NAME)
+}
 
 /// Check if the given model has a large enough context length for the given
-/// messages.
+/// messages,
+/// leaving room for at least `min_completion_tokens` in the completion.
 ///
 /// # Errors
-/// If the model could not be retrieved.
+/// If the tokenizer of the model could not be loaded.
 #[inline]
 fn messages_fit_model(
-    model: &str,
+    model: &models::Model,
     messages: &[aot::ChatCompletionRequestMessage],
-) -> color_eyre::eyre::Result<bool> {
-    let max_tokens = tiktoken_rs::async_openai::get_chat_completion_max_tokens(model, messages)
-        .map_err(|err| color_eyre::eyre::eyre!(err))?
-        >= MIN_COMPLETION_TOKENS;
-    Ok(max_tokens)
+    min_completion_tokens: usize,
+) -> Result<bool, EllieError> {
+    let max_tokens = model
+        .context_length()
+        .saturating_sub(model.count_tokens(messages)?);
+    Ok(max_tokens >= min_completion_tokens)
 }
 
 /// Find the cheapest model with large enough context length for the given
-/// messages.
+/// messages,
+/// or a higher-priority one within the price tolerance
+/// (see [`models::Models::choose`]).
 ///
+/// If `smart` is set,
+/// smart models are preferred when any of them fits.
+/// Models whose tokenizer could not be loaded are skipped with a warning.
 /// If no model with large enough context length can be found,
 /// this function returns [`None`].
 #[inline]
-fn choose_model(messages: &[aot::ChatCompletionRequestMessage]) -> Option<&'static str> {
-    MODELS.into_iter().find(|model| {
-        messages_fit_model(model, messages)
-            .expect("model retrieval of known models should never fail")
-    })
+fn choose_model<'m>(
+    models: &'m models::Models,
+    messages: &[aot::ChatCompletionRequestMessage],
+    min_completion_tokens: usize,
+    smart: bool,
+) -> Option<&'m models::Model> {
+    let fits = |model: &&models::Model| {
+        messages_fit_model(model, messages, min_completion_tokens).unwrap_or_else(|err| {
+            log::warn!("skipping model '{name}': {err}", name = model.name);
+            false
+        })
+    };
+    smart
+        .then(|| models.choose(models.iter().filter(|model| model.smart).filter(fits)))
+        .flatten()
+        .or_else(|| models.choose(models.iter().filter(fits)))
 }
 
 /// Call the given function with the given standard input arguments
-/// and build a message out of the returned contents.
+/// (or finish the given call already streaming them)
+/// and build a message out of the returned contents,
+/// also returning the function response itself.
 #[inline]
 fn create_function_message(
+    functions: &functions::Functions,
     name: &str,
     arguments: &str,
+    policy: functions::CallPolicy<'_>,
+    streaming_call: Option<functions::StreamingCall>,
 ) -> Result<
-    aot::ChatCompletionRequestMessage,
-    either::Either<async_openai::error::OpenAIError, dialoguer::Error>,
+    (
+        aot::ChatCompletionRequestMessage,
+        functions::FunctionResponse,
+    ),
+    EllieError,
 > {
-    let response = functions::Functions::load()
-        .unwrap_or_default()
-        .call(name, arguments)
-        .map_err(either::Either::Right)?;
-    log::info!("{name}({arguments}): {response:?}");
-    aot::ChatCompletionRequestMessageArgs::default()
+    let response = match streaming_call {
+        Some(call) => functions.finish_streaming(call, arguments, policy)?,
+        None => functions.call(name, arguments, policy)?,
+    };
+    let message = aot::ChatCompletionRequestMessageArgs::default()
         .role(aot::Role::Function)
         .name(name)
         .content(response.to_string())
-        .build()
-        .map_err(either::Either::Left)
+        .build()?;
+    Ok((message, response))
 }
 
 /// Create a user message for the given input.
 ///
 /// # Errors
-/// If the created message could not fit the cheapest model alone.
+/// If the created message could not fit even the largest model alone.
 #[inline]
-fn create_user_message(input: &str) -> color_eyre::eyre::Result<aot::ChatCompletionRequestMessage> {
+fn create_user_message(
+    models: &models::Models,
+    input: &str,
+    min_completion_tokens: usize,
+) -> Result<aot::ChatCompletionRequestMessage, EllieError> {
     let input = input.trim();
     let messages = [aot::ChatCompletionRequestMessageArgs::default()
         .role(aot::Role::User)
         .content(input)
         .build()?];
-    color_eyre::eyre::ensure!(
-        messages_fit_model(MODELS[0], &messages)
-            .expect("model retrieval of known models should never fail"),
-        "user input should fit model '{model}'",
-        model = MODELS[0]
-    );
+    let model = models.largest();
+    if !messages_fit_model(model, &messages, min_completion_tokens)? {
+        return Err(EllieError::InputTooLong {
+            model: model.name.clone(),
+        });
+    }
     let [message] = messages;
     Ok(message)
 }
 
+/// Create a system message describing the current environment
+/// (working directory, operating system and shell).
+///
+/// # Errors
+/// If the current working directory could not be retrieved.
+#[inline]
+fn create_environment_message() -> color_eyre::eyre::Result<aot::ChatCompletionRequestMessage> {
+    let cwd = std::env::current_dir()?;
+    let shell = std::env::var("SHELL").unwrap_or_else(|_| "unknown".to_owned());
+    Ok(aot::ChatCompletionRequestMessageArgs::default()
+        .role(aot::Role::System)
+        .content(format!(
+            "Current working directory: {cwd}\nOperating system: {os} ({arch})\nShell: {shell}",
+            cwd = cwd.display(),
+            os = std::env::consts::OS,
+            arch = std::env::consts::ARCH,
+        ))
+        .build()?)
+}
+
+/// What to do when the conversation fits no model.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Overflow {
+    /// Fail with an error.
+    #[default]
+    Error,
+    /// Drop the oldest messages of the history.
+    Trim,
+    /// Condense the oldest messages of the history into a summary.
+    Summarize,
+}
+
+/// When the user input is composed in an editor.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum EditInput {
+    /// If the standard input is a terminal.
+    #[default]
+    Auto,
+    /// Always,
+    /// starting from the standard input if it is not a terminal.
+    Always,
+    /// Never,
+    /// reading the standard input instead.
+    Never,
+}
+
+/// Time zone of injected timestamps.
+#[derive(Clone, Copy, Debug)]
+pub enum Timezone {
+    Local,
+    Utc,
+    Offset(chrono::FixedOffset),
+}
+
+impl std::str::FromStr for Timezone {
+    type Err = String;
+
+    /// Parse `local`,
+    /// `utc`,
+    /// or a UTC offset such as `+05:30`.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "local" => Ok(Self::Local),
+            "utc" => Ok(Self::Utc),
+            _ => s
+                .parse()
+                .map(Self::Offset)
+                .map_err(|_| format!("expected 'local', 'utc' or an offset like '+05:30': '{s}'")),
+        }
+    }
+}
+
+/// Which function the model may call.
+#[derive(Clone, Debug)]
+pub enum ToolChoice {
+    /// Whichever the model prefers,
+    /// if any.
+    Auto,
+    /// None at all.
+    None,
+    /// The given one,
+    /// in the first request of every answer.
+    Function(String),
+}
+
+impl std::str::FromStr for ToolChoice {
+    type Err = String;
+
+    /// Parse `auto`,
+    /// `none`,
+    /// or a function name.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "" => Err("expected 'auto', 'none' or a function name".to_owned()),
+            "auto" => Ok(Self::Auto),
+            "none" => Ok(Self::None),
+            name => Ok(Self::Function(name.to_owned())),
+        }
+    }
+}
+
+impl ToolChoice {
+    /// Get the `function_call` field of a request,
+    /// given whether it is the first of its answer.
+    ///
+    /// A function is only forced once,
+    /// so that the model can answer with its result
+    /// instead of calling it forever.
+    #[inline]
+    fn function_call(&self, first: bool) -> Option<aot::ChatCompletionFunctionCall> {
+        match self {
+            Self::Auto => None,
+            Self::None => Some(aot::ChatCompletionFunctionCall::String("none".to_owned())),
+            Self::Function(name) if first => Some(aot::ChatCompletionFunctionCall::Object(
+                serde_json::json!({ "name": name }),
+            )),
+            Self::Function(_) => None,
+        }
+    }
+}
+
+/// Largest number of tokens to generate in a completion.
+#[derive(Clone, Copy, Debug)]
+pub enum MaxTokens {
+    /// Whatever the context of the chosen model leaves after the prompt.
+    Auto,
+    /// The given number.
+    Fixed(u16),
+}
+
+impl std::str::FromStr for MaxTokens {
+    type Err = String;
+
+    /// Parse `auto` or a number of tokens.
+    #[inline]
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "auto" => Ok(Self::Auto),
+            tokens => tokens
+                .parse()
+                .ok()
+                .filter(|&tokens| tokens > 0)
+                .map(Self::Fixed)
+                .ok_or_else(|| format!("expected 'auto' or a positive number of tokens: '{s}'")),
+        }
+    }
+}
+
+impl MaxTokens {
+    /// Get the `max_tokens` field of the given request to the given model.
+    ///
+    /// In automatic mode,
+    /// this is the context length of the model minus the tokens of the
+    /// messages and the function specifications,
+    /// and a small safety margin.
+    ///
+    /// # Errors
+    /// If the tokenizer of the model could not be loaded.
+    #[inline]
+    fn resolve(
+        self,
+        model: &models::Model,
+        request: &aot::CreateChatCompletionRequest,
+    ) -> Result<u16, EllieError> {
+        match self {
+            Self::Fixed(tokens) => Ok(tokens),
+            Self::Auto => {
+                let mut prompt_tokens = model.count_tokens(&request.messages)?;
+                if let Some(functions) = &request.functions {
+                    let specifications = aot::ChatCompletionRequestMessageArgs::default()
+                        .role(aot::Role::System)
+                        .content(serde_json::to_string(functions).unwrap_or_default())
+                        .build()?;
+                    prompt_tokens += model.count_tokens(&[specifications])?;
+                }
+                let tokens = model
+                    .context_length()
+                    .saturating_sub(prompt_tokens + MAX_TOKENS_MARGIN)
+                    .max(1);
+                Ok(u16::try_from(tokens).unwrap_or(u16::MAX))
+            }
+        }
+    }
+}
+
+/// Create a system message with the current date and time,
+/// formatted with the given `strftime`-like format in the given time zone.
+///
+/// # Errors
+/// If the format is invalid.
+#[inline]
+fn create_time_message(
+    format: &str,
+    timezone: Timezone,
+) -> color_eyre::eyre::Result<aot::ChatCompletionRequestMessage> {
+    use std::fmt::Write as _;
+
+    use color_eyre::eyre::Context as _;
+
+    let mut now = String::new();
+    match timezone {
+        Timezone::Local => write!(now, "{}", chrono::Local::now().format(format)),
+        Timezone::Utc => write!(now, "{}", chrono::Utc::now().format(format)),
+        Timezone::Offset(offset) => write!(
+            now,
+            "{}",
+            chrono::Utc::now().with_timezone(&offset).format(format)
+        ),
+    }
+    .with_context(|| format!("bad time format '{format}'"))?;
+    Ok(aot::ChatCompletionRequestMessageArgs::default()
+        .role(aot::Role::System)
+        .content(format!("Current date and time: {now}"))
+        .build()?)
+}
+
+/// Create a system message with the output of the last shell command,
+/// as captured by the shell in the given file.
+///
+/// This function returns [`None`] if the output is empty.
+///
+/// # Errors
+/// If no file is given or the file could not be read.
+#[inline]
+fn create_last_output_message(
+    path: Option<&std::path::Path>,
+) -> color_eyre::eyre::Result<Option<aot::ChatCompletionRequestMessage>> {
+    use color_eyre::eyre::Context as _;
+
+    let Some(path) = path else {
+        color_eyre::eyre::bail!(
+            "no file to read the last output from (pass '--last-output-file' or set ELLIE_LAST_OUTPUT_FILE)"
+        );
+    };
+    let output = std::fs::read(path)
+        .with_context(|| format!("reading last output from '{path}'", path = path.display()))?;
+    let output = functions::strip_ansi(&String::from_utf8_lossy(&output));
+    let output = output.trim();
+    if output.is_empty() {
+        log::warn!("last output file '{path}' is empty", path = path.display());
+        return Ok(None);
+    }
+    Ok(Some(
+        aot::ChatCompletionRequestMessageArgs::default()
+            .role(aot::Role::System)
+            .content(format!(
+                "Output of the last shell command:\n```\n{output}\n```"
+            ))
+            .build()?,
+    ))
+}
+
+/// Create a system message enumerating the given functions and when to use
+/// them,
+/// which helps weaker models with tool use.
+///
+/// Functions that should be called after others are marked as such.
+///
+/// This function returns [`None`] if there are no functions.
+#[inline]
+fn create_functions_message(
+    functions: &functions::Functions,
+    specifications: &[aot::ChatCompletionFunctions],
+) -> Result<Option<aot::ChatCompletionRequestMessage>, EllieError> {
+    use std::fmt::Write as _;
+
+    if specifications.is_empty() {
+        return Ok(None);
+    }
+    let mut content =
+        "You can call the following functions when they help answer the user:\n".to_owned();
+    for spec in specifications {
+        match &spec.description {
+            Some(description) => writeln!(content, "- {name}: {description}", name = spec.name),
+            None => writeln!(content, "- {name}", name = spec.name),
+        }
+        .expect("writing to a string should never fail");
+        let requirements = functions.requirements(&spec.name);
+        if !requirements.is_empty() {
+            writeln!(
+                content,
+                "  (call only after {requirements})",
+                requirements = requirements.join(", ")
+            )
+            .expect("writing to a string should never fail");
+        }
+    }
+    Ok(Some(
+        aot::ChatCompletionRequestMessageArgs::default()
+            .role(aot::Role::System)
+            .content(content.trim_end())
+            .build()?,
+    ))
+}
+
 /// Get chat messages ending in the given new messages,
-/// essentially building context to them.
+/// essentially building context to them out of the conversation history,
+/// preceded by the context messages of the current answer.
 #[inline]
 fn create_chat_messages(
+    context: &[aot::ChatCompletionRequestMessage],
+    history: &[aot::ChatCompletionRequestMessage],
     new_messages: &[aot::ChatCompletionRequestMessage],
+    function_role: functions::FunctionRole,
 ) -> Vec<aot::ChatCompletionRequestMessage> {
-    new_messages.to_owned()
+    context
+        .iter()
+        .chain(history)
+        .chain(new_messages)
+        .cloned()
+        .map(|message| function_role.apply(message))
+        .collect()
+}
+
+/// Read the standard input in chunks,
+/// stopping as soon as it exceeds the given number of bytes,
+/// or,
+/// if models are given (with the minimum number of completion tokens),
+/// as soon as it can't fit the cheapest one as a user message.
+///
+/// Progress is shown while reading takes long.
+///
+/// # Errors
+/// If the standard input could not be read,
+/// is not valid UTF-8,
+/// exceeds the limit,
+/// or doesn't fit even the largest model.
+#[inline]
+fn read_input(
+    limit: u64,
+    fit: Option<(&models::Models, usize)>,
+) -> color_eyre::eyre::Result<String> {
+    use std::io::Read as _;
+
+    let progress = progress::Progress::start();
+    let mut stdin = std::io::stdin().lock();
+    let mut input = Vec::new();
+    let mut chunk = vec![0; INPUT_CHUNK_BYTES];
+    // Every token has at least one byte,
+    // so tokens are only counted once there are more bytes than would fit,
+    // and again whenever the input doubles,
+    // which keeps counting linear in the input size.
+    let mut next_check = fit.map(|(models, min_completion_tokens)| {
+        models
+            .largest()
+            .context_length()
+            .saturating_sub(min_completion_tokens)
+    });
+    loop {
+        let read = match stdin.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        input.extend_from_slice(&chunk[..read]);
+        if input.len() as u64 > limit {
+            return Err(EllieError::InputTooLarge { limit }.into());
+        }
+        progress.update(format!(
+            "reading standard input ({size} KiB)",
+            size = input.len() / 1024
+        ));
+        if let (Some((models, min_completion_tokens)), Some(check)) = (fit, next_check) {
+            if input.len() > check {
+                create_user_message(
+                    models,
+                    &String::from_utf8_lossy(&input),
+                    min_completion_tokens,
+                )?;
+                next_check = Some(2 * input.len());
+            }
+        }
+    }
+    Ok(String::from_utf8(input)?)
+}
+
+/// Get the user input according to the given policy,
+/// either from the standard input
+/// (see [`read_input`])
+/// or composed in `$VISUAL` or `$EDITOR`.
+///
+/// # Errors
+/// If the standard input could not be read,
+/// if the editor failed,
+/// or if it was closed without saving.
+#[inline]
+fn get_input(
+    edit: EditInput,
+    limit: u64,
+    fit: Option<(&models::Models, usize)>,
+) -> color_eyre::eyre::Result<String> {
+    use std::io::IsTerminal as _;
+
+    let is_terminal = std::io::stdin().is_terminal();
+    let initial = match (edit, is_terminal) {
+        (EditInput::Never, _) | (EditInput::Auto, false) => return read_input(limit, fit),
+        (_, true) => String::new(),
+        // The input may get shorter while editing it.
+        (EditInput::Always, false) => read_input(limit, None)?,
+    };
+    // Function specifications may be loading meanwhile.
+    let _progress = progress::suppress();
+    let input = dialoguer::Editor::new()
+        .extension(".md")
+        .edit(&initial)?
+        .ok_or_else(|| color_eyre::eyre::eyre!("editor closed without saving the prompt"))?;
+    if input.len() as u64 > limit {
+        return Err(EllieError::InputTooLarge { limit }.into());
+    }
+    Ok(input)
+}
+
+/// Run the given shell command with the given input,
+/// returning its output,
+/// which replaces the input.
+///
+/// # Errors
+/// If the command could not be run or exited unsuccessfully,
+/// so that unprocessed input is never sent.
+#[inline]
+fn preprocess(command: &str, input: String) -> Result<String, EllieError> {
+    let shell = if cfg!(windows) {
+        duct::cmd!("cmd", "/C", command)
+    } else {
+        duct::cmd!("sh", "-c", command)
+    };
+    shell
+        .stdin_bytes(input)
+        .read()
+        .map_err(|err| EllieError::PreCommand {
+            command: command.to_owned(),
+            source: err.into(),
+        })
+}
+
+/// Everything defining the assistant,
+/// loaded once and shared by all answers.
+#[derive(Debug)]
+struct Assistant {
+    models: models::Models,
+    toolset: functions::Toolset,
+
+    /// System prompt prepended to every conversation.
+    system: Option<String>,
+
+    temperature: f32,
+
+    /// Example exchanges inserted at the start of conversations.
+    examples: Vec<project::Example>,
+
+    /// Approves function calls that are not marked as safe.
+    approver: Box<dyn approval::Approver>,
+
+    /// Snippets referenced in prompts.
+    snippets: snippets::Snippets,
+
+    /// Patterns redacted from answers.
+    redactions: redact::Redactions,
+
+    /// Adaptive delay before requests,
+    /// if enabled.
+    throttle: Option<throttle::Throttle>,
+
+    /// Usage of all requests,
+    /// if reported.
+    stats: Option<stats::Stats>,
+
+    /// API keys used in turn instead of the default client,
+    /// if several are given.
+    keys: Option<keys::Keys>,
+}
+
+impl Assistant {
+    /// Load the assistant,
+    /// optionally defined by a project file.
+    ///
+    /// Provider commands are spawned while the standard input is read,
+    /// since loading functions does not depend on the input.
+    /// This function returns the assistant together with the input.
+    ///
+    /// # Errors
+    /// If the configuration could not be loaded,
+    /// or if the standard input could not be read.
+    #[inline]
+    async fn load(
+        options: &cli::Options,
+        timings: &timing::Timings,
+    ) -> color_eyre::eyre::Result<(Self, String)> {
+        use color_eyre::eyre::Context as _;
+
+        let project_file = options
+            .project
+            .as_deref()
+            .map(project::Project::load)
+            .transpose()?;
+        let project_path = options.project.clone();
+        let allowed_commands = options.allow_commands.clone();
+        let missing_command = options.missing_command;
+        let strict_names = options.strict_function_names;
+        let span = tracing::info_span!("spec loading");
+        let toolset = tokio::task::spawn_blocking(move || {
+            let _entered = span.entered();
+            let start = std::time::Instant::now();
+            let toolset = functions::Toolset::load(
+                project_path.as_deref(),
+                &allowed_commands,
+                missing_command,
+                strict_names,
+            );
+            (toolset, start.elapsed())
+        });
+        let (tier, allowed_models) = project_file.as_ref().map_or_else(
+            || (models::Tier::default(), &[][..]),
+            |project_file| (project_file.tier, &project_file.allowed_models[..]),
+        );
+        let models = models::Models::load()
+            .and_then(|models| models.with_tier(tier))
+            .and_then(|models| models.with_allowed(allowed_models))
+            .context("loading models")?;
+        let pre_command = options.pre_command.as_ref().or_else(|| {
+            project_file
+                .as_ref()
+                .and_then(|project_file| project_file.pre_command.as_ref())
+        });
+
+        let start = std::time::Instant::now();
+        // Only the whole input is a single user message,
+        // as it is given and untruncated.
+        let fit = (!options.batch
+            && !options.first_line_system
+            && pre_command.is_none()
+            && budget::get(&options.budgets, budget::Source::Input).is_none())
+        .then_some((&models, options.min_completion_tokens));
+        let input = tracing::info_span!("stdin read")
+            .in_scope(|| get_input(options.edit_input, options.max_input_bytes, fit))?;
+        timings.record_since("stdin read", start);
+        let input = match pre_command {
+            Some(pre_command) => {
+                let start = std::time::Instant::now();
+                let input = tracing::info_span!("preprocessing")
+                    .in_scope(|| preprocess(pre_command, input))?;
+                timings.record_since("preprocessing", start);
+                input
+            }
+            None => input,
+        };
+        let (toolset, duration) = toolset.await?;
+        timings.record("spec loading", duration);
+        let toolset = toolset.context("getting function specifications")?;
+        if let ToolChoice::Function(name) = &options.tool_choice {
+            color_eyre::eyre::ensure!(
+                toolset.specifications.iter().any(|spec| &spec.name == name),
+                "unknown function '{name}' in '--tool-choice'"
+            );
+        }
+
+        let project_file = project_file.unwrap_or_default();
+        let mut snippets = snippets::Snippets::load().context("loading snippets")?;
+        snippets.apply_budget(models.cheapest(), &options.budgets)?;
+        let patterns = options
+            .redactions
+            .iter()
+            .cloned()
+            .map(Ok)
+            .chain(project_file.redact.iter().map(|pattern| {
+                regex::Regex::new(pattern)
+                    .with_context(|| format!("compiling redaction pattern '{pattern}'"))
+            }))
+            .collect::<color_eyre::eyre::Result<_>>()?;
+        let redactions = redact::Redactions::new(patterns, options.redaction_placeholder.clone());
+        let approver = options.approval.approver(
+            &options.approved_functions,
+            options.approved_functions_file.as_deref(),
+            options.approval_timeout.map(|secs| {
+                (
+                    std::time::Duration::from_secs(secs),
+                    options.approval_timeout_action,
+                )
+            }),
+        )?;
+        let approver: Box<dyn approval::Approver> =
+            if options.approvals.is_empty() && options.approvals_file.is_none() {
+                approver
+            } else {
+                Box::new(approval::Scripted::load(
+                    &options.approvals,
+                    options.approvals_file.as_deref(),
+                    approver,
+                )?)
+            };
+        let assistant = Self {
+            models,
+            toolset,
+            system: project_file.system,
+            temperature: project_file.temperature.unwrap_or(TEMPERATURE),
+            examples: project_file.example,
+            approver,
+            snippets,
+            redactions,
+            throttle: options.adaptive_throttle.then(throttle::Throttle::default),
+            stats: options.stats.then(stats::Stats::default),
+            keys: keys::Keys::new(&options.api_keys, &options.api_base, options.key_rotation),
+        };
+        Ok((assistant, input))
+    }
+}
+
+/// Summarize the given messages into a system message,
+/// returning [`None`] if they fit no model.
+///
+/// # Errors
+/// If the summary could not be requested.
+#[inline]
+async fn summarize<C: async_openai::config::Config + Sync>(
+    client: &async_openai::Client<C>,
+    assistant: &Assistant,
+    messages: &[aot::ChatCompletionRequestMessage],
+    min_completion_tokens: usize,
+) -> color_eyre::eyre::Result<Option<aot::ChatCompletionRequestMessage>> {
+    use color_eyre::eyre::Context as _;
+
+    let summary_messages = vec![
+        aot::ChatCompletionRequestMessageArgs::default()
+            .role(aot::Role::System)
+            .content(SUMMARY_PROMPT)
+            .build()?,
+        aot::ChatCompletionRequestMessageArgs::default()
+            .role(aot::Role::User)
+            .content(sessions::to_markdown(messages)?)
+            .build()?,
+    ];
+    let Some(model) = choose_model(
+        &assistant.models,
+        &summary_messages,
+        min_completion_tokens,
+        false,
+    ) else {
+        return Ok(None);
+    };
+    log::info!(
+        "summarizing {count} messages with model '{name}'",
+        count = messages.len(),
+        name = model.name
+    );
+    let mut request = aot::CreateChatCompletionRequestArgs::default();
+    request.model(&model.name).messages(summary_messages);
+    if model.supports(models::Parameter::Temperature) {
+        request.temperature(TEMPERATURE);
+    }
+    let response = client
+        .chat()
+        .create(request.build()?)
+        .await
+        .context("summarizing messages")?;
+    let summary = response
+        .choices
+        .into_iter()
+        .find_map(|choice| choice.message.content)
+        .unwrap_or_default();
+    Ok(Some(
+        aot::ChatCompletionRequestMessageArgs::default()
+            .role(aot::Role::System)
+            .content(format!("Summary of the earlier conversation:\n\n{summary}"))
+            .build()?,
+    ))
+}
+
+/// Create an `OpenAI` request for the given history and new messages,
+/// handling conversations that fit no model according to `--on-overflow`.
+///
+/// Only the history is trimmed or summarized
+/// (oldest messages first, system messages excepted),
+/// a step at a time until the conversation fits,
+/// never separating function calls from their results.
+/// The given history itself is left untouched,
+/// so that saved sessions stay complete.
+///
+/// # Errors
+/// If a model could not be chosen even so,
+/// or a summary could not be requested.
+#[inline]
+async fn create_fitting_request<C: async_openai::config::Config + Sync>(
+    client: &async_openai::Client<C>,
+    options: &cli::Options,
+    assistant: &Assistant,
+    context: &[aot::ChatCompletionRequestMessage],
+    history: &[aot::ChatCompletionRequestMessage],
+    new_messages: &[aot::ChatCompletionRequestMessage],
+    smart: bool,
+) -> color_eyre::eyre::Result<aot::CreateChatCompletionRequest> {
+    let mut history = history.to_vec();
+    budget::trim_history(assistant.models.cheapest(), &options.budgets, &mut history)?;
+    loop {
+        let messages = create_chat_messages(context, &history, new_messages, options.function_role);
+        let oldest = history
+            .iter()
+            .position(|message| message.role != aot::Role::System);
+        let oldest = match (
+            create_request(assistant, messages, options.min_completion_tokens, smart),
+            oldest,
+        ) {
+            (Err(EllieError::NoModel), Some(oldest)) if options.on_overflow != Overflow::Error => {
+                oldest
+            }
+            (result, _) => return Ok(result?),
+        };
+
+        // Summarize the older half of the history,
+        // or less if even that fits no model.
+        let end = oldest
+            + ((history.len() - oldest) / 2)
+                .max(2)
+                .min(history.len() - oldest);
+        // Function calls are summarized along with their results.
+        let mut end = budget::skip_results(&history, end);
+        let mut summary = None;
+        while options.on_overflow == Overflow::Summarize && end - oldest >= 2 {
+            summary = summarize(
+                client,
+                assistant,
+                &history[oldest..end],
+                options.min_completion_tokens,
+            )
+            .await?;
+            if summary.is_some() {
+                break;
+            }
+            let shorter = budget::skip_results(&history, oldest + (end - oldest) / 2);
+            if shorter >= end {
+                break;
+            }
+            end = shorter;
+        }
+        match summary {
+            Some(summary) => {
+                log::warn!(
+                    "summarized the {count} oldest messages to fit the context",
+                    count = end - oldest
+                );
+                history.splice(oldest..end, [summary]);
+            }
+            None => {
+                let count = budget::drop_message(&mut history, oldest);
+                log::warn!("dropped the {count} oldest messages to fit the context");
+            }
+        }
+    }
 }
 
 /// Create an `OpenAI` request.
 ///
 /// # Errors
-/// If a model could not be chosen for the given messages,
-/// or if functions could not be retrieved.
+/// If a model could not be chosen for the given messages.
 #[inline]
 fn create_request(
+    assistant: &Assistant,
     messages: Vec<aot::ChatCompletionRequestMessage>,
-) -> color_eyre::eyre::Result<aot::CreateChatCompletionRequest> {
-    use color_eyre::eyre::ContextCompat as _;
-
+    min_completion_tokens: usize,
+    smart: bool,
+) -> Result<aot::CreateChatCompletionRequest, EllieError> {
     let mut request = aot::CreateChatCompletionRequestArgs::default();
-    request.temperature(TEMPERATURE);
 
-    let model = choose_model(&messages)
-        .context("choosing model with large enough context length for the given messages")?;
-    log::info!("model '{model}'");
-    request.model(model);
+    let model = choose_model(&assistant.models, &messages, min_completion_tokens, smart)
+        .ok_or(EllieError::NoModel)?;
+    log::info!("model '{name}'", name = model.name);
+    model.warn_if_deprecated();
+    request.model(&model.name);
 
-    let functions = functions::Functions::load()
-        .unwrap_or_default()
-        .specifications()
-        .collect::<Result<Vec<_>, _>>()?;
-    if !functions.is_empty() {
-        request.functions(functions);
+    if model.supports(models::Parameter::Temperature) {
+        request.temperature(assistant.temperature);
+    } else if assistant.temperature != TEMPERATURE {
+        log::warn!(
+            "model '{name}' does not support temperature, omitting it",
+            name = model.name
+        );
+    }
+
+    let specifications = &assistant.toolset.specifications;
+    if !specifications.is_empty() {
+        if model.supports(models::Parameter::Functions) {
+            request.functions(specifications.clone());
+        } else {
+            log::warn!(
+                "model '{name}' does not support functions, omitting them",
+                name = model.name
+            );
+        }
     }
     Ok(request.messages(messages).build()?)
 }
 
+/// Get a `curl` command sending the given request,
+/// as ellie would,
+/// to reproduce it outside ellie.
+///
+/// The API key is read from `OPENAI_API_KEY` by the shell,
+/// so that it never appears in the command.
+#[inline]
+fn curl_command(api_base: &str, request: &aot::CreateChatCompletionRequest) -> String {
+    let mut request = request.clone();
+    request.stream = Some(true);
+    let body =
+        serde_json::to_string(&request).expect("serialization of requests should never fail");
+    format!(
+        concat!(
+            "curl -N {url} \\\n",
+            "  -H 'Content-Type: application/json' \\\n",
+            "  -H \"Authorization: Bearer $OPENAI_API_KEY\" \\\n",
+            "  -d {body}",
+        ),
+        url = shell_quote(&format!("{api_base}/chat/completions")),
+        body = shell_quote(&body)
+    )
+}
+
+/// Quote the given text for POSIX shells.
+#[inline]
+fn shell_quote(text: &str) -> String {
+    format!("'{text}'", text = text.replace('\'', r"'\''"))
+}
+
 #[inline]
 async fn create_response<C: async_openai::config::Config + Sync>(
     client: &async_openai::Client<C>,
@@ -140,98 +1026,439 @@ async fn create_response<C: async_openai::config::Config + Sync>(
     client.chat().create_stream(request).await
 }
 
+/// How a response stream is turned into an assistant message.
+#[derive(Clone, Copy, Debug)]
+struct StreamPolicy<'a> {
+    /// How function arguments are passed on to providers.
+    arguments_format: functions::ArgumentsFormat,
+
+    /// Whether a truncated answer is going to be continued.
+    continuing: bool,
+
+    /// Patterns redacted from the content as it streams.
+    redactions: &'a redact::Redactions,
+
+    /// Functions whose calls may start while their arguments stream,
+    /// with how calls are handled.
+    functions: Option<(&'a functions::Functions, functions::CallPolicy<'a>)>,
+}
+
+/// New parts of a streamed assistant message,
+/// from a single delta.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Delta {
+    /// Content,
+    /// already redacted.
+    content: Option<String>,
+    function_name: Option<String>,
+    function_arguments: Option<String>,
+    finish_reason: Option<String>,
+}
+
+/// Assistant message accumulated from the deltas of a response stream.
+#[derive(Debug, Default)]
+struct Accumulator {
+    /// Redacted content.
+    content: String,
+    function_name: String,
+    function_arguments: String,
+
+    /// Number of deltas with content or a function call.
+    chunks: usize,
+}
+
+impl Accumulator {
+    /// Accumulate a streamed delta,
+    /// redacting its content,
+    /// and get what it adds,
+    /// or nothing if it belongs to another choice.
+    ///
+    /// # Errors
+    /// If the delta is not from the assistant.
+    #[inline]
+    fn push(
+        &mut self,
+        choice: aot::ChatCompletionResponseStreamMessage,
+        redaction: &mut redact::Stream<'_>,
+    ) -> color_eyre::eyre::Result<Option<Delta>> {
+        let aot::ChatCompletionResponseStreamMessage {
+            index,
+            delta:
+                aot::ChatCompletionStreamResponseDelta {
+                    role,
+                    content,
+                    function_call,
+                },
+            finish_reason,
+        } = choice;
+        // Only one choice is requested,
+        // so deltas of any other are never mixed into it.
+        if index != 0 {
+            log::debug!("ignoring delta of unrequested choice {index}");
+            return Ok(None);
+        }
+        if let Some(role) = role {
+            color_eyre::eyre::ensure!(matches!(role, aot::Role::Assistant), "bad role '{role}'");
+        }
+        if content.is_some() || function_call.is_some() {
+            self.chunks += 1;
+        }
+        let content = content.map(|content| redaction.push(&content));
+        if let Some(content) = &content {
+            self.content.push_str(content);
+        }
+        let (function_name, function_arguments) = function_call.map_or((None, None), |call| {
+            let aot::FunctionCallStream { name, arguments } = call;
+            (name, arguments)
+        });
+        if let Some(name) = &function_name {
+            self.function_name.clone_from(name);
+        }
+        if let Some(arguments) = &function_arguments {
+            self.function_arguments.push_str(arguments);
+        }
+        Ok(Some(Delta {
+            content,
+            function_name,
+            function_arguments,
+            finish_reason,
+        }))
+    }
+}
+
+/// Create an assistant message out of a response stream,
+/// also returning whether it was truncated at the token limit.
+///
+/// If the answer is going to be continued,
+/// a truncated answer is neither terminated in the output nor trimmed,
+/// so that the continuation joins it seamlessly.
+///
+/// If functions are given and the requested function streams its arguments,
+/// its call is started as soon as the function name arrives,
+/// and returned as well.
 #[inline]
 async fn create_assistant_message(
     mut response: aot::ChatCompletionResponseStream,
-) -> color_eyre::eyre::Result<aot::ChatCompletionRequestMessage> {
-    use std::fmt::Write as _;
-
+    output: &mut output::Output,
+    StreamPolicy {
+        arguments_format,
+        continuing,
+        redactions,
+        functions,
+    }: StreamPolicy<'_>,
+    timings: &timing::Timings,
+    start: std::time::Instant,
+) -> color_eyre::eyre::Result<(
+    aot::ChatCompletionRequestMessage,
+    bool,
+    Option<functions::StreamingCall>,
+    Option<String>,
+)> {
     use color_eyre::eyre::Context as _;
     use futures::StreamExt as _;
-    use tokio::io::AsyncWriteExt as _;
 
-    let mut stdout = tokio::io::stdout();
-    let mut content_buffer = String::new();
-    let mut function_name = String::new();
-    let mut function_arguments_buffer = String::new();
+    let mut accumulator = Accumulator::default();
+    let mut redaction = redactions.stream();
+    let mut streaming_call = None;
+    let mut first_token = None;
+    let mut served_model = None;
     while let Some(result) = response.next().await {
         match result.context("receiving response chunk") {
-            Err(err) => color_eyre::eyre::bail!(err),
-            Ok(aot::CreateChatCompletionStreamResponse { choices, .. }) => {
-                for aot::ChatCompletionResponseStreamMessage {
-                    delta:
-                        aot::ChatCompletionStreamResponseDelta {
-                            role,
-                            content,
-                            function_call,
-                        },
-                    finish_reason,
-                    ..
-                } in choices
-                {
-                    if let Some(role) = role {
-                        color_eyre::eyre::ensure!(
-                            matches!(role, aot::Role::Assistant),
-                            "bad role '{role}'"
-                        );
-                    }
+            Err(err) => return Err(err),
+            Ok(aot::CreateChatCompletionStreamResponse { choices, model, .. }) => {
+                if first_token.is_none() {
+                    timings.record_since("time to first token", start);
+                    first_token = Some(std::time::Instant::now());
+                }
+                // The model snapshot is the same in every chunk.
+                if served_model.is_none() && !model.is_empty() {
+                    log::info!("served by model '{model}'");
+                    output.model(&model).await?;
+                    served_model = Some(model);
+                }
+                for choice in choices {
+                    let Some(Delta {
+                        content,
+                        function_name,
+                        function_arguments,
+                        finish_reason,
+                    }) = accumulator.push(choice, &mut redaction)?
+                    else {
+                        continue;
+                    };
                     if let Some(content) = content {
-                        stdout.write_all(content.as_ref()).await?;
-                        stdout.flush().await?;
-                        content_buffer.write_str(&content)?;
+                        output.content(&content).await?;
                     }
-                    if let Some(aot::FunctionCallStream { name, arguments }) = function_call {
-                        if let Some(name) = name {
-                            function_name = name;
-                        }
-                        if let Some(arguments) = arguments {
-                            function_arguments_buffer.write_str(&arguments)?;
+                    if function_name.is_some() {
+                        streaming_call = functions.and_then(|(functions, policy)| {
+                            functions.start_streaming(accumulator.function_name.trim(), policy)
+                        });
+                    }
+                    if let Some(arguments) = function_arguments {
+                        if let Some(call) = &mut streaming_call {
+                            call.write(&arguments);
                         }
                     }
                     if let Some(finish_reason) = finish_reason {
+                        let rest = redaction.finish();
+                        if !rest.is_empty() {
+                            output.content(&rest).await?;
+                            accumulator.content.push_str(&rest);
+                        }
+                        if let Some(first_token) = first_token {
+                            timings.record_since("streaming", first_token);
+                        }
+                        // Logged once the content is terminated,
+                        // so that it doesn't interleave with it.
+                        let chunks = accumulator.chunks;
+                        let received =
+                            || progress::log(format!("received {chunks} chunks ({finish_reason})"));
                         match finish_reason.as_ref() {
-                            "stop" | "length" => {
-                                stdout.write_all(b"\n").await?;
-                                stdout.flush().await?;
-                                stdout.shutdown().await?;
-                                return Ok(aot::ChatCompletionRequestMessageArgs::default()
+                            "length" if continuing => {
+                                received();
+                                let message = aot::ChatCompletionRequestMessageArgs::default()
+                                    .role(aot::Role::Assistant)
+                                    .content(accumulator.content)
+                                    .build()?;
+                                return Ok((message, true, None, served_model));
+                            }
+                            reason @ ("stop" | "length") => {
+                                output.finish(reason).await?;
+                                received();
+                                let message = aot::ChatCompletionRequestMessageArgs::default()
                                     .role(aot::Role::Assistant)
-                                    .content(content_buffer.trim())
-                                    .build()?);
+                                    .content(accumulator.content.trim())
+                                    .build()?;
+                                return Ok((message, reason == "length", None, served_model));
                             }
                             "function_call" => {
-                                let name = function_name.trim().to_owned();
+                                let name = accumulator.function_name.trim().to_owned();
                                 let arguments =
-                                    functions::try_compact_json(&function_arguments_buffer);
-                                return Ok(aot::ChatCompletionRequestMessageArgs::default()
+                                    arguments_format.apply(&accumulator.function_arguments);
+                                output
+                                    .function_call(&name, &redactions.apply(&arguments))
+                                    .await?;
+                                received();
+                                let message = aot::ChatCompletionRequestMessageArgs::default()
                                     .role(aot::Role::Assistant)
                                     .content("") // BUG: https://github.com/64bit/async-openai/issues/103#issue-1884273236
                                     .function_call(aot::FunctionCall { name, arguments })
-                                    .build()?);
+                                    .build()?;
+                                return Ok((message, false, streaming_call, served_model));
                             }
                             // https://platform.openai.com/docs/api-reference/chat/streaming#choices-finish_reason
-                            finish_reason => unreachable!("bad finish reason '{finish_reason}'"),
+                            finish_reason => {
+                                output.finish(finish_reason).await?;
+                                received();
+                                color_eyre::eyre::bail!(
+                                    "unexpected finish reason '{finish_reason}'"
+                                )
+                            }
                         }
                     }
                 }
             }
         }
     }
-    unreachable!("no finish reason")
+    color_eyre::eyre::bail!("response stream ended without a finish reason")
 }
 
+/// Write a cached assistant message to the output,
+/// redacted and as if it had been streamed,
+/// finishing it for the given reason unless it is to be continued.
+#[inline]
+async fn replay_assistant_message(
+    message: &aot::ChatCompletionRequestMessage,
+    finish_reason: Option<&str>,
+    redactions: &redact::Redactions,
+    output: &mut output::Output,
+) -> std::io::Result<()> {
+    match &message.function_call {
+        Some(aot::FunctionCall { name, arguments }) => {
+            output
+                .function_call(name, &redactions.apply(arguments))
+                .await?;
+        }
+        None => {
+            if let Some(content) = message
+                .content
+                .as_deref()
+                .filter(|content| !content.is_empty())
+            {
+                output.content(&redactions.apply(content)).await?;
+            }
+            if let Some(finish_reason) = finish_reason {
+                output.finish(finish_reason).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Send the given recorded request again,
+/// optionally to another model,
+/// streaming the new answer.
+///
+/// # Errors
+/// If the request could not be sent or its response received.
+#[inline]
+async fn replay_request<C: async_openai::config::Config + Sync>(
+    client: &async_openai::Client<C>,
+    options: &cli::Options,
+    mut request: aot::CreateChatCompletionRequest,
+    model: Option<String>,
+) -> color_eyre::eyre::Result<()> {
+    use color_eyre::eyre::Context as _;
+
+    if let Some(model) = model {
+        request.model = model;
+    }
+    log::info!("model '{model}'", model = request.model);
+    let timings = timing::Timings::default();
+    let mut output = output::Output::open(options)
+        .await
+        .context("opening the output")?;
+    let start = std::time::Instant::now();
+    let redactions = redact::Redactions::new(
+        options.redactions.clone(),
+        options.redaction_placeholder.clone(),
+    );
+    if options.dump_curl {
+        eprintln!("{}", curl_command(&options.api_base, &request));
+    }
+    let response = create_response(client, request).await?;
+    let (message, ..) = create_assistant_message(
+        response,
+        &mut output,
+        StreamPolicy {
+            arguments_format: options.arguments_format,
+            continuing: false,
+            redactions: &redactions,
+            functions: None,
+        },
+        &timings,
+        start,
+    )
+    .await
+    .context("creating assistant message")?;
+    if let (Some(aot::FunctionCall { name, arguments }), output::StreamFormat::Text) =
+        (&message.function_call, options.stream_format)
+    {
+        // Function calls are not executed,
+        // but they are the answer.
+        let arguments = redactions.apply(arguments);
+        output.content(&format!("{name}({arguments})")).await?;
+        output.finish("function_call").await?;
+    }
+    if options.time {
+        timings.report();
+    }
+    Ok(())
+}
+
+/// Check if the given error was caused by the API rate limiting requests.
+#[inline]
+fn is_rate_limited(err: &color_eyre::eyre::Report) -> bool {
+    use async_openai::error::OpenAIError;
+
+    err.chain()
+        .any(|err| match err.downcast_ref::<OpenAIError>() {
+            Some(OpenAIError::Reqwest(err)) => {
+                err.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS)
+            }
+            Some(OpenAIError::ApiError(err)) => err
+                .code
+                .as_ref()
+                .and_then(serde_json::Value::as_str)
+                .is_some_and(|code| code == "rate_limit_exceeded"),
+            // The API client only reports the status of streams as text.
+            Some(OpenAIError::StreamError(message)) => message.contains("Invalid status code: 429"),
+            _ => false,
+        })
+}
+
+/// Check if the given message is an assistant answer without any content.
+#[inline]
+fn is_empty_answer(message: &aot::ChatCompletionRequestMessage) -> bool {
+    matches!(
+        message,
+        aot::ChatCompletionRequestMessage {
+            role: aot::Role::Assistant,
+            content,
+            function_call: None,
+            ..
+        } if content.as_deref().map_or(true, |content| content.trim().is_empty())
+    )
+}
+
+/// Find the result of a previous call to the given function with the same
+/// arguments among the given messages,
+/// if any.
+///
+/// Arguments are compared as JSON values when possible,
+/// so that formatting differences do not matter.
+#[inline]
+fn find_previous_result<'m>(
+    messages: &'m [aot::ChatCompletionRequestMessage],
+    name: &str,
+    arguments: &str,
+) -> Option<&'m str> {
+    let parse = |arguments: &str| serde_json::from_str::<serde_json::Value>(arguments).ok();
+    let same_arguments = |other: &str| match (parse(arguments), parse(other)) {
+        (Some(arguments), Some(other)) => arguments == other,
+        _ => arguments == other,
+    };
+    // The first call is the one that was actually executed.
+    messages.windows(2).find_map(|pair| match pair {
+        [aot::ChatCompletionRequestMessage {
+            function_call: Some(call),
+            ..
+        }, result]
+            if result.role == aot::Role::Function
+                && call.name == name
+                && same_arguments(&call.arguments) =>
+        {
+            result.content.as_deref()
+        }
+        _ => None,
+    })
+}
+
+/// Push the given assistant message to the new messages,
+/// calling the requested function if any.
+///
+/// If `reuse_results` is set,
+/// a call identical to a previous one among the new messages is not
+/// executed again,
+/// and the previous result is given to the model instead
+/// (killing the given streaming call, if any).
+///
+/// If requirements are enforced,
+/// a call to a function whose required functions were not called earlier
+/// in the history or the new messages is rejected with an error for the
+/// model.
+///
+/// This function returns the response of the called function, if any.
 #[inline]
 fn update_new_messages(
+    functions: &functions::Functions,
+    history: &[aot::ChatCompletionRequestMessage],
     new_messages: &mut Vec<aot::ChatCompletionRequestMessage>,
     assistant_message: aot::ChatCompletionRequestMessage,
-) -> Result<(), either::Either<async_openai::error::OpenAIError, dialoguer::Error>> {
-    match assistant_message {
+    policy: functions::CallPolicy<'_>,
+    reuse_results: bool,
+    streaming_call: Option<functions::StreamingCall>,
+) -> Result<Option<functions::FunctionResponse>, EllieError> {
+    let response = match assistant_message {
         aot::ChatCompletionRequestMessage {
             role: aot::Role::Assistant,
             name: None,
             content: Some(_),
             function_call: None,
-        } => new_messages.push(assistant_message),
+        } => {
+            new_messages.push(assistant_message);
+            None
+        }
         aot::ChatCompletionRequestMessage {
             role: aot::Role::Assistant,
             name: None,
@@ -247,27 +1474,274 @@ fn update_new_messages(
                 // BUG: https://github.com/64bit/async-openai/issues/103#issue-1884273236
                 .is_some_and(|content| content.trim().is_empty()) =>
         {
-            let function_message = create_function_message(name, arguments)?;
+            let previous = reuse_results
+                .then(|| find_previous_result(new_messages, name, arguments))
+                .flatten();
+            let missing: Vec<_> = if policy.enforce_requires {
+                functions
+                    .requirements(name)
+                    .iter()
+                    // Only successful calls count,
+                    // not failed or rejected ones.
+                    .filter(|required| {
+                        !history.iter().chain(new_messages.iter()).any(|message| {
+                            message.role == aot::Role::Function
+                                && message.name.as_ref() == Some(*required)
+                                && message
+                                    .content
+                                    .as_deref()
+                                    .is_some_and(functions::FunctionResponse::executed)
+                        })
+                    })
+                    .map(String::as_str)
+                    .collect()
+            } else {
+                Vec::new()
+            };
+            let (function_message, response) = match previous {
+                _ if !missing.is_empty() => {
+                    let missing = missing.join(", ");
+                    log::warn!("{name}({arguments}) rejected: {missing} not called yet");
+                    let response = functions::FunctionResponse::Error {
+                        kind: "missing_prerequisite".to_owned(),
+                        message: format!("call {missing} before {name}"),
+                    };
+                    (
+                        aot::ChatCompletionRequestMessageArgs::default()
+                            .role(aot::Role::Function)
+                            .name(name)
+                            .content(response.to_string())
+                            .build()?,
+                        response,
+                    )
+                }
+                Some(previous) => {
+                    log::info!("{name}({arguments}) repeated, reusing its result");
+                    let response = functions::FunctionResponse::Repeated(previous.to_owned());
+                    (
+                        aot::ChatCompletionRequestMessageArgs::default()
+                            .role(aot::Role::Function)
+                            .name(name)
+                            .content(response.to_string())
+                            .build()?,
+                        response,
+                    )
+                }
+                None => {
+                    create_function_message(functions, name, arguments, policy, streaming_call)?
+                }
+            };
             new_messages.push(assistant_message);
             new_messages.push(function_message);
+            Some(response)
         }
         assistant_message => unreachable!("bad assistant message '{assistant_message:?}'"),
+    };
+    Ok(response)
+}
+
+/// Keep at most the given number of lines of the given text,
+/// marking how many were left out.
+#[inline]
+fn truncate_lines(text: &str, max_lines: Option<usize>) -> std::borrow::Cow<'_, str> {
+    let count = text.lines().count();
+    match max_lines {
+        Some(max_lines) if count > max_lines => {
+            use itertools::Itertools as _;
+
+            let kept = text.lines().take(max_lines).join("\n");
+            let more = count - max_lines;
+            if kept.is_empty() {
+                format!("… ({more} more lines)").into()
+            } else {
+                format!("{kept}\n… ({more} more lines)").into()
+            }
+        }
+        _ => text.into(),
     }
-    Ok(())
 }
 
-#[tokio::main]
-async fn main() -> color_eyre::eyre::Result<()> {
-    use color_eyre::eyre::Context as _;
+/// Print the function call requested in the given assistant message
+/// and its response (at most the given number of lines)
+/// to the standard error.
+#[inline]
+fn show_function_call(
+    assistant_message: &aot::ChatCompletionRequestMessage,
+    response: &functions::FunctionResponse,
+    max_lines: Option<usize>,
+) {
+    if let Some(aot::FunctionCall { name, arguments }) = &assistant_message.function_call {
+        eprintln!("{name}({arguments})");
+    }
+    for line in truncate_lines(&response.to_string(), max_lines).lines() {
+        eprintln!("  {line}");
+    }
+    if let functions::FunctionResponse::Executed {
+        metadata: Some(metadata),
+        ..
+    } = response
+    {
+        eprintln!("  metadata: {metadata}");
+    }
+}
 
-    pretty_env_logger::init();
-    color_eyre::install()?;
+/// Create an `OpenAI` client configuration for the given options.
+#[inline]
+fn create_config(options: &cli::Options) -> async_openai::config::OpenAIConfig {
+    async_openai::config::OpenAIConfig::new().with_api_base(&options.api_base)
+}
+
+/// Create an `OpenAI` client for the given options.
+#[inline]
+fn create_client(
+    options: &cli::Options,
+) -> async_openai::Client<async_openai::config::OpenAIConfig> {
+    async_openai::Client::with_config(create_config(options))
+}
 
-    let input = std::io::read_to_string(std::io::stdin().lock())?;
-    let user_message = create_user_message(&input)?;
-    let mut new_messages = vec![user_message];
+/// Split batch input into prompts at lines equal to the given delimiter,
+/// skipping empty prompts.
+///
+/// An empty delimiter splits at blank lines.
+#[inline]
+fn split_batch(input: &str, delimiter: &str) -> Vec<String> {
+    let mut prompts = vec![String::new()];
+    for line in input.lines() {
+        if line.trim() == delimiter.trim() {
+            prompts.push(String::new());
+        } else {
+            let prompt = prompts
+                .last_mut()
+                .expect("there should always be at least one prompt");
+            prompt.push_str(line);
+            prompt.push('\n');
+        }
+    }
+    prompts.retain(|prompt| !prompt.trim().is_empty());
+    prompts
+}
 
-    let client = async_openai::Client::new();
+/// Split the first line off the given input as a system prompt,
+/// unless the input has a single line,
+/// in which case it is entirely the user prompt.
+#[inline]
+fn split_system_line(input: &str) -> (Option<&str>, &str) {
+    match input.trim().split_once('\n') {
+        Some((system, user)) if !user.trim().is_empty() => (Some(system.trim()), user),
+        _ => (None, input),
+    }
+}
+
+/// Answer a single prompt following the given conversation history,
+/// calling functions as requested by the model.
+///
+/// New messages are appended to the history once the answer is complete.
+/// This function returns whether any function call was aborted by the user.
+#[inline]
+async fn answer<C: async_openai::config::Config + Sync>(
+    client: &async_openai::Client<C>,
+    options: &cli::Options,
+    assistant: &Assistant,
+    history: &mut Vec<aot::ChatCompletionRequestMessage>,
+    output: &mut output::Output,
+    input: &str,
+    timings: &timing::Timings,
+) -> color_eyre::eyre::Result<bool> {
+    use color_eyre::eyre::Context as _;
+    use tracing::Instrument as _;
+
+    let input = assistant.snippets.expand(input)?;
+    let input = input.as_str();
+    let (system, input) = if options.first_line_system {
+        split_system_line(input)
+    } else {
+        (None, input)
+    };
+    let cheapest = assistant.models.cheapest();
+    let input = budget::truncate(cheapest, &options.budgets, budget::Source::Input, input)?;
+    let user_message =
+        create_user_message(&assistant.models, input, options.min_completion_tokens)?;
+    // Context is sent with every request of this answer,
+    // but never kept in the history,
+    // so that it is current in every answer and never repeated.
+    let mut context = Vec::new();
+    for system in assistant.system.as_deref().into_iter().chain(system) {
+        context.push(
+            aot::ChatCompletionRequestMessageArgs::default()
+                .role(aot::Role::System)
+                .content(system)
+                .build()?,
+        );
+    }
+    if options.describe_functions {
+        context.extend(create_functions_message(
+            &assistant.toolset.functions,
+            &assistant.toolset.specifications,
+        )?);
+    }
+    if options.context_env {
+        context.push(create_environment_message()?);
+    }
+    if options.with_time {
+        context.push(create_time_message(&options.time_format, options.timezone)?);
+    }
+    if options.last_output {
+        context.extend(create_last_output_message(
+            options.last_output_file.as_deref(),
+        )?);
+    }
+    for message in &mut context {
+        if let Some(content) = &mut message.content {
+            let end =
+                budget::truncate(cheapest, &options.budgets, budget::Source::System, content)?
+                    .len();
+            content.truncate(end);
+        }
+    }
+    let mut new_messages = Vec::new();
+    if history.is_empty() {
+        // Examples stay in the history for follow-up answers.
+        for project::Example { user, assistant } in &assistant.examples {
+            new_messages.push(
+                aot::ChatCompletionRequestMessageArgs::default()
+                    .role(aot::Role::User)
+                    .content(user)
+                    .build()?,
+            );
+            new_messages.push(
+                aot::ChatCompletionRequestMessageArgs::default()
+                    .role(aot::Role::Assistant)
+                    .content(assistant)
+                    .build()?,
+            );
+        }
+    }
+    new_messages.push(user_message);
+
+    // Only deterministic requests are worth caching.
+    let cache = (options.cache && assistant.temperature == 0.0)
+        .then(|| cache::Responses::new(options.cache_ttl, options.refresh))
+        .transpose()?;
+    let mut round = 0;
+    let mut continuations = 0;
+    let mut continued_from = None;
+    let mut retried_empty = false;
+    let mut aborted = false;
+    let log = options.log_file.as_ref().map(audit::Log::new);
+    // Model and token usage reported to the completion command.
+    let mut model = String::new();
+    let mut usage = audit::Usage::default();
+    let policy = functions::CallPolicy {
+        approver: assistant.approver.as_ref(),
+        approve_empty_args: options.no_approval_on_empty_args,
+        simulate: options.simulate_functions,
+        strip_ansi: options.strip_ansi,
+        quiet: options.quiet_functions,
+        pretty_json: options.pretty_json_results,
+        retry_guidance: options.retry_guidance,
+        stderr: options.provider_stderr,
+        enforce_requires: options.enforce_requires,
+    };
     while !matches!(
         new_messages
             .iter()
@@ -276,14 +1750,999 @@ async fn main() -> color_eyre::eyre::Result<()> {
             .role,
         aot::Role::Assistant
     ) {
-        let messages = create_chat_messages(&new_messages);
-        let request = create_request(messages)?;
-        let response = create_response(&client, request).await?;
-        let assistant_message = create_assistant_message(response)
-            .await
-            .context("creating assistant message")?;
+        let mut request = create_fitting_request(
+            client,
+            options,
+            assistant,
+            &context,
+            history,
+            &new_messages,
+            options.model_policy.prefers_smart(round),
+        )
+        .await?;
+        if request.functions.is_some() {
+            request.function_call = options.tool_choice.function_call(round == 0);
+        }
+        let chosen = assistant
+            .models
+            .iter()
+            .find(|model| model.name == request.model)
+            .expect("requests should always be made to a known model");
+        if let Some(max_tokens) = options.max_tokens {
+            let max_tokens = max_tokens.resolve(chosen, &request)?;
+            log::info!("at most {max_tokens} completion tokens");
+            request.max_tokens = Some(max_tokens);
+        }
+        round += 1;
+        let logged_request = log.is_some().then(|| request.clone());
+        let continuing = options.auto_continue && continuations < MAX_CONTINUATIONS;
+        let cached = cache.as_ref().and_then(|cache| cache.get(&request));
+        let (assistant_message, truncated, streaming_call, served_model) =
+            if let Some((assistant_message, finish_reason)) = cached {
+                progress::log(format!(
+                    "round {round}: cached response of model '{model}'",
+                    model = request.model
+                ));
+                log::info!("cached response ({finish_reason})");
+                let truncated = finish_reason == "length";
+                replay_assistant_message(
+                    &assistant_message,
+                    Some(finish_reason.as_str()).filter(|_| !(truncated && continuing)),
+                    &assistant.redactions,
+                    output,
+                )
+                .await?;
+                (assistant_message, truncated, None, None)
+            } else {
+                if options.dump_curl {
+                    eprintln!("{}", curl_command(&options.api_base, &request));
+                }
+                progress::log(format!(
+                    "round {round}: request sent to model '{model}'",
+                    model = request.model
+                ));
+                let mut retries = 0;
+                // Keys rate limited since the last backoff.
+                let mut limited_keys = 0;
+                let (assistant_message, truncated, streaming_call, served_model) = loop {
+                    if let Some(throttle) = &assistant.throttle {
+                        throttle.wait().await;
+                    }
+                    let start = std::time::Instant::now();
+                    let span = tracing::info_span!(
+                        "request",
+                        model = %request.model,
+                        error = tracing::field::Empty
+                    );
+                    let attempt = async {
+                        let (key, response) = match (&assistant.keys, &assistant.throttle) {
+                            (Some(keys), Some(throttle)) => {
+                                let (key, _) = keys.next();
+                                let response = throttle
+                                    .create_stream(keys.config(key), request.clone())
+                                    .await?;
+                                (Some(key), response)
+                            }
+                            (Some(keys), None) => {
+                                let (key, client) = keys.next();
+                                (Some(key), create_response(client, request.clone()).await?)
+                            }
+                            (None, Some(throttle)) => {
+                                let config = create_config(options);
+                                (
+                                    None,
+                                    throttle.create_stream(&config, request.clone()).await?,
+                                )
+                            }
+                            (None, None) => (None, create_response(client, request.clone()).await?),
+                        };
+                        let message = create_assistant_message(
+                            response,
+                            output,
+                            StreamPolicy {
+                                arguments_format: options.arguments_format,
+                                continuing,
+                                redactions: &assistant.redactions,
+                                functions: Some((&assistant.toolset.functions, policy)),
+                            },
+                            timings,
+                            start,
+                        )
+                        .await;
+                        color_eyre::eyre::Ok((key, message))
+                    }
+                    .instrument(span.clone())
+                    .await;
+                    let (key, message) = attempt.map_err(|err| {
+                        span.record("error", format!("{err:#}").as_str());
+                        err
+                    })?;
+                    if let Err(err) = &message {
+                        span.record("error", format!("{err:#}").as_str());
+                    }
+                    drop(span);
+                    match message {
+                        Err(err) if retries < MAX_RATE_LIMIT_RETRIES && is_rate_limited(&err) => {
+                            if let (Some(keys), Some(key)) = (&assistant.keys, key) {
+                                keys.rate_limited(key);
+                                limited_keys += 1;
+                                if limited_keys < keys.len() {
+                                    // Another key may not be rate limited yet.
+                                    continue;
+                                }
+                                limited_keys = 0;
+                            }
+                            if let Some(throttle) = &assistant.throttle {
+                                // The next request waits for the throttle.
+                                throttle.rate_limited();
+                            } else {
+                                let delay = std::time::Duration::from_secs(1 << retries);
+                                log::warn!("rate limited, retrying in {delay:?}");
+                                tokio::time::sleep(delay).await;
+                            }
+                            retries += 1;
+                        }
+                        result => {
+                            if let (Ok(_), Some(throttle)) = (&result, &assistant.throttle) {
+                                throttle.succeeded();
+                            }
+                            break result.context("creating assistant message")?;
+                        }
+                    }
+                };
+                if let Some(cache) = &cache {
+                    let finish_reason = if assistant_message.function_call.is_some() {
+                        "function_call"
+                    } else if truncated {
+                        "length"
+                    } else {
+                        "stop"
+                    };
+                    if let Err(err) = cache.put(&request, &assistant_message, finish_reason) {
+                        log::warn!("could not cache response: {err}");
+                    }
+                }
+                (assistant_message, truncated, streaming_call, served_model)
+            };
 
-        update_new_messages(&mut new_messages, assistant_message)?;
+        let length = new_messages.len();
+        let mut permit = None;
+        if let Some(aot::FunctionCall { name, .. }) = &assistant_message.function_call {
+            // Streaming calls already hold a permit.
+            if streaming_call.is_none() {
+                permit = Some(functions::permit().await);
+            }
+            progress::log(format!("calling function {name}"));
+        }
+        let span = assistant_message
+            .function_call
+            .as_ref()
+            .map_or_else(tracing::Span::none, |call| {
+                tracing::info_span!("function", name = %call.name, error = tracing::field::Empty)
+            });
+        let start = std::time::Instant::now();
+        let response = span.in_scope(|| {
+            update_new_messages(
+                &assistant.toolset.functions,
+                history,
+                &mut new_messages,
+                assistant_message,
+                policy,
+                options.reuse_function_results,
+                streaming_call,
+            )
+        })?;
+        drop(permit);
+        if let Some(aot::FunctionCall { name, .. }) = &new_messages[length].function_call {
+            let failure = response
+                .as_ref()
+                .and_then(functions::FunctionResponse::failure);
+            progress::log(format!(
+                "function {name} {outcome} after {elapsed:.1?}",
+                outcome = failure.as_deref().map_or_else(
+                    || "done".to_owned(),
+                    |failure| format!("failed ({failure})")
+                ),
+                elapsed = start.elapsed()
+            ));
+            if let Some(failure) = &failure {
+                span.record("error", failure.as_str());
+            }
+            timings.record_since(format!("function {name}"), start);
+        }
+        aborted |= matches!(response, Some(functions::FunctionResponse::Aborted));
+        if let Some(response) = &response {
+            if let Some(aot::FunctionCall { name, arguments }) = &new_messages[length].function_call
+            {
+                output.function_result(name, response).await?;
+                log::info!(
+                    "{name}({arguments}): {response}",
+                    response = truncate_lines(&response.to_string(), options.function_output_lines)
+                );
+            }
+            if options.show_functions {
+                show_function_call(
+                    &new_messages[length],
+                    response,
+                    options.function_output_lines,
+                );
+            }
+        }
+        if options.on_complete.is_some() || assistant.stats.is_some() {
+            match audit::Usage::new(chosen, &request, &new_messages[length]) {
+                Ok(round_usage) => {
+                    if let Some(stats) = &assistant.stats {
+                        stats.record(&request.model, &round_usage, chosen.price);
+                    }
+                    usage += round_usage;
+                }
+                Err(err) => log::warn!("could not count tokens: {err}"),
+            }
+            model = served_model
+                .clone()
+                .unwrap_or_else(|| request.model.clone());
+        }
+        if let (Some(log), Some(request)) = (&log, logged_request) {
+            log.record(
+                chosen,
+                &request,
+                served_model.as_deref(),
+                &new_messages[length],
+                new_messages.get(length + 1),
+            )
+            .context("writing log file")?;
+        }
+        if options.fail_fast_functions {
+            if let (Some(reason), Some(aot::FunctionCall { name, .. })) = (
+                response
+                    .as_ref()
+                    .and_then(functions::FunctionResponse::failure),
+                &new_messages[length].function_call,
+            ) {
+                return Err(EllieError::FunctionFailed {
+                    name: name.clone(),
+                    reason,
+                }
+                .into());
+            }
+        }
+
+        if truncated && continuing {
+            log::info!("answer truncated, continuing");
+            continuations += 1;
+            continued_from.get_or_insert(length);
+            new_messages.push(
+                aot::ChatCompletionRequestMessageArgs::default()
+                    .role(aot::Role::User)
+                    .content(CONTINUE_NUDGE)
+                    .build()?,
+            );
+            continue;
+        }
+        // Continuations are only joined if no function was called meanwhile.
+        if let Some(start) = continued_from.take().filter(|&start| {
+            new_messages[start..]
+                .iter()
+                .all(|message| message.function_call.is_none() && message.name.is_none())
+        }) {
+            let content: String = new_messages
+                .drain(start..)
+                .filter(|message| matches!(message.role, aot::Role::Assistant))
+                .filter_map(|message| message.content)
+                .collect();
+            new_messages.push(
+                aot::ChatCompletionRequestMessageArgs::default()
+                    .role(aot::Role::Assistant)
+                    .content(content.trim())
+                    .build()?,
+            );
+        }
+
+        let last_message = new_messages
+            .last()
+            .expect("there should always be at least one new message");
+        if is_empty_answer(last_message) {
+            if options.retry_empty && !retried_empty {
+                log::warn!("model returned an empty answer, retrying");
+                retried_empty = true;
+                new_messages.push(
+                    aot::ChatCompletionRequestMessageArgs::default()
+                        .role(aot::Role::User)
+                        .content(EMPTY_ANSWER_NUDGE)
+                        .build()?,
+                );
+            } else {
+                log::warn!("the model returned an empty answer");
+            }
+        }
+    }
+    output
+        .answer(
+            new_messages
+                .last()
+                .filter(|message| message.role == aot::Role::Assistant)
+                .and_then(|message| message.content.as_deref())
+                .unwrap_or_default(),
+        )
+        .await?;
+    if options.copy {
+        if let Some(content) = new_messages
+            .last()
+            .and_then(|message| message.content.as_deref())
+        {
+            copy_to_clipboard(content);
+        }
+    }
+    if let Some(command) = &options.on_complete {
+        let content = new_messages
+            .last()
+            .and_then(|message| message.content.as_deref())
+            .unwrap_or_default();
+        run_completion_command(command, content, &model, &usage);
+    }
+    history.append(&mut new_messages);
+    Ok(aborted)
+}
+
+/// Run the given shell command with the final answer on its standard input,
+/// and the model and token usage in its environment,
+/// warning if it fails.
+///
+/// Its standard output goes to the standard error,
+/// so that it never mixes with answers.
+#[inline]
+fn run_completion_command(command: &str, content: &str, model: &str, usage: &audit::Usage) {
+    let shell = if cfg!(windows) {
+        duct::cmd!("cmd", "/C", command)
+    } else {
+        duct::cmd!("sh", "-c", command)
+    };
+    let result = shell
+        .stdin_bytes(content)
+        .stdout_to_stderr()
+        .env("ELLIE_MODEL", model)
+        .env("ELLIE_PROMPT_TOKENS", usage.prompt_tokens.to_string())
+        .env(
+            "ELLIE_COMPLETION_TOKENS",
+            usage.completion_tokens.to_string(),
+        )
+        .env("ELLIE_TOTAL_TOKENS", usage.total_tokens.to_string())
+        .run();
+    match result {
+        Ok(_) => log::info!("completion command '{command}' succeeded"),
+        Err(err) => log::warn!("completion command '{command}' failed: {err}"),
+    }
+}
+
+/// Copy the given text to the system clipboard,
+/// warning if there is none (e.g., in headless environments).
+#[inline]
+fn copy_to_clipboard(text: &str) {
+    match set_clipboard(text) {
+        Ok(()) => log::info!("answer copied to the clipboard"),
+        Err(err) => log::warn!("could not copy the answer to the clipboard: {err}"),
+    }
+}
+
+/// Set the text of the system clipboard.
+#[cfg(not(target_os = "linux"))]
+#[inline]
+fn set_clipboard(text: &str) -> color_eyre::eyre::Result<()> {
+    Ok(arboard::Clipboard::new()?.set_text(text)?)
+}
+
+/// Set the text of the system clipboard
+/// from a background `hold-clipboard` process,
+/// since the clipboard is only served while its owner runs,
+/// and ellie may exit right after.
+#[cfg(target_os = "linux")]
+#[inline]
+fn set_clipboard(text: &str) -> color_eyre::eyre::Result<()> {
+    use std::{
+        io::{BufRead as _, Write as _},
+        os::unix::process::CommandExt as _,
+    };
+
+    let mut holder = std::process::Command::new(std::env::current_exe()?)
+        .arg("hold-clipboard")
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        // Interrupting ellie must not empty the clipboard.
+        .process_group(0)
+        .spawn()?;
+    holder
+        .stdin
+        .take()
+        .expect("holder standard input should be piped")
+        .write_all(text.as_bytes())?;
+    let mut status = String::new();
+    std::io::BufReader::new(
+        holder
+            .stdout
+            .take()
+            .expect("holder standard output should be piped"),
+    )
+    .read_line(&mut status)?;
+    match status.trim_end() {
+        "ok" => Ok(()),
+        "" => Err(color_eyre::eyre::eyre!("clipboard holder exited")),
+        err => Err(color_eyre::eyre::eyre!("{err}")),
+    }
+}
+
+/// Put the standard input in the clipboard
+/// and keep serving it until another program replaces it,
+/// reporting `ok` on the standard output once the clipboard is reached
+/// (or the error otherwise).
+#[cfg(target_os = "linux")]
+#[inline]
+fn hold_clipboard() -> color_eyre::eyre::Result<()> {
+    use arboard::SetExtLinux as _;
+    use std::io::Read as _;
+
+    let mut text = String::new();
+    std::io::stdin().read_to_string(&mut text)?;
+    let mut clipboard = match arboard::Clipboard::new() {
+        Ok(clipboard) => clipboard,
+        Err(err) => {
+            println!("{err}");
+            return Ok(());
+        }
+    };
+    println!("ok");
+    Ok(clipboard.set().wait().text(text)?)
+}
+
+/// Run the given future,
+/// failing if it has not completed by the deadline,
+/// if any.
+///
+/// Errors after the deadline
+/// (e.g., from a provider command killed because of it)
+/// are reported as the deadline being exceeded.
+#[inline]
+async fn until<T>(
+    deadline: Option<std::time::Instant>,
+    future: impl std::future::Future<Output = color_eyre::eyre::Result<T>>,
+) -> color_eyre::eyre::Result<T> {
+    let Some(deadline) = deadline else {
+        return future.await;
+    };
+    match tokio::time::timeout_at(deadline.into(), future).await {
+        Ok(Err(err)) if std::time::Instant::now() >= deadline => {
+            Err(err.wrap_err(EllieError::DeadlineExceeded))
+        }
+        Ok(result) => result,
+        Err(_) => Err(EllieError::DeadlineExceeded.into()),
+    }
+}
+
+/// Run the given future,
+/// failing if the run is interrupted first.
+#[inline]
+async fn unless_interrupted<T>(
+    future: impl std::future::Future<Output = color_eyre::eyre::Result<T>>,
+) -> color_eyre::eyre::Result<T> {
+    let future = std::pin::pin!(future);
+    let interrupted = std::pin::pin!(functions::interrupted());
+    match futures::future::select(future, interrupted).await {
+        futures::future::Either::Left((result, _)) => result,
+        futures::future::Either::Right(((), _)) => Err(EllieError::Interrupted.into()),
+    }
+}
+
+/// Answer the user input,
+/// exporting the phases of the run as spans if a collector is configured,
+/// whether it succeeded or not.
+#[inline]
+async fn chat<C: async_openai::config::Config + Sync>(
+    client: &async_openai::Client<C>,
+    options: &cli::Options,
+) -> color_eyre::eyre::Result<()> {
+    use tracing::Instrument as _;
+
+    let telemetry = telemetry::Telemetry::install();
+    let timings = timing::Timings::default();
+    let span = tracing::info_span!("ellie", error = tracing::field::Empty);
+    let result = unless_interrupted(converse(client, options, &timings))
+        .instrument(span.clone())
+        .await;
+    if let Err(err) = &result {
+        span.record("error", format!("{err:#}").as_str());
+    }
+    drop(span);
+    if let Some(telemetry) = telemetry {
+        telemetry.export().await;
+    }
+    result
+}
+
+/// Answer the standard input,
+/// either as a single prompt or as a batch of independent prompts.
+///
+/// # Errors
+/// If answering failed,
+/// or with [`EllieError::FunctionAborted`] once all answers are
+/// complete if any function call was aborted by the user.
+#[inline]
+async fn converse<C: async_openai::config::Config + Sync>(
+    client: &async_openai::Client<C>,
+    options: &cli::Options,
+    timings: &timing::Timings,
+) -> color_eyre::eyre::Result<()> {
+    use color_eyre::eyre::Context as _;
+    use futures::StreamExt as _;
+
+    let deadline = options.deadline.map(|secs| {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(secs);
+        functions::set_deadline(deadline);
+        deadline
+    });
+    functions::set_concurrency(options.tool_concurrency.into());
+    if options.progress_log {
+        progress::enable_log();
+    }
+    let (assistant, input) = until(deadline, Assistant::load(options, timings)).await?;
+    let assistant = &assistant;
+    let mut output = output::Output::open(options)
+        .await
+        .context("opening the output")?;
+    let aborted = if options.batch {
+        let prompts = split_batch(&input, &options.delimiter);
+        until(deadline, async {
+            let mut aborted = false;
+            if options.concurrency > 1 {
+                // Answers are buffered so that they can be printed in order.
+                let mut answers = futures::stream::iter(prompts)
+                    .map(|prompt| async move {
+                        let mut output = output::Output::buffered(options);
+                        let aborted = answer(
+                            client,
+                            options,
+                            assistant,
+                            &mut Vec::new(),
+                            &mut output,
+                            &prompt,
+                            timings,
+                        )
+                        .await?;
+                        Ok::<_, color_eyre::eyre::Report>((output.into_bytes(), aborted))
+                    })
+                    .buffered(options.concurrency.into());
+                let mut index = 0;
+                while let Some(answer) = answers.next().await {
+                    if index > 0 {
+                        output.separator(&options.delimiter).await?;
+                    }
+                    let (answer, answer_aborted) = answer?;
+                    output.raw(&answer).await?;
+                    aborted |= answer_aborted;
+                    index += 1;
+                }
+            } else {
+                for (index, prompt) in prompts.into_iter().enumerate() {
+                    if index > 0 {
+                        output.separator(&options.delimiter).await?;
+                    }
+                    aborted |= answer(
+                        client,
+                        options,
+                        assistant,
+                        &mut Vec::new(),
+                        &mut output,
+                        &prompt,
+                        timings,
+                    )
+                    .await?;
+                }
+            }
+            Ok(aborted)
+        })
+        .await?
+    } else {
+        let session = options
+            .session
+            .as_deref()
+            .map(sessions::Session::open)
+            .transpose()?;
+        let mut history = session
+            .as_ref()
+            .map(sessions::Session::messages)
+            .transpose()?
+            .unwrap_or_default();
+        let mut input = input;
+        if let Some(unanswered) = sessions::take_unanswered(&mut history) {
+            use std::io::IsTerminal as _;
+
+            if input.trim().is_empty() {
+                eprintln!("re-sending the unanswered message of the session");
+                input = unanswered;
+            } else if !std::io::stderr().is_terminal()
+                || dialoguer::Confirm::with_theme(&dialoguer::theme::ColorfulTheme::default())
+                    .with_prompt("Re-send the unanswered message of the session along?")
+                    .default(true)
+                    .interact()
+                    .map_err(EllieError::Prompt)?
+            {
+                // Without a terminal to ask,
+                // the prompt is kept rather than silently lost.
+                log::warn!("re-sending the unanswered message of the session along");
+                history.push(
+                    aot::ChatCompletionRequestMessageArgs::default()
+                        .role(aot::Role::User)
+                        .content(unanswered)
+                        .build()?,
+                );
+            }
+        }
+        if let Some(session) = &session {
+            // The prompt is kept until answered,
+            // so that it can be re-sent if ellie is interrupted.
+            let mut pending = history.clone();
+            pending.push(
+                aot::ChatCompletionRequestMessageArgs::default()
+                    .role(aot::Role::User)
+                    .content(input.trim())
+                    .build()?,
+            );
+            session.save(&pending).context("saving session")?;
+        }
+        // An unfinished answer leaves the prompt unanswered in the session.
+        let aborted = until(
+            deadline,
+            answer(
+                client,
+                options,
+                assistant,
+                &mut history,
+                &mut output,
+                &input,
+                timings,
+            ),
+        )
+        .await?;
+        if let Some(session) = session {
+            session.save(&history).context("saving session")?;
+        }
+        aborted
+    };
+    if options.time {
+        timings.report();
+    }
+    if let Some(stats) = &assistant.stats {
+        stats.report();
+        output.stats(stats).await?;
+    }
+    if aborted {
+        return Err(EllieError::FunctionAborted.into());
     }
     Ok(())
 }
+
+/// Variables ignored in an implicit `.env` file,
+/// since they run commands,
+/// approve calls
+/// or send data elsewhere,
+/// and a `.env` file may come with any cloned repository.
+const UNTRUSTED_ENV_VARS: &[&str] = &[
+    "OPENAI_API_BASE",
+    "ELLIE_PRE_COMMAND",
+    "ELLIE_APPROVED_FUNCTIONS",
+    "ELLIE_OUTPUT_SOCKET",
+    "ELLIE_LAST_OUTPUT_FILE",
+];
+
+/// Load the `.env` file of the current directory,
+/// if any,
+/// except for untrusted variables (see [`UNTRUSTED_ENV_VARS`]).
+///
+/// Variables already set in the environment take precedence.
+///
+/// # Errors
+/// If the file could not be read or parsed.
+#[inline]
+fn load_implicit_env_file() -> Result<(), dotenvy::Error> {
+    let variables = match dotenvy::from_path_iter("./.env") {
+        Ok(variables) => variables,
+        Err(err) if err.not_found() => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    for variable in variables {
+        let (key, value) = variable?;
+        if UNTRUSTED_ENV_VARS.contains(&key.as_str()) {
+            log::warn!("ignoring '{key}' from '.env', pass '--env-file .env' to trust it");
+        } else if std::env::var_os(&key).is_none() {
+            std::env::set_var(key, value);
+        }
+    }
+    Ok(())
+}
+
+#[inline]
+async fn run() -> color_eyre::eyre::Result<()> {
+    use clap::Parser as _;
+    use color_eyre::eyre::Context as _;
+
+    let cli::Cli { command, options } = match cli::Cli::parse().options.env_file {
+        Some(path) => {
+            dotenvy::from_path(&path)
+                .with_context(|| format!("loading env file '{}'", path.display()))?;
+            // Parse again for options that read the environment.
+            cli::Cli::parse()
+        }
+        None => {
+            load_implicit_env_file().context("loading '.env'")?;
+            cli::Cli::parse()
+        }
+    };
+    let client = create_client(&options);
+    match command {
+        Some(cli::Command::Doctor) => doctor::run(&client, &options.api_base).await,
+        Some(cli::Command::Models) => Ok(models::list(&client).await?),
+        Some(cli::Command::Config {
+            command: cli::ConfigCommand::Show { format },
+        }) => config::show(&options, format),
+        Some(cli::Command::Cache {
+            command: cli::CacheCommand::Clear,
+        }) => cache::clear(),
+        Some(cli::Command::Sessions {
+            command: cli::SessionsCommand::List,
+        }) => sessions::list(),
+        Some(cli::Command::Sessions {
+            command: cli::SessionsCommand::Rm { name },
+        }) => sessions::remove(&name),
+        Some(cli::Command::Export { session, format }) => sessions::export(&session, format),
+        Some(cli::Command::Replay {
+            log_file,
+            entry,
+            model,
+        }) => {
+            let request = audit::Log::new(log_file).request(entry)?;
+            replay_request(&client, &options, request, model).await
+        }
+        #[cfg(target_os = "linux")]
+        Some(cli::Command::HoldClipboard) => hold_clipboard(),
+        None => chat(&client, &options).await,
+    }
+}
+
+#[tokio::main]
+async fn main() -> std::process::ExitCode {
+    pretty_env_logger::init();
+    if let Err(err) = color_eyre::install() {
+        eprintln!("Error: {err:?}");
+        return error::code::OTHER.into();
+    }
+
+    match run().await {
+        Ok(()) => std::process::ExitCode::SUCCESS,
+        // Downstream closed early: stop quietly like a well-mannered filter.
+        Err(err) if error::is_broken_pipe(&err) => std::process::ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("Error: {err:?}");
+            error::exit_code(&err).into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a streamed delta of the given choice.
+    fn choice(index: u32, delta: serde_json::Value) -> aot::ChatCompletionResponseStreamMessage {
+        serde_json::from_value(serde_json::json!({
+            "index": index,
+            "delta": delta,
+            "finish_reason": null,
+        }))
+        .expect("test deltas should be valid")
+    }
+
+    #[test]
+    fn interleaved_choices_are_not_mixed() {
+        let redactions = redact::Redactions::new(Vec::new(), String::new());
+        let mut redaction = redactions.stream();
+        let mut accumulator = Accumulator::default();
+        let choices = [
+            choice(
+                0,
+                serde_json::json!({"role": "assistant", "content": "Hel"}),
+            ),
+            choice(
+                1,
+                serde_json::json!({"role": "assistant", "content": "Bon"}),
+            ),
+            choice(1, serde_json::json!({"content": "jour"})),
+            choice(0, serde_json::json!({"content": "lo"})),
+            choice(
+                1,
+                serde_json::json!({"function_call": {"name": "other", "arguments": "{\"a\""}}),
+            ),
+            choice(
+                0,
+                serde_json::json!({"function_call": {"name": "get_current_weather", "arguments": "{\"location\""}}),
+            ),
+            choice(
+                1,
+                serde_json::json!({"function_call": {"arguments": ": 1}"}}),
+            ),
+            choice(
+                0,
+                serde_json::json!({"function_call": {"arguments": ": \"Boston\"}"}}),
+            ),
+        ];
+        let deltas: Vec<_> = choices
+            .into_iter()
+            .map(|choice| accumulator.push(choice, &mut redaction).unwrap())
+            .collect();
+
+        assert_eq!(deltas.iter().filter(|delta| delta.is_none()).count(), 4);
+        assert_eq!(
+            deltas[3],
+            Some(Delta {
+                content: Some("lo".to_owned()),
+                ..Delta::default()
+            })
+        );
+        assert_eq!(accumulator.content, "Hello");
+        assert_eq!(accumulator.function_name, "get_current_weather");
+        assert_eq!(accumulator.function_arguments, "{\"location\": \"Boston\"}");
+        assert_eq!(accumulator.chunks, 4);
+    }
+
+    #[test]
+    fn deltas_of_other_roles_are_rejected() {
+        let redactions = redact::Redactions::new(Vec::new(), String::new());
+        let mut redaction = redactions.stream();
+        let mut accumulator = Accumulator::default();
+        let delta = choice(0, serde_json::json!({"role": "user", "content": "hi"}));
+        assert!(accumulator.push(delta, &mut redaction).is_err());
+        assert!(accumulator.content.is_empty());
+    }
+
+    /// Build a function result with the given content.
+    fn function_result(name: &str, content: &str) -> aot::ChatCompletionRequestMessage {
+        aot::ChatCompletionRequestMessageArgs::default()
+            .role(aot::Role::Function)
+            .name(name)
+            .content(content)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn prerequisites_must_have_been_executed() {
+        let path = std::env::temp_dir().join(format!(
+            "ellie-requires-{pid}.toml",
+            pid = std::process::id()
+        ));
+        std::fs::write(
+            &path,
+            "[[provider]]\nname = \"checkout\"\ncommand = \"true\"\nrequires = [\"login\"]\n",
+        )
+        .unwrap();
+        let functions = functions::Functions::load_from(&path);
+        std::fs::remove_file(&path).unwrap();
+        let functions = functions.unwrap();
+        let policy = functions::CallPolicy {
+            approver: &approval::Approve,
+            approve_empty_args: false,
+            simulate: true,
+            strip_ansi: false,
+            quiet: false,
+            pretty_json: false,
+            retry_guidance: false,
+            stderr: functions::ProviderStderr::default(),
+            enforce_requires: true,
+        };
+        let checkout = |history: &[aot::ChatCompletionRequestMessage]| {
+            let call = aot::ChatCompletionRequestMessageArgs::default()
+                .role(aot::Role::Assistant)
+                .function_call(aot::FunctionCall {
+                    name: "checkout".to_owned(),
+                    arguments: "{}".to_owned(),
+                })
+                .build()
+                .unwrap();
+            update_new_messages(
+                &functions,
+                history,
+                &mut Vec::new(),
+                call,
+                policy,
+                false,
+                None,
+            )
+            .unwrap()
+        };
+
+        let failures = [
+            functions::FunctionResponse::Aborted,
+            functions::FunctionResponse::NotFound,
+            functions::FunctionResponse::Invalid("missing field".to_owned()),
+            functions::FunctionResponse::Simulated,
+            functions::FunctionResponse::Failed {
+                content: String::new(),
+                reason: "exit code 1".to_owned(),
+                guidance: true,
+            },
+            functions::FunctionResponse::Error {
+                kind: "missing_prerequisite".to_owned(),
+                message: "call session before login".to_owned(),
+            },
+        ];
+        for failure in failures {
+            let history = [function_result("login", &failure.to_string())];
+            assert!(
+                matches!(
+                    checkout(&history),
+                    Some(functions::FunctionResponse::Error { kind, .. })
+                        if kind == "missing_prerequisite"
+                ),
+                "{failure:?} should not satisfy the prerequisite"
+            );
+        }
+        let history = [function_result("login", "logged in")];
+        assert!(matches!(
+            checkout(&history),
+            Some(functions::FunctionResponse::Simulated)
+        ));
+    }
+
+    #[test]
+    fn rate_limits_are_recognized_through_context() {
+        use async_openai::error::OpenAIError;
+        use std::io::{Read as _, Write as _};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 429 Too Many Requests\r\ncontent-length: 0\r\n\r\n")
+                .unwrap();
+        });
+        let rejected = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(async {
+                reqwest::get(format!("http://{address}"))
+                    .await
+                    .unwrap()
+                    .error_for_status()
+                    .unwrap_err()
+            });
+        server.join().unwrap();
+        let report = |err: OpenAIError| {
+            color_eyre::eyre::Report::new(err).wrap_err("creating assistant message")
+        };
+        let api_error = |code: &str| -> async_openai::error::ApiError {
+            serde_json::from_value(serde_json::json!({
+                "message": "slow down",
+                "type": "requests",
+                "param": null,
+                "code": code,
+            }))
+            .unwrap()
+        };
+
+        assert!(is_rate_limited(&report(OpenAIError::Reqwest(rejected))));
+        assert!(is_rate_limited(&report(OpenAIError::ApiError(api_error(
+            "rate_limit_exceeded"
+        )))));
+        assert!(is_rate_limited(&report(OpenAIError::StreamError(
+            "Invalid status code: 429 Too Many Requests".to_owned()
+        ))));
+        assert!(!is_rate_limited(&report(OpenAIError::ApiError(api_error(
+            "invalid_api_key"
+        )))));
+        assert!(!is_rate_limited(&report(OpenAIError::StreamError(
+            "Invalid status code: 500 Internal Server Error".to_owned()
+        ))));
+    }
+}
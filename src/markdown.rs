@@ -0,0 +1,221 @@
+/// Bold text.
+const BOLD: (&str, &str) = ("\x1b[1m", "\x1b[22m");
+
+/// Inline code and code blocks.
+const CODE: (&str, &str) = ("\x1b[36m", "\x1b[39m");
+
+/// Headings.
+const HEADING: (&str, &str) = ("\x1b[1;4m", "\x1b[0m");
+
+/// Get the fence marker (e.g., "```") opening a code block on the given
+/// line,
+/// if any.
+#[inline]
+fn fence_marker(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    ['`', '~'].into_iter().find_map(|fence| {
+        let marker = &line[..line.len() - line.trim_start_matches(fence).len()];
+        (marker.len() >= 3).then_some(marker)
+    })
+}
+
+/// Render inline formatting (bold and code spans) of the given text.
+#[inline]
+fn inline(text: &str, rendered: &mut String) {
+    let mut bold = false;
+    let mut code = false;
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '`' {
+            code = !code;
+            rendered.push_str(if code { CODE.0 } else { CODE.1 });
+            rest = &rest[1..];
+        } else if !code && rest.starts_with("**") {
+            bold = !bold;
+            rendered.push_str(if bold { BOLD.0 } else { BOLD.1 });
+            rest = &rest[2..];
+        } else {
+            rendered.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    // Unbalanced markers must not leak formatting into later blocks.
+    if code {
+        rendered.push_str(CODE.1);
+    }
+    if bold {
+        rendered.push_str(BOLD.1);
+    }
+}
+
+/// Streaming Markdown renderer for the terminal.
+///
+/// Content is buffered only until a block boundary is reached
+/// (a blank line ends a paragraph,
+/// a closing fence ends a code block),
+/// so that output still appears progressively
+/// but half-received blocks are never rendered.
+#[derive(Debug, Default)]
+pub struct Renderer {
+    /// Incomplete last line.
+    pending: String,
+
+    /// Complete lines of the current block.
+    block: Vec<String>,
+
+    /// Marker of the open code block,
+    /// if any.
+    fence: Option<String>,
+}
+
+impl Renderer {
+    /// Add a chunk of content,
+    /// returning whatever blocks it completed, rendered.
+    #[inline]
+    pub fn push(&mut self, chunk: &str) -> String {
+        self.pending.push_str(chunk);
+        let mut rendered = String::new();
+        while let Some(end) = self.pending.find('\n') {
+            let line = self.pending[..end].to_owned();
+            self.pending.replace_range(..=end, "");
+            self.line(line, &mut rendered);
+        }
+        rendered
+    }
+
+    /// Render everything left,
+    /// closing any unterminated code block.
+    ///
+    /// The renderer can be reused afterwards.
+    #[inline]
+    pub fn finish(&mut self) -> String {
+        let mut rendered = String::new();
+        let pending = std::mem::take(&mut self.pending);
+        if !pending.is_empty() {
+            self.line(pending, &mut rendered);
+        }
+        self.flush(&mut rendered);
+        self.fence = None;
+        rendered.truncate(rendered.trim_end_matches('\n').len());
+        rendered
+    }
+
+    #[inline]
+    fn line(&mut self, line: String, rendered: &mut String) {
+        match &self.fence {
+            Some(fence) => {
+                let trimmed = line.trim();
+                if trimmed.starts_with(fence.as_str())
+                    && trimmed.trim_start_matches(fence.as_str()).is_empty()
+                {
+                    self.flush(rendered);
+                    self.fence = None;
+                } else {
+                    self.block.push(line);
+                }
+            }
+            None => {
+                if let Some(fence) = fence_marker(&line) {
+                    self.flush(rendered);
+                    self.fence = Some(fence.to_owned());
+                } else if line.trim().is_empty() {
+                    self.flush(rendered);
+                    rendered.push('\n');
+                } else {
+                    self.block.push(line);
+                }
+            }
+        }
+    }
+
+    /// Render the current block.
+    #[inline]
+    fn flush(&mut self, rendered: &mut String) {
+        let block = std::mem::take(&mut self.block);
+        if self.fence.is_some() {
+            for line in block {
+                rendered.push_str(CODE.0);
+                rendered.push_str(&line);
+                rendered.push_str(CODE.1);
+                rendered.push('\n');
+            }
+        } else if !block.is_empty() {
+            let mut paragraph = String::new();
+            for line in block {
+                let trimmed = line.trim_start();
+                if trimmed.starts_with('#') {
+                    if !paragraph.is_empty() {
+                        inline(&paragraph, rendered);
+                        paragraph.clear();
+                    }
+                    rendered.push_str(HEADING.0);
+                    rendered.push_str(trimmed.trim_start_matches('#').trim());
+                    rendered.push_str(HEADING.1);
+                    rendered.push('\n');
+                } else if let Some(item) = trimmed
+                    .strip_prefix("- ")
+                    .or_else(|| trimmed.strip_prefix("* "))
+                {
+                    paragraph.push_str(&line[..line.len() - trimmed.len()]);
+                    paragraph.push_str("• ");
+                    paragraph.push_str(item);
+                    paragraph.push('\n');
+                } else {
+                    paragraph.push_str(&line);
+                    paragraph.push('\n');
+                }
+            }
+            inline(&paragraph, rendered);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Render the given chunks,
+    /// returning what each push rendered and what finishing rendered.
+    fn render(chunks: &[&str]) -> (Vec<String>, String) {
+        let mut renderer = Renderer::default();
+        let pushed = chunks.iter().map(|chunk| renderer.push(chunk)).collect();
+        (pushed, renderer.finish())
+    }
+
+    #[test]
+    fn fences_split_across_chunks_are_rendered_once_closed() {
+        let (pushed, finished) = render(&["```r", "ust\nfn main() {}\n``", "`\nDone.\n\n"]);
+        assert_eq!(
+            pushed,
+            [
+                String::new(),
+                String::new(),
+                format!("{}fn main() {{}}{}\nDone.\n\n", CODE.0, CODE.1),
+            ]
+        );
+        assert_eq!(finished, "");
+    }
+
+    #[test]
+    fn unterminated_fences_are_closed_at_the_end() {
+        let mut renderer = Renderer::default();
+        assert_eq!(renderer.push("```\nlet x = 1;\n"), "");
+        assert_eq!(renderer.finish(), format!("{}let x = 1;{}", CODE.0, CODE.1));
+        // Later content is no longer code.
+        renderer.push("Plain");
+        assert_eq!(renderer.finish(), "Plain");
+    }
+
+    #[test]
+    fn the_final_block_is_flushed() {
+        let (pushed, finished) = render(&["# Title\n", "Hello **world**"]);
+        assert_eq!(pushed, ["", ""]);
+        assert_eq!(
+            finished,
+            format!(
+                "{}Title{}\nHello {}world{}",
+                HEADING.0, HEADING.1, BOLD.0, BOLD.1
+            )
+        );
+    }
+}
@@ -0,0 +1,484 @@
+use async_openai::types as aot;
+
+use crate::error::EllieError;
+
+/// Characters per token assumed by [`Tokenizer::Chars`],
+/// on the low side so that estimates are conservative.
+const CHARS_PER_TOKEN: usize = 3;
+
+/// Tokens added to every message by chat formatting,
+/// as estimated by [`Tokenizer::Chars`].
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// An `OpenAI` model that can be chosen for requests.
+#[derive(Debug, Clone, serde::Deserialize, serde::Serialize)]
+pub struct Model {
+    pub name: String,
+
+    /// Price in USD per 1K tokens,
+    /// used to prefer cheaper models.
+    #[serde(default)]
+    pub price: f64,
+
+    /// Whether the model is deprecated (or about to be).
+    #[serde(default)]
+    pub deprecated: bool,
+
+    /// Suggested replacement for a deprecated model.
+    #[serde(default)]
+    pub replacement: Option<String>,
+
+    /// Whether the model is capable enough for reasoning turns,
+    /// see [`ModelPolicy::SmartFirst`].
+    #[serde(default)]
+    pub smart: bool,
+
+    /// Preference among models of similar price
+    /// (higher is better),
+    /// see [`Models::choose`].
+    #[serde(default)]
+    pub priority: i32,
+
+    /// Request parameters the model rejects,
+    /// which are omitted when it is selected.
+    #[serde(default)]
+    pub unsupported: Vec<Parameter>,
+
+    /// How tokens are counted to check whether messages fit the model.
+    #[serde(default)]
+    pub tokenizer: Tokenizer,
+
+    /// Context length in tokens,
+    /// guessed from the model name by default
+    /// (e.g., required for non-OpenAI models).
+    #[serde(default)]
+    pub context_length: Option<usize>,
+}
+
+/// How tokens are counted for a model.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Tokenizer {
+    /// The `OpenAI` tokenizer of the model,
+    /// found by its name,
+    /// or a character-based estimate for unknown models.
+    #[default]
+    Tiktoken,
+    /// The `cl100k_base` `OpenAI` tokenizer
+    /// (e.g., of GPT-3.5 and GPT-4).
+    Cl100kBase,
+    /// The `o200k_base` `OpenAI` tokenizer
+    /// (e.g., of GPT-4o).
+    O200kBase,
+    /// A conservative character-based estimate,
+    /// for models with any other tokenizer.
+    Chars,
+}
+
+/// Estimate the number of tokens of the given messages from their length.
+#[inline]
+fn estimate_tokens(messages: &[aot::ChatCompletionRequestMessage]) -> usize {
+    messages
+        .iter()
+        .map(|message| {
+            let chars = message.role.to_string().chars().count()
+                + message
+                    .content
+                    .as_deref()
+                    .map_or(0, |text| text.chars().count())
+                + message
+                    .name
+                    .as_deref()
+                    .map_or(0, |text| text.chars().count())
+                + message.function_call.as_ref().map_or(0, |call| {
+                    call.name.chars().count() + call.arguments.chars().count()
+                });
+            (chars + CHARS_PER_TOKEN - 1) / CHARS_PER_TOKEN + TOKENS_PER_MESSAGE
+        })
+        .sum()
+}
+
+impl Model {
+    #[inline]
+    fn new(name: &str, price: f64, smart: bool) -> Self {
+        Self {
+            name: name.to_owned(),
+            price,
+            deprecated: false,
+            replacement: None,
+            smart,
+            priority: 0,
+            unsupported: Vec::new(),
+            tokenizer: Tokenizer::default(),
+            context_length: None,
+        }
+    }
+
+    /// Context length of the model in tokens.
+    #[inline]
+    pub fn context_length(&self) -> usize {
+        self.context_length
+            .unwrap_or_else(|| tiktoken_rs::model::get_context_size(&self.name))
+    }
+
+    /// Count the tokens of the given messages as a prompt to the model.
+    ///
+    /// # Errors
+    /// If the tokenizer could not be loaded.
+    #[inline]
+    pub fn count_tokens(
+        &self,
+        messages: &[aot::ChatCompletionRequestMessage],
+    ) -> Result<usize, EllieError> {
+        let Some(model) = self.tokenizer_model() else {
+            return Ok(estimate_tokens(messages));
+        };
+        tiktoken_rs::async_openai::num_tokens_from_messages(model, messages)
+            .map_err(|err| self.unknown(err))
+    }
+
+    /// Count the tokens of the given text
+    /// (e.g., of a completion).
+    ///
+    /// # Errors
+    /// If the tokenizer could not be loaded.
+    #[inline]
+    pub fn count_text_tokens(&self, text: &str) -> Result<usize, EllieError> {
+        match self.tokenizer_model() {
+            Some(model) => Ok(tiktoken_rs::get_bpe_from_model(model)
+                .map_err(|err| self.unknown(err))?
+                .encode_with_special_tokens(text)
+                .len()),
+            None => Ok((text.chars().count() + CHARS_PER_TOKEN - 1) / CHARS_PER_TOKEN),
+        }
+    }
+
+    /// Cut the given text down to at most the given number of tokens,
+    /// keeping its start.
+    ///
+    /// # Errors
+    /// If the tokenizer could not be loaded.
+    #[inline]
+    pub fn truncate<'t>(&self, text: &'t str, max_tokens: usize) -> Result<&'t str, EllieError> {
+        let mut end = match self.tokenizer_model() {
+            Some(model) => {
+                let bpe =
+                    tiktoken_rs::get_bpe_from_model(model).map_err(|err| self.unknown(err))?;
+                let tokens = bpe.encode_with_special_tokens(text);
+                if tokens.len() <= max_tokens {
+                    return Ok(text);
+                }
+                // Tokens decode to the exact bytes they encode.
+                bpe._decode_native(&tokens[..max_tokens]).len()
+            }
+            None => text
+                .char_indices()
+                .nth(max_tokens.saturating_mul(CHARS_PER_TOKEN))
+                .map_or(text.len(), |(end, _)| end),
+        };
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        Ok(&text[..end])
+    }
+
+    /// Get the model whose tiktoken tokenizer counts tokens,
+    /// or nothing if tokens are estimated from characters.
+    ///
+    /// Named tokenizers are used through a model known to use them.
+    #[inline]
+    fn tokenizer_model(&self) -> Option<&str> {
+        let model = match self.tokenizer {
+            Tokenizer::Tiktoken => self.name.as_str(),
+            Tokenizer::Cl100kBase => "gpt-4",
+            Tokenizer::O200kBase => "gpt-4o",
+            Tokenizer::Chars => return None,
+        };
+        if self.tokenizer == Tokenizer::Tiktoken
+            && tiktoken_rs::tokenizer::get_tokenizer(model).is_none()
+        {
+            log::debug!(
+                "no tokenizer known for model '{name}', estimating tokens from characters",
+                name = self.name
+            );
+            return None;
+        }
+        Some(model)
+    }
+
+    /// Create a [`EllieError::UnknownModel`] error for this model.
+    #[inline]
+    fn unknown(&self, err: impl std::fmt::Display) -> EllieError {
+        EllieError::UnknownModel {
+            model: self.name.clone(),
+            message: err.to_string(),
+        }
+    }
+
+    /// Whether this model accepts the given request parameter.
+    #[inline]
+    pub fn supports(&self, parameter: Parameter) -> bool {
+        !self.unsupported.contains(&parameter)
+    }
+
+    /// Warn if this model is flagged deprecated,
+    /// suggesting its replacement if any.
+    #[inline]
+    pub fn warn_if_deprecated(&self) {
+        if self.deprecated {
+            match &self.replacement {
+                Some(replacement) => log::warn!(
+                    "model '{name}' is deprecated, consider '{replacement}' instead",
+                    name = self.name
+                ),
+                None => log::warn!("model '{name}' is deprecated", name = self.name),
+            }
+        }
+    }
+}
+
+/// Request parameter that not every model supports.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Parameter {
+    /// Sampling temperature
+    /// (e.g., rejected by reasoning models).
+    Temperature,
+    /// Function specifications.
+    Functions,
+}
+
+/// How models are chosen in each round of a function-calling loop.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum ModelPolicy {
+    /// Use the cheapest model that fits in every round.
+    #[default]
+    Cheapest,
+    /// Use the cheapest smart model that fits in the first round,
+    /// and the cheapest model that fits in follow-up rounds.
+    SmartFirst,
+}
+
+impl ModelPolicy {
+    /// Whether a smart model should be preferred in the given round
+    /// (starting at zero).
+    #[inline]
+    pub const fn prefers_smart(self, round: usize) -> bool {
+        matches!(self, Self::SmartFirst) && round == 0
+    }
+}
+
+/// Which models an assistant may use.
+#[derive(Clone, Copy, Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Tier {
+    /// Any model.
+    #[default]
+    Any,
+    /// Only smart models.
+    Smart,
+}
+
+/// Table of available models.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct Models {
+    #[serde(default)]
+    model: Vec<Model>,
+
+    /// Relative price difference to the cheapest candidate
+    /// (e.g., 0.2 for 20%)
+    /// within which higher-priority models are preferred.
+    #[serde(default)]
+    price_tolerance: f64,
+}
+
+impl Default for Models {
+    #[inline]
+    fn default() -> Self {
+        Self {
+            model: vec![
+                Model::new("gpt-3.5-turbo", 0.0015, false),
+                Model::new("gpt-3.5-turbo-16k", 0.003, false),
+                Model::new("gpt-4", 0.03, true),
+                Model::new("gpt-4-32k", 0.06, true),
+            ],
+            price_tolerance: 0.0,
+        }
+    }
+}
+
+impl Models {
+    #[inline]
+    pub(super) fn path() -> Result<std::path::PathBuf, EllieError> {
+        Ok(crate::get_project_dirs()
+            .ok_or(EllieError::ProjectDirs)?
+            .config_dir()
+            .join("models.toml"))
+    }
+
+    /// Load the model table,
+    /// falling back to the default table if the file does not exist.
+    ///
+    /// # Errors
+    /// If the file could not be read or parsed,
+    /// or if it defines no models.
+    #[inline]
+    pub(super) fn load() -> Result<Self, EllieError> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content =
+            std::fs::read_to_string(&path).map_err(|err| EllieError::config(&path, err))?;
+        let Self {
+            mut model,
+            price_tolerance,
+        } = toml::from_str(&content).map_err(|err| EllieError::config(&path, err))?;
+        if model.is_empty() {
+            return Err(EllieError::config(path, "no models defined"));
+        }
+        model.sort_by(|m, n| m.price.total_cmp(&n.price));
+        Ok(Self {
+            model,
+            price_tolerance,
+        })
+    }
+
+    /// Keep only the models of the given tier.
+    ///
+    /// # Errors
+    /// If no model belongs to the tier.
+    #[inline]
+    pub(super) fn with_tier(self, tier: Tier) -> Result<Self, EllieError> {
+        let Self {
+            mut model,
+            price_tolerance,
+        } = self;
+        if matches!(tier, Tier::Smart) {
+            model.retain(|model| model.smart);
+        }
+        if model.is_empty() {
+            return Err(EllieError::NoModel);
+        }
+        Ok(Self {
+            model,
+            price_tolerance,
+        })
+    }
+
+    /// Keep only the models of the given names,
+    /// or all of them if no name is given.
+    ///
+    /// Names missing from the table are ignored with a warning.
+    ///
+    /// # Errors
+    /// If none of the names is in the table.
+    #[inline]
+    pub(super) fn with_allowed(self, names: &[String]) -> Result<Self, EllieError> {
+        let Self {
+            mut model,
+            price_tolerance,
+        } = self;
+        if !names.is_empty() {
+            for name in names {
+                if !model.iter().any(|model| &model.name == name) {
+                    log::warn!("allowed model '{name}' is not in the model table");
+                }
+            }
+            model.retain(|model| names.contains(&model.name));
+            if model.is_empty() {
+                return Err(EllieError::config(
+                    Self::path()?,
+                    "none of the allowed models is in the model table",
+                ));
+            }
+        }
+        Ok(Self {
+            model,
+            price_tolerance,
+        })
+    }
+
+    /// Iterate over models sorted by price (cheapest first).
+    #[inline]
+    pub fn iter(&self) -> std::slice::Iter<'_, Model> {
+        self.model.iter()
+    }
+
+    /// Choose among the given candidates (sorted by price)
+    /// the one with the highest priority
+    /// within the price tolerance of the cheapest,
+    /// preferring cheaper ones on ties.
+    ///
+    /// Candidates beyond the tolerance are never consumed.
+    #[inline]
+    pub fn choose<'m>(&self, mut candidates: impl Iterator<Item = &'m Model>) -> Option<&'m Model> {
+        let cheapest = candidates.next()?;
+        let limit = cheapest.price * (1.0 + self.price_tolerance);
+        Some(
+            candidates
+                .take_while(|model| model.price <= limit)
+                .fold(cheapest, |best, model| {
+                    if model.priority > best.priority {
+                        model
+                    } else {
+                        best
+                    }
+                }),
+        )
+    }
+
+    /// Get the model with the largest context length.
+    #[inline]
+    pub fn largest(&self) -> &Model {
+        self.model
+            .iter()
+            .max_by_key(|model| model.context_length())
+            .expect("there should always be at least one model")
+    }
+
+    /// Get the cheapest model.
+    #[inline]
+    pub fn cheapest(&self) -> &Model {
+        self.model
+            .first()
+            .expect("there should always be at least one model")
+    }
+}
+
+/// Print the models available to the account,
+/// flagging the ones ellie can select along with their price,
+/// followed by configured models the account lacks.
+///
+/// # Errors
+/// If the model table could not be loaded or the API request failed.
+#[inline]
+pub(super) async fn list<C: async_openai::config::Config + Sync>(
+    client: &async_openai::Client<C>,
+) -> Result<(), EllieError> {
+    use itertools::Itertools as _;
+
+    let models = Models::load()?;
+    let available = client
+        .models()
+        .list()
+        .await?
+        .data
+        .into_iter()
+        .map(|model| model.id)
+        .sorted()
+        .collect::<Vec<_>>();
+    for id in &available {
+        match models.iter().find(|model| &model.name == id) {
+            Some(model) => println!("{id}\tselectable\t${price}/1K tokens", price = model.price),
+            None => println!("{id}"),
+        }
+    }
+    for model in models
+        .iter()
+        .filter(|model| !available.contains(&model.name))
+    {
+        println!("{name}\tunavailable", name = model.name);
+    }
+    Ok(())
+}
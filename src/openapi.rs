@@ -0,0 +1,521 @@
+use async_openai::types::ChatCompletionFunctions;
+
+/// Maximum number of `$ref` pointers followed in a row,
+/// past which a reference is left unresolved.
+const MAX_REF_HOPS: usize = 16;
+
+/// How long fetching an `OpenAPI` document may take.
+const FETCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// HTTP methods that can appear in an `OpenAPI` path item.
+const METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// `OpenAPI` document to generate functions from.
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct Source {
+    /// URL or local path of the `OpenAPI` document (JSON).
+    spec: String,
+
+    /// Base URL of the API,
+    /// defaulting to the first server declared in the document.
+    #[serde(default)]
+    base_url: Option<String>,
+
+    /// Whether operations can be safely executed *without user approval*.
+    #[serde(default)]
+    safe: bool,
+}
+
+impl Source {
+    /// Fetch and parse the `OpenAPI` document.
+    #[inline]
+    fn document(&self) -> color_eyre::eyre::Result<serde_json::Value> {
+        let content = if self.spec.starts_with("http://") || self.spec.starts_with("https://") {
+            // Fetching never outlasts the deadline.
+            let timeout = crate::functions::remaining()
+                .map_or(FETCH_TIMEOUT, |remaining| remaining.min(FETCH_TIMEOUT));
+            ureq::get(&self.spec)
+                .timeout(timeout)
+                .call()?
+                .into_string()?
+        } else {
+            std::fs::read_to_string(shellexpand::full(&self.spec)?.as_ref())?
+        };
+        Ok(serde_json::from_str(&content)?)
+    }
+
+    /// Generate one operation per method of each path in the `OpenAPI`
+    /// document.
+    ///
+    /// # Errors
+    /// If the document could not be fetched or parsed.
+    #[inline]
+    pub(super) fn operations(&self) -> color_eyre::eyre::Result<Vec<Operation>> {
+        use color_eyre::eyre::ContextCompat as _;
+
+        let document = self.document()?;
+        let base_url = self
+            .base_url
+            .clone()
+            .or_else(|| {
+                document
+                    .pointer("/servers/0/url")
+                    .and_then(serde_json::Value::as_str)
+                    .map(ToOwned::to_owned)
+            })
+            .with_context(|| format!("no base URL for '{spec}'", spec = self.spec))?;
+        let base_url = base_url.trim_end_matches('/');
+
+        let mut operations = Vec::new();
+        let paths = document
+            .get("paths")
+            .and_then(serde_json::Value::as_object)
+            .into_iter()
+            .flatten();
+        for (path, item) in paths {
+            let shared_parameters = item
+                .get("parameters")
+                .and_then(serde_json::Value::as_array)
+                .cloned()
+                .unwrap_or_default();
+            for method in METHODS {
+                if let Some(operation) = item.get(method) {
+                    operations.push(Operation::new(
+                        &document,
+                        base_url,
+                        path,
+                        method,
+                        operation,
+                        &shared_parameters,
+                        self.safe,
+                    ));
+                }
+            }
+        }
+        Ok(operations)
+    }
+}
+
+/// Location of an operation parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Location {
+    Path,
+    Query,
+    Header,
+}
+
+/// A single `OpenAPI` operation exposed as a function.
+#[derive(Debug)]
+pub struct Operation {
+    /// Generated function specification.
+    spec: ChatCompletionFunctions,
+
+    /// HTTP method (lowercase).
+    method: String,
+
+    /// URL template,
+    /// with path parameters in braces.
+    url: String,
+
+    /// Path, query and header parameters.
+    parameters: Vec<(String, Location)>,
+
+    /// Whether this operation accepts a JSON request body.
+    has_body: bool,
+
+    /// Whether this operation can be safely executed *without user
+    /// approval*.
+    safe: bool,
+}
+
+impl Operation {
+    #[inline]
+    fn new(
+        document: &serde_json::Value,
+        base_url: &str,
+        path: &str,
+        method: &str,
+        operation: &serde_json::Value,
+        shared_parameters: &[serde_json::Value],
+        safe: bool,
+    ) -> Self {
+        let name = operation
+            .get("operationId")
+            .and_then(serde_json::Value::as_str)
+            .map_or_else(|| function_name(&format!("{method}_{path}")), function_name);
+        let description = ["summary", "description"]
+            .into_iter()
+            .find_map(|key| operation.get(key).and_then(serde_json::Value::as_str))
+            .map(ToOwned::to_owned);
+
+        let mut properties = serde_json::Map::new();
+        let mut required = Vec::new();
+        let mut parameters = Vec::new();
+        let operation_parameters = operation
+            .get("parameters")
+            .and_then(serde_json::Value::as_array)
+            .into_iter()
+            .flatten();
+        for parameter in shared_parameters.iter().chain(operation_parameters) {
+            let parameter = resolve(document, parameter);
+            let (Some(parameter_name), Some(location)) = (
+                parameter.get("name").and_then(serde_json::Value::as_str),
+                parameter
+                    .get("in")
+                    .and_then(serde_json::Value::as_str)
+                    .and_then(|location| match location {
+                        "path" => Some(Location::Path),
+                        "query" => Some(Location::Query),
+                        "header" => Some(Location::Header),
+                        _ => None,
+                    }),
+            ) else {
+                continue;
+            };
+            let mut schema = parameter
+                .get("schema")
+                .cloned()
+                .unwrap_or_else(|| serde_json::json!({"type": "string"}));
+            if let (Some(schema), Some(description)) =
+                (schema.as_object_mut(), parameter.get("description"))
+            {
+                schema.insert("description".to_owned(), description.clone());
+            }
+            if location == Location::Path
+                || parameter
+                    .get("required")
+                    .and_then(serde_json::Value::as_bool)
+                    .unwrap_or_default()
+            {
+                required.push(serde_json::Value::from(parameter_name));
+            }
+            properties.insert(parameter_name.to_owned(), schema);
+            parameters.retain(|(name, _)| name != parameter_name);
+            parameters.push((parameter_name.to_owned(), location));
+        }
+
+        let body = operation
+            .get("requestBody")
+            .map(|body| resolve(document, body));
+        let body_schema = body
+            .as_ref()
+            .and_then(|body| body.pointer("/content/application~1json/schema"));
+        if let (Some(body), Some(body_schema)) = (&body, body_schema) {
+            properties.insert("body".to_owned(), body_schema.clone());
+            if body
+                .get("required")
+                .and_then(serde_json::Value::as_bool)
+                .unwrap_or_default()
+            {
+                required.push("body".into());
+            }
+        }
+
+        let spec = ChatCompletionFunctions {
+            name,
+            description,
+            parameters: Some(serde_json::json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+            })),
+        };
+        Self {
+            spec,
+            method: method.to_owned(),
+            url: format!("{base_url}{path}"),
+            parameters,
+            has_body: body_schema.is_some(),
+            safe,
+        }
+    }
+
+    #[inline]
+    pub(super) fn name(&self) -> &str {
+        &self.spec.name
+    }
+
+    #[inline]
+    pub(super) const fn is_safe(&self) -> bool {
+        self.safe
+    }
+
+    #[inline]
+    pub(super) fn specification(&self) -> ChatCompletionFunctions {
+        self.spec.clone()
+    }
+
+    /// Describe the HTTP request this operation performs.
+    #[inline]
+    pub(super) fn describe(&self) -> String {
+        format!(
+            "{method} {url}",
+            method = self.method.to_uppercase(),
+            url = self.url
+        )
+    }
+
+    /// Perform the HTTP request for the given JSON arguments,
+    /// returning the response body.
+    ///
+    /// Error responses are returned as text too,
+    /// so that the model can act on them.
+    #[inline]
+    pub(super) fn call(&self, arguments: &str) -> String {
+        let arguments: serde_json::Map<String, serde_json::Value> =
+            match serde_json::from_str(arguments) {
+                Ok(arguments) => arguments,
+                Err(err) => return format!("invalid arguments: {err}"),
+            };
+
+        let mut url = self.url.clone();
+        for (name, location) in &self.parameters {
+            if *location == Location::Path {
+                if let Some(value) = arguments.get(name) {
+                    url = url.replace(&format!("{{{name}}}"), &encode(&to_text(value)));
+                }
+            }
+        }
+        let mut request = ureq::request(&self.method.to_uppercase(), &url);
+        for (name, location) in &self.parameters {
+            if let Some(value) = arguments.get(name) {
+                match location {
+                    Location::Path => {}
+                    Location::Query => request = request.query(name, &to_text(value)),
+                    Location::Header => request = request.set(name, &to_text(value)),
+                }
+            }
+        }
+        log::info!("{method} {url}", method = self.method.to_uppercase());
+
+        let response = match arguments.get("body") {
+            Some(body) if self.has_body => request.send_json(body),
+            _ => request.call(),
+        };
+        match response {
+            Ok(response) => response
+                .into_string()
+                .unwrap_or_else(|err| format!("unreadable response: {err}")),
+            Err(ureq::Error::Status(status, response)) => format!(
+                "HTTP {status}: {body}",
+                body = response.into_string().unwrap_or_default()
+            ),
+            Err(err) => format!("request failed: {err}"),
+        }
+    }
+}
+
+/// Follow local `$ref` pointers (recursively),
+/// returning the referenced values.
+///
+/// Cyclic references and chains longer than [`MAX_REF_HOPS`]
+/// are replaced by an empty schema (accepting anything),
+/// with a warning.
+#[inline]
+fn resolve(document: &serde_json::Value, value: &serde_json::Value) -> serde_json::Value {
+    resolve_with(document, value, &mut Vec::new())
+}
+
+/// Follow local `$ref` pointers (recursively)
+/// while already following the given ones.
+#[inline]
+fn resolve_with<'d>(
+    document: &'d serde_json::Value,
+    value: &'d serde_json::Value,
+    followed: &mut Vec<&'d str>,
+) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(object) => {
+            if let Some(reference) = object.get("$ref").and_then(serde_json::Value::as_str) {
+                if followed.contains(&reference) {
+                    log::warn!("cyclic '$ref' to '{reference}' left unresolved");
+                    return serde_json::json!({});
+                }
+                if followed.len() >= MAX_REF_HOPS {
+                    log::warn!(
+                        "'$ref' to '{reference}' left unresolved after {MAX_REF_HOPS} references"
+                    );
+                    return serde_json::json!({});
+                }
+                let Some(target) = reference
+                    .strip_prefix('#')
+                    .and_then(|pointer| document.pointer(pointer))
+                else {
+                    return value.clone();
+                };
+                followed.push(reference);
+                let resolved = resolve_with(document, target, followed);
+                followed.pop();
+                return resolved;
+            }
+            object
+                .iter()
+                .map(|(key, value)| (key.clone(), resolve_with(document, value, followed)))
+                .collect::<serde_json::Map<_, _>>()
+                .into()
+        }
+        serde_json::Value::Array(array) => array
+            .iter()
+            .map(|value| resolve_with(document, value, followed))
+            .collect::<Vec<_>>()
+            .into(),
+        value => value.clone(),
+    }
+}
+
+/// Create a valid function name (a-z, A-Z, 0-9, underscores and dashes,
+/// at most 64 characters).
+#[inline]
+fn function_name(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect::<String>()
+        .trim_matches('_')
+        .chars()
+        .take(64)
+        .collect()
+}
+
+/// Render a JSON value as plain text for URLs and headers.
+#[inline]
+pub(super) fn to_text(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Percent-encode a path segment.
+#[inline]
+fn encode(segment: &str) -> String {
+    use std::fmt::Write as _;
+
+    segment.bytes().fold(String::new(), |mut encoded, byte| {
+        if byte.is_ascii_alphanumeric() || b"-._~".contains(&byte) {
+            encoded.push(byte.into());
+        } else {
+            write!(encoded, "%{byte:02X}").expect("writing to a string should never fail");
+        }
+        encoded
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn references_are_resolved() {
+        let document = serde_json::json!({
+            "components": {
+                "schemas": {
+                    "Id": {"$ref": "#/components/schemas/Text"},
+                    "Text": {"type": "string"},
+                    "Item": {"type": "object", "properties": {"id": {"$ref": "#/components/schemas/Id"}}},
+                },
+            },
+        });
+        let resolved = resolve(
+            &document,
+            &serde_json::json!({"$ref": "#/components/schemas/Item"}),
+        );
+        assert_eq!(
+            resolved,
+            serde_json::json!({"type": "object", "properties": {"id": {"type": "string"}}})
+        );
+        let missing = serde_json::json!({"$ref": "#/components/schemas/Missing"});
+        assert_eq!(resolve(&document, &missing), missing);
+    }
+
+    #[test]
+    fn cyclic_references_are_left_unresolved() {
+        let document = serde_json::json!({
+            "components": {
+                "schemas": {
+                    "Node": {
+                        "type": "object",
+                        "properties": {"next": {"$ref": "#/components/schemas/Node"}},
+                    },
+                    "A": {"$ref": "#/components/schemas/B"},
+                    "B": {"$ref": "#/components/schemas/A"},
+                },
+            },
+        });
+        assert_eq!(
+            resolve(
+                &document,
+                &serde_json::json!({"$ref": "#/components/schemas/Node"})
+            ),
+            serde_json::json!({"type": "object", "properties": {"next": {}}})
+        );
+        assert_eq!(
+            resolve(
+                &document,
+                &serde_json::json!({"$ref": "#/components/schemas/A"})
+            ),
+            serde_json::json!({})
+        );
+    }
+
+    #[test]
+    fn long_reference_chains_are_cut() {
+        let schemas: serde_json::Map<_, _> = (0..MAX_REF_HOPS)
+            .map(|hop| {
+                (
+                    format!("S{hop}"),
+                    serde_json::json!({"$ref": format!("#/components/schemas/S{}", hop + 1)}),
+                )
+            })
+            .chain([(
+                format!("S{MAX_REF_HOPS}"),
+                serde_json::json!({"type": "string"}),
+            )])
+            .collect();
+        let document = serde_json::json!({"components": {"schemas": schemas}});
+        let reference =
+            |hop: usize| serde_json::json!({"$ref": format!("#/components/schemas/S{hop}")});
+        // One reference too many from the start of the chain.
+        assert_eq!(resolve(&document, &reference(0)), serde_json::json!({}));
+        assert_eq!(
+            resolve(&document, &reference(1)),
+            serde_json::json!({"type": "string"})
+        );
+    }
+
+    #[test]
+    fn operations_are_named_after_their_id_or_method_and_path() {
+        let document = serde_json::json!({});
+        let operation = |operation: serde_json::Value, path: &str| {
+            Operation::new(
+                &document,
+                "https://example.com",
+                path,
+                "get",
+                &operation,
+                &[],
+                false,
+            )
+        };
+        assert_eq!(
+            operation(serde_json::json!({"operationId": "getItem"}), "/items/{id}").name(),
+            "getItem"
+        );
+        assert_eq!(
+            operation(serde_json::json!({}), "/items/{id}").name(),
+            "get__items__id"
+        );
+        assert_eq!(
+            operation(serde_json::json!({"operationId": "list items!"}), "/items").name(),
+            "list_items"
+        );
+        assert_eq!(function_name(&"a".repeat(100)).len(), 64);
+    }
+}
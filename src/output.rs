@@ -0,0 +1,565 @@
+use tokio::io::AsyncWriteExt as _;
+
+use crate::{cli, functions::FunctionResponse, markdown, stats, wrap};
+
+/// How streamed output is written.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum StreamFormat {
+    /// Plain text as it is streamed.
+    #[default]
+    Text,
+    /// One JSON object per line for each event
+    /// (content delta, function call, function result and finish).
+    Jsonl,
+}
+
+/// Get the width to wrap content to:
+/// an explicit width,
+/// or the terminal width if the standard output is a terminal.
+#[inline]
+fn wrap_width(wrap: Option<Option<usize>>) -> Option<usize> {
+    use std::io::IsTerminal as _;
+
+    match wrap? {
+        Some(width) => Some(width),
+        None if std::io::stdout().is_terminal() => {
+            terminal_size::terminal_size().map(|(terminal_size::Width(width), _)| width.into())
+        }
+        None => None,
+    }
+}
+
+/// Where output is written to.
+#[derive(Debug)]
+enum Writer {
+    Stdout(tokio::io::Stdout),
+
+    /// Connected Unix domain socket,
+    /// see [`Output::connect`].
+    #[cfg(unix)]
+    Socket(tokio::net::UnixStream),
+
+    /// Named pipe,
+    /// see [`Output::connect`].
+    #[cfg(unix)]
+    File(tokio::fs::File),
+
+    /// In-memory buffer,
+    /// used when answers have to be printed in order after the fact.
+    Buffer(Vec<u8>),
+}
+
+impl Writer {
+    #[inline]
+    async fn write_all(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        match self {
+            Self::Stdout(stdout) => stdout.write_all(bytes).await,
+            #[cfg(unix)]
+            Self::Socket(socket) => socket.write_all(bytes).await,
+            #[cfg(unix)]
+            Self::File(file) => file.write_all(bytes).await,
+            Self::Buffer(buffer) => {
+                buffer.extend_from_slice(bytes);
+                Ok(())
+            }
+        }
+    }
+
+    #[inline]
+    async fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Stdout(stdout) => stdout.flush().await,
+            #[cfg(unix)]
+            Self::Socket(socket) => socket.flush().await,
+            #[cfg(unix)]
+            Self::File(file) => file.flush().await,
+            Self::Buffer(_) => Ok(()),
+        }
+    }
+}
+
+/// Destination of assistant content streamed to the user.
+#[derive(Debug)]
+pub struct Output {
+    writer: Writer,
+
+    /// Text written before the first chunk of content.
+    prefix: Option<String>,
+
+    /// Text written after the last chunk of content.
+    suffix: Option<String>,
+
+    /// Whether the prefix was already written for the current content.
+    started: bool,
+
+    /// Leading whitespace of the current content,
+    /// held back until it turns out to be part of an answer
+    /// (function calls may come with whitespace content too).
+    leading: String,
+
+    /// Markdown renderer,
+    /// if content is rendered.
+    markdown: Option<markdown::Renderer>,
+
+    /// Word wrapper,
+    /// if content is wrapped.
+    wrap: Option<wrap::Wrapper>,
+
+    format: StreamFormat,
+
+    /// Whether only the output of executed function calls is written.
+    functions_only: bool,
+
+    /// Whether only the final answer is written,
+    /// once complete.
+    answer_only: bool,
+}
+
+impl Output {
+    #[inline]
+    fn with_writer(writer: Writer, options: &cli::Options) -> Self {
+        Self {
+            writer,
+            prefix: options.output_prefix.clone(),
+            suffix: options.output_suffix.clone(),
+            started: false,
+            leading: String::new(),
+            markdown: options.markdown.then(markdown::Renderer::default),
+            wrap: wrap_width(options.wrap).map(wrap::Wrapper::new),
+            format: options.stream_format,
+            functions_only: options.functions_only,
+            answer_only: options.answer_only,
+        }
+    }
+
+    /// Create an output writing to the standard output.
+    #[inline]
+    pub fn new(options: &cli::Options) -> Self {
+        Self::with_writer(Writer::Stdout(tokio::io::stdout()), options)
+    }
+
+    /// Create an output writing to `--output-socket`,
+    /// if given,
+    /// or to the standard output.
+    ///
+    /// # Errors
+    /// If the output socket could not be connected to.
+    #[inline]
+    pub async fn open(options: &cli::Options) -> std::io::Result<Self> {
+        match &options.output_socket {
+            Some(path) => Self::connect(options, path).await,
+            None => Ok(Self::new(options)),
+        }
+    }
+
+    /// Create an output writing to the given Unix domain socket or named pipe
+    /// instead of the standard output,
+    /// e.g., for a front-end rendering it in a separate process.
+    ///
+    /// Sockets are connected to
+    /// and named pipes opened for writing
+    /// (which waits for a reader),
+    /// where a named pipe is created if nothing is there.
+    ///
+    /// # Errors
+    /// If the socket could not be connected to,
+    /// the named pipe could not be created or opened,
+    /// or something else is at the given path.
+    #[inline]
+    pub async fn connect(options: &cli::Options, path: &std::path::Path) -> std::io::Result<Self> {
+        #[cfg(unix)]
+        {
+            use std::os::unix::{ffi::OsStrExt as _, fs::FileTypeExt as _};
+
+            let file_type = match std::fs::metadata(path) {
+                Ok(metadata) => metadata.file_type(),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    let c_path =
+                        std::ffi::CString::new(path.as_os_str().as_bytes()).map_err(|err| {
+                            std::io::Error::new(std::io::ErrorKind::InvalidInput, err)
+                        })?;
+                    // SAFETY: the path is a valid NUL-terminated string.
+                    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                    std::fs::metadata(path)?.file_type()
+                }
+                Err(err) => return Err(err),
+            };
+            if file_type.is_socket() {
+                let socket = tokio::net::UnixStream::connect(path).await?;
+                return Ok(Self::with_writer(Writer::Socket(socket), options));
+            }
+            if file_type.is_fifo() {
+                let file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+                return Ok(Self::with_writer(Writer::File(file), options));
+            }
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!(
+                    "'{path}' is neither a socket nor a named pipe",
+                    path = path.display()
+                ),
+            ))
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = options;
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                format!(
+                    "'{path}' cannot be connected to outside Unix",
+                    path = path.display()
+                ),
+            ))
+        }
+    }
+
+    /// Create an output writing to an in-memory buffer,
+    /// see [`Output::into_bytes`].
+    #[inline]
+    pub fn buffered(options: &cli::Options) -> Self {
+        Self::with_writer(Writer::Buffer(Vec::new()), options)
+    }
+
+    /// Get everything written to a buffered output.
+    #[inline]
+    pub fn into_bytes(self) -> Vec<u8> {
+        match self.writer {
+            Writer::Stdout(_) => Vec::new(),
+            #[cfg(unix)]
+            Writer::Socket(_) | Writer::File(_) => Vec::new(),
+            Writer::Buffer(buffer) => buffer,
+        }
+    }
+
+    /// Write the prefix if this is the first chunk of content.
+    #[inline]
+    async fn start(&mut self) -> std::io::Result<()> {
+        if !self.started {
+            self.started = true;
+            if let Some(prefix) = &self.prefix {
+                self.writer.write_all(prefix.as_ref()).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write an event as a JSON line.
+    #[inline]
+    async fn event(&mut self, event: serde_json::Value) -> std::io::Result<()> {
+        self.writer.write_all(event.to_string().as_ref()).await?;
+        self.writer.write_all(b"\n").await?;
+        self.writer.flush().await
+    }
+
+    /// Write a chunk of content.
+    #[inline]
+    pub async fn content(&mut self, content: &str) -> std::io::Result<()> {
+        if self.functions_only || self.answer_only {
+            return Ok(());
+        }
+        if let StreamFormat::Jsonl = self.format {
+            if content.is_empty() {
+                return Ok(());
+            }
+            return self
+                .event(serde_json::json!({"type": "content", "delta": content}))
+                .await;
+        }
+        if !self.started {
+            if content.trim().is_empty() {
+                self.leading.push_str(content);
+                return Ok(());
+            }
+            self.start().await?;
+        }
+        let content = if self.leading.is_empty() {
+            std::borrow::Cow::Borrowed(content)
+        } else {
+            let mut leading = std::mem::take(&mut self.leading);
+            leading.push_str(content);
+            std::borrow::Cow::Owned(leading)
+        };
+        let rendered = match &mut self.markdown {
+            Some(markdown) => markdown.push(&content).into(),
+            None => content,
+        };
+        match &mut self.wrap {
+            Some(wrap) => {
+                let wrapped = wrap.push(&rendered);
+                self.writer.write_all(wrapped.as_ref()).await?;
+            }
+            None => self.writer.write_all(rendered.as_bytes()).await?,
+        }
+        self.writer.flush().await
+    }
+
+    /// Write raw bytes,
+    /// e.g., previously buffered output.
+    #[inline]
+    pub async fn raw(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.writer.write_all(bytes).await?;
+        self.writer.flush().await
+    }
+
+    /// Write the model snapshot serving the current response,
+    /// only as an event.
+    #[inline]
+    pub async fn model(&mut self, model: &str) -> std::io::Result<()> {
+        if self.functions_only || self.answer_only {
+            return Ok(());
+        }
+        match self.format {
+            StreamFormat::Text => Ok(()),
+            StreamFormat::Jsonl => {
+                self.event(serde_json::json!({"type": "model", "model": model}))
+                    .await
+            }
+        }
+    }
+
+    /// Write a function call about to be executed,
+    /// only as an event.
+    #[inline]
+    pub async fn function_call(&mut self, name: &str, arguments: &str) -> std::io::Result<()> {
+        self.leading.clear();
+        if self.functions_only || self.answer_only {
+            return Ok(());
+        }
+        match self.format {
+            StreamFormat::Text => Ok(()),
+            StreamFormat::Jsonl => {
+                self.event(serde_json::json!({
+                    "type": "function_call",
+                    "name": name,
+                    "arguments": arguments,
+                }))
+                .await
+            }
+        }
+    }
+
+    /// Write the result of a function call,
+    /// only as an event,
+    /// or as plain text if only the output of executed calls is written.
+    #[inline]
+    pub async fn function_result(
+        &mut self,
+        name: &str,
+        response: &FunctionResponse,
+    ) -> std::io::Result<()> {
+        if self.answer_only
+            || (self.functions_only && !matches!(response, FunctionResponse::Executed { .. }))
+        {
+            return Ok(());
+        }
+        match self.format {
+            StreamFormat::Text if self.functions_only => {
+                self.writer.write_all(response.to_string().as_ref()).await?;
+                self.writer.write_all(b"\n").await?;
+                self.writer.flush().await
+            }
+            StreamFormat::Text => Ok(()),
+            StreamFormat::Jsonl => {
+                self.event(serde_json::json!({
+                    "type": "function_result",
+                    "name": name,
+                    "content": response.to_string(),
+                }))
+                .await
+            }
+        }
+    }
+
+    /// Write the final answer,
+    /// trimmed,
+    /// if only the final answer is written.
+    #[inline]
+    pub async fn answer(&mut self, content: &str) -> std::io::Result<()> {
+        if !self.answer_only {
+            return Ok(());
+        }
+        self.writer.write_all(content.trim().as_ref()).await?;
+        self.writer.write_all(b"\n").await?;
+        self.writer.flush().await
+    }
+
+    /// Write the usage of all requests,
+    /// only as an event.
+    #[inline]
+    pub async fn stats(&mut self, stats: &stats::Stats) -> std::io::Result<()> {
+        match self.format {
+            StreamFormat::Text => Ok(()),
+            StreamFormat::Jsonl => {
+                let mut event = stats.to_json();
+                event["type"] = "stats".into();
+                self.event(event).await
+            }
+        }
+    }
+
+    /// Write a separator line between independent answers.
+    #[inline]
+    pub async fn separator(&mut self, separator: &str) -> std::io::Result<()> {
+        if let StreamFormat::Jsonl = self.format {
+            return self.event(serde_json::json!({"type": "separator"})).await;
+        }
+        self.writer.write_all(separator.as_ref()).await?;
+        self.writer.write_all(b"\n").await?;
+        self.writer.flush().await
+    }
+
+    /// Terminate the current content for the given finish reason
+    /// (e.g., "stop"),
+    /// writing the suffix.
+    #[inline]
+    pub async fn finish(&mut self, reason: &str) -> std::io::Result<()> {
+        if self.functions_only || self.answer_only {
+            return Ok(());
+        }
+        if let StreamFormat::Jsonl = self.format {
+            return self
+                .event(serde_json::json!({"type": "finish", "reason": reason}))
+                .await;
+        }
+        self.leading.clear();
+        self.start().await?;
+        let rendered = self
+            .markdown
+            .as_mut()
+            .map(markdown::Renderer::finish)
+            .unwrap_or_default();
+        match &mut self.wrap {
+            Some(wrap) => {
+                let mut wrapped = wrap.push(&rendered);
+                wrapped.push_str(&wrap.finish());
+                self.writer.write_all(wrapped.as_ref()).await?;
+            }
+            None => self.writer.write_all(rendered.as_ref()).await?,
+        }
+        self.writer.write_all(b"\n").await?;
+        if let Some(suffix) = &self.suffix {
+            self.writer.write_all(suffix.as_ref()).await?;
+        }
+        self.started = false;
+        self.writer.flush().await
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn options() -> cli::Options {
+        <cli::Cli as clap::Parser>::parse_from(["ellie"]).options
+    }
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "ellie-output-{name}-{pid}",
+            pid = std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn prefixes_are_only_written_before_answers() {
+        let options = <cli::Cli as clap::Parser>::parse_from([
+            "ellie",
+            "--output-prefix",
+            "> ",
+            "--output-suffix",
+            "<\n",
+        ])
+        .options;
+        let mut output = Output::buffered(&options);
+        block_on(async {
+            // A function call round with whitespace content.
+            output.content("\n\n").await.unwrap();
+            output
+                .function_call("get_current_weather", "{}")
+                .await
+                .unwrap();
+            output.content("").await.unwrap();
+            output.content(" Sunny").await.unwrap();
+            output.content(" today.").await.unwrap();
+            output.finish("stop").await.unwrap();
+        });
+        assert_eq!(
+            String::from_utf8(output.into_bytes()).unwrap(),
+            ">  Sunny today.\n<\n"
+        );
+    }
+
+    #[test]
+    fn output_is_written_to_sockets() {
+        use tokio::io::AsyncReadExt as _;
+
+        let path = temp_path("socket");
+        let written = block_on(async {
+            let listener = tokio::net::UnixListener::bind(&path).unwrap();
+            let mut output = Output::connect(&options(), &path).await.unwrap();
+            let (mut stream, _) = listener.accept().await.unwrap();
+            output.content("Hello, world!").await.unwrap();
+            output.finish("stop").await.unwrap();
+            drop(output);
+            let mut written = String::new();
+            stream.read_to_string(&mut written).await.unwrap();
+            written
+        });
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, "Hello, world!\n");
+    }
+
+    #[test]
+    fn named_pipes_are_created_if_missing() {
+        use std::{io::Read as _, os::unix::fs::FileTypeExt as _};
+
+        let path = temp_path("fifo");
+        let written = block_on(async {
+            let reader = {
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || {
+                    // The pipe is only there once the output creates it.
+                    while !path.exists() {
+                        std::thread::sleep(std::time::Duration::from_millis(10));
+                    }
+                    let mut written = String::new();
+                    std::fs::File::open(&path)
+                        .unwrap()
+                        .read_to_string(&mut written)
+                        .unwrap();
+                    written
+                })
+            };
+            let mut output = Output::connect(&options(), &path).await.unwrap();
+            output.content("Hello!").await.unwrap();
+            output.finish("stop").await.unwrap();
+            drop(output);
+            reader.await.unwrap()
+        });
+        assert!(std::fs::metadata(&path).unwrap().file_type().is_fifo());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, "Hello!\n");
+    }
+
+    #[test]
+    fn regular_files_are_never_overwritten() {
+        let path = temp_path("file");
+        std::fs::write(&path, "important").unwrap();
+        let err = block_on(Output::connect(&options(), &path)).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "important");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,126 @@
+/// Time to wait before showing any progress,
+/// so that quick phases stay silent.
+const DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Whether milestones are logged,
+/// see [`log`].
+static LOG: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Log milestones of the run from now on.
+#[inline]
+pub fn enable_log() {
+    LOG.store(true, std::sync::atomic::Ordering::Relaxed);
+}
+
+/// Write a milestone of the run (e.g., a function call) as a line on the
+/// standard error,
+/// if enabled with [`enable_log`].
+///
+/// Unlike [`Progress`],
+/// this is meant for logs (e.g., in CI),
+/// so it doesn't need a terminal.
+#[inline]
+pub fn log(milestone: impl std::fmt::Display) {
+    if LOG.load(std::sync::atomic::Ordering::Relaxed) {
+        eprintln!("[ellie] {milestone}");
+    }
+}
+
+/// Held by the progress whose status is shown,
+/// so that concurrent phases (e.g., reading the input while loading
+/// function specifications) take turns instead of overwriting each other.
+static LINE: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Keep progress from being shown until the returned guard is dropped,
+/// e.g., while an editor takes over the terminal.
+///
+/// This waits for a progress already shown to finish.
+#[inline]
+pub fn suppress() -> std::sync::MutexGuard<'static, ()> {
+    LINE.lock().expect("progress line should never be poisoned")
+}
+
+/// Single-line progress status on the standard error,
+/// shown only if it is a terminal
+/// and the phase takes more than a moment.
+///
+/// Only one progress is shown at a time,
+/// the others waiting for the line to be free.
+/// The line is cleared when the progress is dropped.
+#[derive(Debug)]
+pub struct Progress {
+    sender: Option<std::sync::mpsc::Sender<String>>,
+    handle: Option<std::thread::JoinHandle<()>>,
+}
+
+impl Progress {
+    #[inline]
+    pub fn start() -> Self {
+        use std::io::IsTerminal as _;
+
+        if !std::io::stderr().is_terminal() {
+            return Self {
+                sender: None,
+                handle: None,
+            };
+        }
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            let start = std::time::Instant::now();
+            let mut status = String::new();
+            let mut is_dirty = false;
+            let mut is_shown = false;
+            let mut line = None;
+            loop {
+                match receiver.recv_timeout(std::time::Duration::from_millis(100)) {
+                    Ok(update) => {
+                        status = update;
+                        is_dirty = true;
+                    }
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                    Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+                if is_dirty && start.elapsed() >= DELAY {
+                    if line.is_none() {
+                        line = LINE.try_lock().ok();
+                    }
+                    if line.is_some() {
+                        eprint!("\r\x1b[2K{status}");
+                        is_dirty = false;
+                        is_shown = true;
+                    }
+                }
+            }
+            if is_shown {
+                eprint!("\r\x1b[2K");
+            }
+            drop(line);
+        });
+        Self {
+            sender: Some(sender),
+            handle: Some(handle),
+        }
+    }
+
+    /// Replace the current status.
+    #[inline]
+    pub fn update(&self, status: impl Into<String>) {
+        if let Some(sender) = &self.sender {
+            // The thread only stops once the sender is dropped,
+            // so sending never fails.
+            sender.send(status.into()).ok();
+        }
+    }
+}
+
+impl Drop for Progress {
+    #[inline]
+    fn drop(&mut self) {
+        self.sender = None;
+        if let Some(handle) = self.handle.take() {
+            if handle.join().is_err() {
+                log::warn!("progress thread panicked");
+            }
+        }
+    }
+}
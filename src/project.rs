@@ -0,0 +1,67 @@
+use crate::{error::EllieError, models};
+
+/// Definition of an assistant from a single project file (e.g.,
+/// `ellie.toml`),
+/// overriding the global configuration.
+///
+/// The same file may define `[[provider]]`, `[[function]]` and `[[openapi]]`
+/// sections,
+/// see [`crate::functions::Toolset::load`].
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+pub struct Project {
+    /// System prompt prepended to every conversation.
+    #[serde(default)]
+    pub system: Option<String>,
+
+    /// Sampling temperature of all requests.
+    #[serde(default)]
+    pub temperature: Option<f32>,
+
+    /// Which models the assistant may use.
+    #[serde(default)]
+    pub tier: models::Tier,
+
+    /// Names of the only models the assistant may use
+    /// (e.g., to forbid expensive ones),
+    /// or any model of the tier if empty.
+    #[serde(default)]
+    pub allowed_models: Vec<String>,
+
+    /// Shell command whose output replaces the user input,
+    /// see [`crate::preprocess`].
+    #[serde(default)]
+    pub pre_command: Option<String>,
+
+    /// Example exchanges inserted before the first user input of every
+    /// conversation.
+    #[serde(default)]
+    pub example: Vec<Example>,
+
+    /// Regular expressions redacted from answers,
+    /// in addition to `--redact`.
+    #[serde(default)]
+    pub redact: Vec<String>,
+}
+
+/// Example exchange from an `[[example]]` section,
+/// used to steer answers (few-shot prompting).
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+pub struct Example {
+    /// User message.
+    pub user: String,
+
+    /// Expected assistant answer.
+    pub assistant: String,
+}
+
+impl Project {
+    /// Load the given project file.
+    ///
+    /// # Errors
+    /// If the file could not be read or parsed.
+    #[inline]
+    pub fn load(path: &std::path::Path) -> Result<Self, EllieError> {
+        let content = std::fs::read_to_string(path).map_err(|err| EllieError::config(path, err))?;
+        toml::from_str(&content).map_err(|err| EllieError::config(path, err))
+    }
+}
@@ -0,0 +1,135 @@
+/// Patterns redacted from answers before they are written or saved,
+/// e.g., secrets or personal data echoed by the model.
+#[derive(Debug, Default)]
+pub struct Redactions {
+    patterns: Vec<regex::Regex>,
+
+    /// Replacement of every match.
+    placeholder: String,
+}
+
+impl Redactions {
+    #[inline]
+    pub fn new(patterns: Vec<regex::Regex>, placeholder: String) -> Self {
+        Self {
+            patterns,
+            placeholder,
+        }
+    }
+
+    /// Replace every match in the given text with the placeholder.
+    #[inline]
+    pub fn apply<'t>(&self, text: &'t str) -> std::borrow::Cow<'t, str> {
+        let mut text = std::borrow::Cow::Borrowed(text);
+        for pattern in &self.patterns {
+            if let std::borrow::Cow::Owned(redacted) =
+                pattern.replace_all(&text, regex::NoExpand(&self.placeholder))
+            {
+                text = std::borrow::Cow::Owned(redacted);
+            }
+        }
+        text
+    }
+
+    /// Start redacting streamed content.
+    #[inline]
+    pub const fn stream(&self) -> Stream<'_> {
+        Stream {
+            redactions: self,
+            pending: String::new(),
+        }
+    }
+}
+
+/// Redaction of streamed content,
+/// line by line,
+/// since a match may span chunks.
+///
+/// Content is only held back if there is anything to redact,
+/// and matches never span lines.
+#[derive(Debug)]
+pub struct Stream<'r> {
+    redactions: &'r Redactions,
+
+    /// Content of the current line.
+    pending: String,
+}
+
+impl Stream<'_> {
+    /// Add a chunk of content,
+    /// returning the redacted content that can be written already.
+    #[inline]
+    pub fn push(&mut self, chunk: &str) -> String {
+        if self.redactions.patterns.is_empty() {
+            return chunk.to_owned();
+        }
+        self.pending.push_str(chunk);
+        match self.pending.rfind('\n') {
+            Some(end) => {
+                let rest = self.pending.split_off(end + 1);
+                let lines = std::mem::replace(&mut self.pending, rest);
+                self.redactions.apply(&lines).into_owned()
+            }
+            None => String::new(),
+        }
+    }
+
+    /// Redact the rest of the content,
+    /// returning it.
+    #[inline]
+    pub fn finish(&mut self) -> String {
+        let rest = std::mem::take(&mut self.pending);
+        self.redactions.apply(&rest).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redactions(patterns: &[&str]) -> Redactions {
+        Redactions::new(
+            patterns
+                .iter()
+                .map(|pattern| regex::Regex::new(pattern).unwrap())
+                .collect(),
+            "[REDACTED]".to_owned(),
+        )
+    }
+
+    #[test]
+    fn every_match_is_replaced_literally() {
+        let redactions = redactions(&[r"sk-\w+", r"\d{3}-\d{4}"]);
+        assert_eq!(
+            redactions.apply("key sk-abc, call 555-1234 or sk-def"),
+            "key [REDACTED], call [REDACTED] or [REDACTED]"
+        );
+        // Placeholders are not expanded.
+        let redactions =
+            Redactions::new(vec![regex::Regex::new(r"sk-\w+").unwrap()], "$0".to_owned());
+        assert_eq!(redactions.apply("key sk-abc"), "key $0");
+        assert!(matches!(
+            redactions.apply("nothing here"),
+            std::borrow::Cow::Borrowed(_)
+        ));
+    }
+
+    #[test]
+    fn matches_spanning_chunks_are_redacted() {
+        let redactions = redactions(&[r"sk-\w+"]);
+        let mut stream = redactions.stream();
+        assert_eq!(stream.push("Your key is s"), "");
+        assert_eq!(stream.push("k-abc1"), "");
+        assert_eq!(stream.push("23.\nNext"), "Your key is [REDACTED].\n");
+        assert_eq!(stream.push(" sk-x"), "");
+        assert_eq!(stream.finish(), "Next [REDACTED]");
+    }
+
+    #[test]
+    fn nothing_is_held_back_without_patterns() {
+        let redactions = redactions(&[]);
+        let mut stream = redactions.stream();
+        assert_eq!(stream.push("sk-abc"), "sk-abc");
+        assert_eq!(stream.finish(), "");
+    }
+}
@@ -0,0 +1,260 @@
+use async_openai::types as aot;
+use color_eyre::eyre::Context as _;
+
+/// Directory where sessions are saved.
+///
+/// # Errors
+/// If project directories could not be retrieved.
+#[inline]
+fn dir() -> color_eyre::eyre::Result<std::path::PathBuf> {
+    use color_eyre::eyre::ContextCompat as _;
+
+    Ok(crate::get_project_dirs()
+        .context("getting project directories")?
+        .data_dir()
+        .join("sessions"))
+}
+
+/// Path to the file of the named session.
+///
+/// # Errors
+/// If the name is not a plain file name.
+#[inline]
+fn path(name: &str) -> color_eyre::eyre::Result<std::path::PathBuf> {
+    color_eyre::eyre::ensure!(
+        !name.is_empty() && !name.starts_with('.') && !name.contains(std::path::is_separator),
+        "bad session name '{name}'"
+    );
+    Ok(dir()?.join(format!("{name}.json")))
+}
+
+/// Named conversation persisted across invocations.
+#[derive(Debug)]
+pub struct Session {
+    path: std::path::PathBuf,
+}
+
+impl Session {
+    /// Open the named session,
+    /// which is created on the first save.
+    ///
+    /// # Errors
+    /// If the name is not a plain file name.
+    #[inline]
+    pub fn open(name: &str) -> color_eyre::eyre::Result<Self> {
+        Ok(Self { path: path(name)? })
+    }
+
+    /// Load the saved messages,
+    /// if any.
+    ///
+    /// # Errors
+    /// If the session file could not be read or parsed.
+    #[inline]
+    pub fn messages(&self) -> color_eyre::eyre::Result<Vec<aot::ChatCompletionRequestMessage>> {
+        if !self.path.exists() {
+            return Ok(Vec::new());
+        }
+        let content = std::fs::read_to_string(&self.path)?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("parsing session '{path}'", path = self.path.display()))
+    }
+
+    /// Save the given messages,
+    /// replacing previously saved ones.
+    ///
+    /// The file is replaced at once,
+    /// so that an interrupted save never leaves a session half written.
+    ///
+    /// # Errors
+    /// If the session file could not be written.
+    #[inline]
+    pub fn save(
+        &self,
+        messages: &[aot::ChatCompletionRequestMessage],
+    ) -> color_eyre::eyre::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let temporary = self.path.with_extension("json.tmp");
+        std::fs::write(&temporary, serde_json::to_string(messages)?)?;
+        std::fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Remove the trailing user message of the given messages,
+/// left unanswered by an interrupted answer,
+/// returning its content.
+#[inline]
+pub fn take_unanswered(messages: &mut Vec<aot::ChatCompletionRequestMessage>) -> Option<String> {
+    if messages.last()?.role != aot::Role::User {
+        return None;
+    }
+    messages.pop()?.content
+}
+
+/// Format how long ago the given time was.
+#[inline]
+fn ago(time: std::time::SystemTime) -> String {
+    let seconds = time.elapsed().unwrap_or_default().as_secs();
+    match seconds {
+        0..=59 => format!("{seconds}s ago"),
+        60..=3599 => format!("{minutes}m ago", minutes = seconds / 60),
+        3600..=86399 => format!("{hours}h ago", hours = seconds / 3600),
+        _ => format!("{days}d ago", days = seconds / 86400),
+    }
+}
+
+/// Print the name,
+/// message count and last-modified time of each saved session.
+///
+/// # Errors
+/// If the sessions directory or any session could not be read.
+#[inline]
+pub fn list() -> color_eyre::eyre::Result<()> {
+    use itertools::Itertools as _;
+
+    let dir = dir()?;
+    if !dir.exists() {
+        return Ok(());
+    }
+    let entries = std::fs::read_dir(dir)?
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+        .sorted_by_key(std::fs::DirEntry::path);
+    for entry in entries {
+        let path = entry.path();
+        let name = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let session = Session { path };
+        let count = session.messages()?.len();
+        let modified = entry.metadata()?.modified()?;
+        println!(
+            "{name}\t{count} messages\t{modified}",
+            modified = ago(modified)
+        );
+    }
+    Ok(())
+}
+
+/// Remove the named session.
+///
+/// # Errors
+/// If the session does not exist or could not be removed.
+#[inline]
+pub fn remove(name: &str) -> color_eyre::eyre::Result<()> {
+    let path = path(name)?;
+    std::fs::remove_file(&path).with_context(|| format!("removing session '{name}'"))
+}
+
+/// Format of exported sessions.
+#[derive(Clone, Copy, Debug, Default, clap::ValueEnum)]
+pub enum ExportFormat {
+    /// Markdown transcript.
+    #[default]
+    Md,
+}
+
+/// Get a code fence longer than any run of backticks in the given text.
+#[inline]
+fn fence_for(text: &str) -> String {
+    let longest = text
+        .split(|c| c != '`')
+        .map(str::len)
+        .max()
+        .unwrap_or_default();
+    "`".repeat(longest.max(2) + 1)
+}
+
+/// Write the given messages as a Markdown transcript.
+#[inline]
+pub(super) fn to_markdown(
+    messages: &[aot::ChatCompletionRequestMessage],
+) -> Result<String, std::fmt::Error> {
+    use std::fmt::Write as _;
+
+    let mut transcript = String::new();
+    for message in messages {
+        let content = message.content.as_deref().unwrap_or_default().trim();
+        match (&message.role, &message.function_call) {
+            (aot::Role::Assistant, Some(aot::FunctionCall { name, arguments })) => {
+                let fence = fence_for(arguments);
+                write!(
+                    transcript,
+                    "## Function call: `{name}`\n\n{fence}json\n{arguments}\n{fence}\n\n"
+                )?;
+            }
+            (aot::Role::Function, _) => {
+                let fence = fence_for(content);
+                write!(
+                    transcript,
+                    "### Result of `{name}`\n\n{fence}\n{content}\n{fence}\n\n",
+                    name = message.name.as_deref().unwrap_or_default()
+                )?;
+            }
+            (role, _) => {
+                let role = match role {
+                    aot::Role::System => "System",
+                    aot::Role::User => "User",
+                    _ => "Assistant",
+                };
+                write!(transcript, "## {role}\n\n{content}\n\n")?;
+            }
+        }
+    }
+    transcript.truncate(transcript.trim_end().len());
+    transcript.push('\n');
+    Ok(transcript)
+}
+
+/// Print the named session in the given format.
+///
+/// # Errors
+/// If the session does not exist or could not be read.
+#[inline]
+pub fn export(name: &str, format: ExportFormat) -> color_eyre::eyre::Result<()> {
+    let session = Session::open(name)?;
+    color_eyre::eyre::ensure!(session.path.exists(), "session '{name}' not found");
+    let messages = session.messages()?;
+    match format {
+        ExportFormat::Md => print!("{transcript}", transcript = to_markdown(&messages)?),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(role: aot::Role, content: &str) -> aot::ChatCompletionRequestMessage {
+        aot::ChatCompletionRequestMessageArgs::default()
+            .role(role)
+            .content(content)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn only_trailing_user_messages_are_unanswered() {
+        let mut messages = vec![
+            message(aot::Role::User, "Hello!"),
+            message(aot::Role::Assistant, "Hi!"),
+        ];
+        assert_eq!(take_unanswered(&mut messages), None);
+        assert_eq!(messages.len(), 2);
+
+        messages.push(message(aot::Role::User, "How are you?"));
+        assert_eq!(
+            take_unanswered(&mut messages).as_deref(),
+            Some("How are you?")
+        );
+        assert_eq!(messages.len(), 2);
+
+        assert_eq!(take_unanswered(&mut Vec::new()), None);
+    }
+}
@@ -0,0 +1,94 @@
+use crate::{budget, error::EllieError, models};
+
+/// Marker of a snippet reference,
+/// followed by the snippet name.
+const MARKER: &str = "@snippet:";
+
+/// Named reusable message snippets,
+/// defined in the `snippets.toml` configuration file as `name = "text"`.
+#[derive(Debug, Default, serde::Deserialize, serde::Serialize)]
+#[serde(transparent)]
+pub struct Snippets {
+    snippets: std::collections::BTreeMap<String, String>,
+}
+
+impl Snippets {
+    #[inline]
+    pub(super) fn path() -> Result<std::path::PathBuf, EllieError> {
+        Ok(crate::get_project_dirs()
+            .ok_or(EllieError::ProjectDirs)?
+            .config_dir()
+            .join("snippets.toml"))
+    }
+
+    /// Load the snippets,
+    /// falling back to none if the file does not exist.
+    ///
+    /// # Errors
+    /// If the file could not be read or parsed.
+    #[inline]
+    pub(super) fn load() -> Result<Self, EllieError> {
+        let path = Self::path()?;
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content =
+            std::fs::read_to_string(&path).map_err(|err| EllieError::config(&path, err))?;
+        toml::from_str(&content).map_err(|err| EllieError::config(&path, err))
+    }
+
+    /// Cut every snippet down to the snippets budget,
+    /// if any.
+    ///
+    /// # Errors
+    /// If the tokenizer of the model could not be loaded.
+    #[inline]
+    pub(super) fn apply_budget(
+        &mut self,
+        model: &models::Model,
+        budgets: &[budget::Budget],
+    ) -> Result<(), EllieError> {
+        for snippet in self.snippets.values_mut() {
+            let end = budget::truncate(model, budgets, budget::Source::Snippets, snippet)?.len();
+            snippet.truncate(end);
+        }
+        Ok(())
+    }
+
+    /// Replace `@snippet:<name>` references in the given text with their
+    /// snippets,
+    /// where names are made of alphanumeric characters, `-` and `_`.
+    ///
+    /// Snippets are not expanded recursively.
+    ///
+    /// # Errors
+    /// If a referenced snippet is not defined.
+    #[inline]
+    pub(super) fn expand(&self, text: &str) -> Result<String, EllieError> {
+        let mut expanded = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find(MARKER) {
+            expanded.push_str(&rest[..start]);
+            rest = &rest[start + MARKER.len()..];
+            let end = rest
+                .find(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_'))
+                .unwrap_or(rest.len());
+            // A trailing dash or underscore is more likely punctuation.
+            let name = rest[..end].trim_end_matches(['-', '_']);
+            if name.is_empty() {
+                expanded.push_str(MARKER);
+                continue;
+            }
+            let snippet = self
+                .snippets
+                .get(name)
+                .ok_or_else(|| EllieError::UnknownSnippet {
+                    name: name.to_owned(),
+                })?;
+            expanded.push_str(snippet.trim_end());
+            rest = &rest[name.len()..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+}
@@ -0,0 +1,113 @@
+use crate::audit;
+
+/// Usage of a single model.
+#[derive(Debug, Default, serde::Serialize)]
+pub struct ModelStats {
+    pub requests: usize,
+    pub prompt_tokens: usize,
+    pub completion_tokens: usize,
+
+    /// Estimated cost in USD,
+    /// from the configured price of the model.
+    pub cost: f64,
+}
+
+impl ModelStats {
+    #[inline]
+    fn add(&mut self, other: &Self) {
+        self.requests += other.requests;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.cost += other.cost;
+    }
+}
+
+/// Usage of all requests of a run,
+/// reported with `--stats`,
+/// per model.
+///
+/// Usage may be recorded concurrently
+/// (e.g., in batch mode).
+#[derive(Debug, Default)]
+pub struct Stats {
+    models: std::sync::Mutex<std::collections::BTreeMap<String, ModelStats>>,
+}
+
+impl Stats {
+    #[inline]
+    fn models(&self) -> std::sync::MutexGuard<'_, std::collections::BTreeMap<String, ModelStats>> {
+        self.models.lock().expect("stats should never be poisoned")
+    }
+
+    /// Record the usage of a request to the given model,
+    /// priced in USD per 1K tokens.
+    #[inline]
+    pub fn record(&self, model: &str, usage: &audit::Usage, price: f64) {
+        let cost = usage.total_tokens as f64 / 1000.0 * price;
+        self.models()
+            .entry(model.to_owned())
+            .or_default()
+            .add(&ModelStats {
+                requests: 1,
+                prompt_tokens: usage.prompt_tokens,
+                completion_tokens: usage.completion_tokens,
+                cost,
+            });
+    }
+
+    /// Get the totals and the per-model breakdown as JSON.
+    #[inline]
+    pub fn to_json(&self) -> serde_json::Value {
+        let models = self.models();
+        let total = total(&models);
+        serde_json::json!({
+            "requests": total.requests,
+            "prompt_tokens": total.prompt_tokens,
+            "completion_tokens": total.completion_tokens,
+            "total_tokens": total.prompt_tokens + total.completion_tokens,
+            "cost": total.cost,
+            "models": *models,
+        })
+    }
+
+    /// Print the usage of every model and the totals to the standard error.
+    #[inline]
+    pub fn report(&self) {
+        let models = self.models();
+        let width = models
+            .keys()
+            .map(String::len)
+            .max()
+            .unwrap_or_default()
+            .max("total".len());
+        for (model, stats) in models.iter() {
+            report_line(model, stats, width);
+        }
+        report_line("total", &total(&models), width);
+    }
+}
+
+/// Sum the usage of all models.
+#[inline]
+fn total(models: &std::collections::BTreeMap<String, ModelStats>) -> ModelStats {
+    let mut total = ModelStats::default();
+    for stats in models.values() {
+        total.add(stats);
+    }
+    total
+}
+
+/// Print a line of the usage report.
+#[inline]
+fn report_line(name: &str, stats: &ModelStats, width: usize) {
+    let ModelStats {
+        requests,
+        prompt_tokens,
+        completion_tokens,
+        cost,
+    } = stats;
+    eprintln!(
+        "{name:width$}  {requests} requests  {prompt_tokens} prompt + {completion_tokens} \
+         completion tokens  ${cost:.4}"
+    );
+}
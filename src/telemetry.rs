@@ -0,0 +1,407 @@
+/// Name of the exported service,
+/// unless `OTEL_SERVICE_NAME` is set.
+const SERVICE_NAME: &str = "ellie";
+
+/// Longest time to wait for the collector,
+/// so that a slow collector never holds up a run for long.
+const EXPORT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Status codes of `OpenTelemetry` spans.
+const STATUS_OK: u8 = 1;
+const STATUS_ERROR: u8 = 2;
+
+/// Kind of internal `OpenTelemetry` spans.
+const KIND_INTERNAL: u8 = 1;
+
+/// Get a non-empty environment variable.
+#[inline]
+fn var(key: &str) -> Option<String> {
+    std::env::var(key).ok().filter(|value| !value.is_empty())
+}
+
+/// Get the OTLP/HTTP endpoint receiving spans,
+/// following the standard `OpenTelemetry` environment variables,
+/// if configured.
+#[inline]
+fn endpoint() -> Option<String> {
+    var("OTEL_EXPORTER_OTLP_TRACES_ENDPOINT").or_else(|| {
+        var("OTEL_EXPORTER_OTLP_ENDPOINT").map(|endpoint| {
+            format!(
+                "{endpoint}/v1/traces",
+                endpoint = endpoint.trim_end_matches('/')
+            )
+        })
+    })
+}
+
+/// Get headers sent to the collector
+/// (e.g., for authentication),
+/// given as comma-separated `key=value` pairs.
+#[inline]
+fn headers() -> Vec<(String, String)> {
+    var("OTEL_EXPORTER_OTLP_TRACES_HEADERS")
+        .or_else(|| var("OTEL_EXPORTER_OTLP_HEADERS"))
+        .into_iter()
+        .flat_map(|headers| {
+            headers
+                .split(',')
+                .filter_map(|header| {
+                    let (key, value) = header.split_once('=')?;
+                    Some((key.trim().to_owned(), value.trim().to_owned()))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Get a random identifier of the given number of bytes,
+/// encoded as hexadecimal.
+#[inline]
+fn random_id(bytes: usize) -> String {
+    use ring::rand::SecureRandom as _;
+    use std::fmt::Write as _;
+
+    let mut id = vec![0; bytes];
+    ring::rand::SystemRandom::new()
+        .fill(&mut id)
+        .expect("the system random number generator should never fail");
+    id.iter().fold(String::new(), |mut hex, byte| {
+        write!(hex, "{byte:02x}").expect("writing to a string should never fail");
+        hex
+    })
+}
+
+/// Get the given time in nanoseconds since the Unix epoch,
+/// as OTLP/JSON expects it.
+#[inline]
+fn nanos(time: std::time::SystemTime) -> String {
+    time.duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos()
+        .to_string()
+}
+
+/// Span still open,
+/// see [`Collector`].
+#[derive(Debug)]
+struct OpenSpan {
+    span_id: String,
+    parent_id: Option<String>,
+    name: &'static str,
+    start: std::time::SystemTime,
+    attributes: Vec<(&'static str, String)>,
+
+    /// Recorded in the `error` field,
+    /// if the span failed.
+    failure: Option<String>,
+
+    /// Number of handles to the span,
+    /// which is closed once there are none.
+    handles: usize,
+}
+
+impl OpenSpan {
+    /// Close the span,
+    /// creating it in the OTLP/JSON encoding.
+    #[inline]
+    fn close(self, trace_id: &str) -> serde_json::Value {
+        let status = self.failure.map_or_else(
+            || serde_json::json!({ "code": STATUS_OK }),
+            |message| serde_json::json!({ "code": STATUS_ERROR, "message": message }),
+        );
+        let attributes: Vec<_> = self
+            .attributes
+            .into_iter()
+            .map(|(key, value)| serde_json::json!({"key": key, "value": {"stringValue": value}}))
+            .collect();
+        serde_json::json!({
+            "traceId": trace_id,
+            "spanId": self.span_id,
+            "parentSpanId": self.parent_id.unwrap_or_default(),
+            "name": self.name,
+            "kind": KIND_INTERNAL,
+            "startTimeUnixNano": nanos(self.start),
+            "endTimeUnixNano": nanos(std::time::SystemTime::now()),
+            "attributes": attributes,
+            "status": status,
+        })
+    }
+}
+
+impl tracing::field::Visit for OpenSpan {
+    #[inline]
+    fn record_str(&mut self, field: &tracing::field::Field, value: &str) {
+        if field.name() == "error" {
+            self.failure = Some(value.to_owned());
+        } else {
+            self.attributes.push((field.name(), value.to_owned()));
+        }
+    }
+
+    #[inline]
+    fn record_debug(&mut self, field: &tracing::field::Field, value: &dyn std::fmt::Debug) {
+        self.record_str(field, &format!("{value:?}"));
+    }
+}
+
+std::thread_local! {
+    /// Spans entered on this thread,
+    /// innermost last.
+    static ENTERED: std::cell::RefCell<Vec<u64>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Spans of ellie collected into a single trace,
+/// open and closed.
+#[derive(Debug)]
+struct Collector {
+    trace_id: String,
+    last_id: std::sync::atomic::AtomicU64,
+    open: std::sync::Mutex<std::collections::HashMap<u64, OpenSpan>>,
+    closed: std::sync::Mutex<Vec<serde_json::Value>>,
+}
+
+impl Collector {
+    #[inline]
+    fn open(&self) -> std::sync::MutexGuard<'_, std::collections::HashMap<u64, OpenSpan>> {
+        self.open
+            .lock()
+            .expect("open spans should never be poisoned")
+    }
+
+    #[inline]
+    fn closed(&self) -> std::sync::MutexGuard<'_, Vec<serde_json::Value>> {
+        self.closed
+            .lock()
+            .expect("closed spans should never be poisoned")
+    }
+}
+
+/// Subscriber collecting the spans of ellie
+/// (but neither events nor spans of dependencies)
+/// for [`Telemetry::export`].
+#[derive(Debug)]
+struct Subscriber(std::sync::Arc<Collector>);
+
+impl tracing::Subscriber for Subscriber {
+    #[inline]
+    fn enabled(&self, metadata: &tracing::Metadata<'_>) -> bool {
+        metadata.is_span() && metadata.target().starts_with(env!("CARGO_CRATE_NAME"))
+    }
+
+    #[inline]
+    fn new_span(&self, attributes: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        let id = self
+            .0
+            .last_id
+            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+            + 1;
+        let parent = if attributes.is_root() {
+            None
+        } else {
+            attributes
+                .parent()
+                .map(tracing::span::Id::into_u64)
+                .or_else(|| ENTERED.with(|entered| entered.borrow().last().copied()))
+        };
+        let mut open = self.0.open();
+        let mut span = OpenSpan {
+            span_id: random_id(8),
+            parent_id: parent
+                .and_then(|parent| open.get(&parent))
+                .map(|parent| parent.span_id.clone()),
+            name: attributes.metadata().name(),
+            start: std::time::SystemTime::now(),
+            attributes: Vec::new(),
+            failure: None,
+            handles: 1,
+        };
+        attributes.record(&mut span);
+        open.insert(id, span);
+        tracing::span::Id::from_u64(id)
+    }
+
+    #[inline]
+    fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+        if let Some(span) = self.0.open().get_mut(&span.into_u64()) {
+            values.record(span);
+        }
+    }
+
+    #[inline]
+    fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {}
+
+    #[inline]
+    fn event(&self, _event: &tracing::Event<'_>) {}
+
+    #[inline]
+    fn enter(&self, span: &tracing::span::Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    #[inline]
+    fn exit(&self, span: &tracing::span::Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(index) = entered.iter().rposition(|&id| id == span.into_u64()) {
+                entered.remove(index);
+            }
+        });
+    }
+
+    #[inline]
+    fn clone_span(&self, span: &tracing::span::Id) -> tracing::span::Id {
+        if let Some(span) = self.0.open().get_mut(&span.into_u64()) {
+            span.handles += 1;
+        }
+        span.clone()
+    }
+
+    #[inline]
+    fn try_close(&self, span: tracing::span::Id) -> bool {
+        let mut open = self.0.open();
+        let Some(handles) = open.get_mut(&span.into_u64()).map(|span| {
+            span.handles -= 1;
+            span.handles
+        }) else {
+            return false;
+        };
+        if handles > 0 {
+            return false;
+        }
+        if let Some(span) = open.remove(&span.into_u64()) {
+            self.0.closed().push(span.close(&self.0.trace_id));
+        }
+        true
+    }
+}
+
+/// Export of the spans of a run to an `OpenTelemetry` collector.
+#[derive(Debug)]
+pub struct Telemetry {
+    endpoint: String,
+    collector: std::sync::Arc<Collector>,
+}
+
+impl Telemetry {
+    /// Start collecting the spans of the run,
+    /// if a collector endpoint is configured,
+    /// doing nothing otherwise
+    /// (spans are then never even created).
+    #[inline]
+    pub fn install() -> Option<Self> {
+        let endpoint = endpoint()?;
+        let collector = std::sync::Arc::new(Collector {
+            trace_id: random_id(16),
+            last_id: std::sync::atomic::AtomicU64::new(0),
+            open: std::sync::Mutex::default(),
+            closed: std::sync::Mutex::default(),
+        });
+        if let Err(err) =
+            tracing::subscriber::set_global_default(Subscriber(std::sync::Arc::clone(&collector)))
+        {
+            log::warn!("could not collect spans: {err}");
+            return None;
+        }
+        Some(Self {
+            endpoint,
+            collector,
+        })
+    }
+
+    /// Export the spans closed so far as a single trace.
+    ///
+    /// Export failures are only logged,
+    /// since observability should never break a run.
+    #[inline]
+    pub async fn export(self) {
+        let spans = std::mem::take(&mut *self.collector.closed());
+        let count = spans.len();
+        let body = serde_json::json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [{
+                        "key": "service.name",
+                        "value": {
+                            "stringValue": var("OTEL_SERVICE_NAME")
+                                .as_deref()
+                                .unwrap_or(SERVICE_NAME),
+                        },
+                    }],
+                },
+                "scopeSpans": [{
+                    "scope": {
+                        "name": env!("CARGO_PKG_NAME"),
+                        "version": env!("CARGO_PKG_VERSION"),
+                    },
+                    "spans": spans,
+                }],
+            }],
+        });
+
+        let endpoint = self.endpoint;
+        log::info!("exporting {count} spans to '{endpoint}'");
+        let sent = tokio::task::spawn_blocking(move || {
+            let mut request = ureq::post(&endpoint).timeout(EXPORT_TIMEOUT);
+            for (name, value) in headers() {
+                request = request.set(&name, &value);
+            }
+            request
+                .send_json(body)
+                .map(drop)
+                .map_err(|err| format!("could not export spans to '{endpoint}': {err}"))
+        })
+        .await;
+        match sent {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => log::warn!("{err}"),
+            Err(err) => log::warn!("could not export spans: {err}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spans_are_collected_into_a_single_trace() {
+        let collector = std::sync::Arc::new(Collector {
+            trace_id: random_id(16),
+            last_id: std::sync::atomic::AtomicU64::new(0),
+            open: std::sync::Mutex::default(),
+            closed: std::sync::Mutex::default(),
+        });
+        let subscriber = Subscriber(std::sync::Arc::clone(&collector));
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("ellie");
+            root.in_scope(|| {
+                let span = tracing::info_span!(
+                    "function",
+                    name = "get_current_weather",
+                    error = tracing::field::Empty
+                );
+                span.record("error", "not found");
+            });
+        });
+
+        let closed = collector.closed();
+        let [function, root] = &closed[..] else {
+            panic!("expected two spans, got {closed:?}");
+        };
+        assert_eq!(root["name"], "ellie");
+        assert_eq!(root["parentSpanId"], "");
+        assert_eq!(root["status"]["code"], STATUS_OK);
+        assert_eq!(function["name"], "function");
+        assert_eq!(function["traceId"], root["traceId"]);
+        assert_eq!(function["parentSpanId"], root["spanId"]);
+        assert_eq!(
+            function["attributes"],
+            serde_json::json!([{"key": "name", "value": {"stringValue": "get_current_weather"}}])
+        );
+        assert_eq!(
+            function["status"],
+            serde_json::json!({"code": STATUS_ERROR, "message": "not found"})
+        );
+        assert!(collector.open().is_empty());
+    }
+}
@@ -0,0 +1,381 @@
+use async_openai::types as aot;
+
+/// Delay after the first rate-limited request.
+const INITIAL_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Longest delay between requests.
+const MAX_DELAY: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Delay below which requests are no longer throttled.
+const MIN_DELAY: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Fraction of the rate limit (of requests or tokens)
+/// left to the current window
+/// at or below which requests pause until the window resets.
+const LOW_REMAINING: f64 = 0.05;
+
+/// Adaptive delay before requests,
+/// shared by all requests of a run (e.g., in batch mode).
+///
+/// The delay doubles whenever a request is rate limited
+/// and halves whenever one succeeds,
+/// so that workloads settle near the rate limit
+/// instead of oscillating between bursts and rate-limited requests.
+///
+/// Requests made through [`Throttle::create_stream`] also report the
+/// `x-ratelimit-*` headers of their responses,
+/// so that requests pause before the limit is reached.
+#[derive(Debug, Default)]
+pub struct Throttle {
+    delay: std::sync::Mutex<std::time::Duration>,
+
+    /// When the rate limit window resets,
+    /// if requests must pause until then.
+    paused_until: std::sync::Mutex<Option<std::time::Instant>>,
+
+    http: reqwest::Client,
+}
+
+impl Throttle {
+    #[inline]
+    fn delay(&self) -> std::sync::MutexGuard<'_, std::time::Duration> {
+        self.delay
+            .lock()
+            .expect("throttle delay should never be poisoned")
+    }
+
+    #[inline]
+    fn paused_until(&self) -> std::sync::MutexGuard<'_, Option<std::time::Instant>> {
+        self.paused_until
+            .lock()
+            .expect("throttle pause should never be poisoned")
+    }
+
+    /// Wait for the current delay,
+    /// if any,
+    /// and until the rate limit window resets,
+    /// if it is nearly exhausted.
+    #[inline]
+    pub async fn wait(&self) {
+        let delay = *self.delay();
+        if !delay.is_zero() {
+            log::info!("throttling for {delay:?}");
+            tokio::time::sleep(delay).await;
+        }
+        // Every waiter sleeps until the pause is over,
+        // which is only forgotten once it has passed.
+        let paused_until = {
+            let mut paused_until = self.paused_until();
+            if paused_until.is_some_and(|until| until <= std::time::Instant::now()) {
+                *paused_until = None;
+            }
+            *paused_until
+        };
+        if let Some(paused_until) = paused_until {
+            log::info!(
+                "pausing for {pause:?} until the rate limit resets",
+                pause = paused_until.saturating_duration_since(std::time::Instant::now())
+            );
+            tokio::time::sleep_until(paused_until.into()).await;
+        }
+    }
+
+    /// Pause later requests if the given response headers report
+    /// a nearly exhausted rate limit of requests or tokens,
+    /// until it resets.
+    #[inline]
+    pub fn observe(&self, headers: &reqwest::header::HeaderMap) {
+        let pause = ["requests", "tokens"]
+            .into_iter()
+            .filter_map(|kind| {
+                let header = |name: &str| {
+                    headers
+                        .get(format!("x-ratelimit-{name}-{kind}"))?
+                        .to_str()
+                        .ok()
+                };
+                let remaining: f64 = header("remaining")?.parse().ok()?;
+                let limit: f64 = header("limit")
+                    .and_then(|limit| limit.parse().ok())
+                    .unwrap_or_default();
+                (remaining <= limit * LOW_REMAINING).then(|| {
+                    let reset = header("reset").and_then(parse_duration);
+                    log::info!("{remaining} {kind} left before the rate limit");
+                    reset.unwrap_or(INITIAL_DELAY)
+                })
+            })
+            .max();
+        if let Some(pause) = pause {
+            let paused_until = std::time::Instant::now() + pause.min(MAX_DELAY);
+            let mut current = self.paused_until();
+            *current = Some(current.map_or(paused_until, |current| current.max(paused_until)));
+        }
+    }
+
+    /// Create a response stream for the given request,
+    /// as the API client would,
+    /// but observing the rate limit headers of the response.
+    ///
+    /// # Errors
+    /// If the request failed or was rejected.
+    #[inline]
+    pub async fn create_stream(
+        &self,
+        config: &impl async_openai::config::Config,
+        mut request: aot::CreateChatCompletionRequest,
+    ) -> Result<aot::ChatCompletionResponseStream, async_openai::error::OpenAIError> {
+        use async_openai::error::OpenAIError;
+
+        request.stream = Some(true);
+        log::debug!(
+            "request '{request}'",
+            request = serde_json::to_string(&request)
+                .expect("serialization of requests should never fail")
+        );
+        let response = self
+            .http
+            .post(config.url("/chat/completions"))
+            .query(&config.query())
+            .headers(config.headers())
+            .json(&request)
+            .send()
+            .await?;
+        self.observe(response.headers());
+        // Reported in the stream as the API client does,
+        // so that rate-limited requests are retried alike.
+        let response = match response.error_for_status() {
+            Ok(response) => response,
+            Err(err) => {
+                return Ok(Box::pin(futures::stream::once(async {
+                    Err(OpenAIError::Reqwest(err))
+                })))
+            }
+        };
+        Ok(Box::pin(chunks(response.bytes_stream())))
+    }
+
+    /// Slow down after a rate-limited request.
+    #[inline]
+    pub fn rate_limited(&self) {
+        let mut delay = self.delay();
+        *delay = (*delay * 2).clamp(INITIAL_DELAY, MAX_DELAY);
+        log::warn!(
+            "rate limited, throttling requests by {delay:?}",
+            delay = *delay
+        );
+    }
+
+    /// Speed up after a successful request.
+    #[inline]
+    pub fn succeeded(&self) {
+        let mut delay = self.delay();
+        *delay /= 2;
+        if *delay < MIN_DELAY {
+            *delay = std::time::Duration::ZERO;
+        }
+    }
+}
+
+/// Parse the chunks of a streamed response out of its server-sent events,
+/// up to the final `[DONE]` event.
+#[inline]
+fn chunks<B: AsRef<[u8]>>(
+    bytes: impl futures::Stream<Item = Result<B, reqwest::Error>>,
+) -> impl futures::Stream<
+    Item = Result<aot::CreateChatCompletionStreamResponse, async_openai::error::OpenAIError>,
+> {
+    use async_openai::error::OpenAIError;
+    use eventsource_stream::Eventsource as _;
+    use futures::StreamExt as _;
+
+    bytes
+        .eventsource()
+        .take_while(|event| {
+            futures::future::ready(!matches!(event, Ok(event) if event.data == "[DONE]"))
+        })
+        .map(|event| match event {
+            Ok(event) => serde_json::from_str(&event.data).map_err(OpenAIError::JSONDeserialize),
+            Err(err) => Err(OpenAIError::StreamError(err.to_string())),
+        })
+}
+
+/// Parse a rate limit reset duration (e.g., `1s`, `6m0s` or `20ms`).
+#[inline]
+fn parse_duration(text: &str) -> Option<std::time::Duration> {
+    let mut total = 0.0;
+    let mut rest = text.trim();
+    while !rest.is_empty() {
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .filter(|&end| end > 0)?;
+        let value: f64 = rest[..end].parse().ok()?;
+        rest = &rest[end..];
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let seconds = match &rest[..unit_end] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += value * seconds;
+        rest = &rest[unit_end..];
+    }
+    Some(std::time::Duration::from_secs_f64(total))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reset_durations_are_parsed() {
+        let secs = std::time::Duration::from_secs_f64;
+        assert_eq!(parse_duration("1s"), Some(secs(1.0)));
+        assert_eq!(parse_duration("6m0s"), Some(secs(360.0)));
+        assert_eq!(parse_duration("1m30.5s"), Some(secs(90.5)));
+        assert_eq!(parse_duration("20ms"), Some(secs(0.02)));
+        assert_eq!(parse_duration("1h"), Some(secs(3600.0)));
+        assert_eq!(parse_duration("soon"), None);
+        assert_eq!(parse_duration("5"), None);
+    }
+
+    #[test]
+    fn nearly_exhausted_limits_pause_requests() {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            pairs
+                .iter()
+                .map(|&(name, value)| {
+                    (
+                        reqwest::header::HeaderName::from_static(name),
+                        reqwest::header::HeaderValue::from_static(value),
+                    )
+                })
+                .collect::<reqwest::header::HeaderMap>()
+        };
+
+        let throttle = Throttle::default();
+        throttle.observe(&headers(&[
+            ("x-ratelimit-limit-requests", "100"),
+            ("x-ratelimit-remaining-requests", "50"),
+            ("x-ratelimit-reset-requests", "30s"),
+        ]));
+        assert!(throttle.paused_until().is_none());
+
+        throttle.observe(&headers(&[
+            ("x-ratelimit-limit-tokens", "40000"),
+            ("x-ratelimit-remaining-tokens", "1000"),
+            ("x-ratelimit-reset-tokens", "30s"),
+        ]));
+        let paused_until = throttle.paused_until().expect("requests should pause");
+        let pause = paused_until.saturating_duration_since(std::time::Instant::now());
+        assert!(pause > std::time::Duration::from_secs(29));
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    #[test]
+    fn events_are_parsed_into_chunks_until_done() {
+        use futures::StreamExt as _;
+
+        let chunk = |content: &str| {
+            serde_json::json!({
+                "id": "chatcmpl-1",
+                "object": "chat.completion.chunk",
+                "created": 0,
+                "model": "gpt-3.5-turbo",
+                "choices": [{"index": 0, "delta": {"content": content}, "finish_reason": null}],
+            })
+            .to_string()
+        };
+        let body = format!(
+            ": keep-alive\r\n\r\ndata: {}\r\n\r\ndata: {}\n\ndata: [DONE]\n\ndata: {}\n\n",
+            chunk("Hello"),
+            chunk(", world"),
+            chunk("never"),
+        );
+        // Events split anywhere across network chunks.
+        let bytes: Vec<Result<_, reqwest::Error>> = body
+            .as_bytes()
+            .chunks(7)
+            .map(|bytes| Ok(bytes.to_vec()))
+            .collect();
+        let contents: Vec<_> = block_on(chunks(futures::stream::iter(bytes)).collect::<Vec<_>>())
+            .into_iter()
+            .map(|chunk| chunk.unwrap().choices[0].delta.content.clone().unwrap())
+            .collect();
+        assert_eq!(contents, ["Hello", ", world"]);
+
+        let bytes: [Result<_, reqwest::Error>; 1] = [Ok(b"data: {\"broken\r\n\r\n".to_vec())];
+        let chunks = block_on(chunks(futures::stream::iter(bytes)).collect::<Vec<_>>());
+        assert!(matches!(
+            chunks[..],
+            [Err(async_openai::error::OpenAIError::JSONDeserialize(_))]
+        ));
+    }
+
+    #[test]
+    fn rejected_requests_fail_in_the_stream_and_pause_requests() {
+        use futures::StreamExt as _;
+        use std::io::{Read as _, Write as _};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 4096];
+            let _ = stream.read(&mut request).unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 429 Too Many Requests\r\n\
+                      x-ratelimit-limit-requests: 100\r\n\
+                      x-ratelimit-remaining-requests: 0\r\n\
+                      x-ratelimit-reset-requests: 30s\r\n\
+                      content-length: 0\r\n\r\n",
+                )
+                .unwrap();
+        });
+
+        let throttle = Throttle::default();
+        let config =
+            async_openai::config::OpenAIConfig::new().with_api_base(format!("http://{address}"));
+        let request = aot::CreateChatCompletionRequestArgs::default()
+            .model("gpt-3.5-turbo")
+            .messages([])
+            .build()
+            .unwrap();
+        let first = block_on(async {
+            let mut stream = throttle.create_stream(&config, request).await.unwrap();
+            stream.next().await
+        });
+        server.join().unwrap();
+        assert!(matches!(
+            first,
+            Some(Err(async_openai::error::OpenAIError::Reqwest(err)))
+                if err.status() == Some(reqwest::StatusCode::TOO_MANY_REQUESTS)
+        ));
+        assert!(throttle.paused_until().is_some());
+    }
+
+    #[test]
+    fn pauses_hold_every_waiter_until_they_pass() {
+        let pause = std::time::Duration::from_millis(50);
+        let throttle = Throttle::default();
+        *throttle.paused_until() = Some(std::time::Instant::now() + pause);
+        let start = std::time::Instant::now();
+        block_on(async { futures::join!(throttle.wait(), throttle.wait()) });
+        assert!(start.elapsed() >= pause);
+        assert!(throttle.paused_until().is_some());
+
+        block_on(throttle.wait());
+        assert!(throttle.paused_until().is_none());
+    }
+}
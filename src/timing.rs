@@ -0,0 +1,43 @@
+/// Durations of the phases of a run,
+/// reported with `--time`.
+///
+/// Durations are recorded in order and may be recorded concurrently
+/// (e.g., in batch mode).
+#[derive(Debug, Default)]
+pub struct Timings {
+    entries: std::sync::Mutex<Vec<(String, std::time::Duration)>>,
+}
+
+impl Timings {
+    /// Record the duration of the given phase.
+    #[inline]
+    pub fn record(&self, phase: impl Into<String>, duration: std::time::Duration) {
+        self.entries
+            .lock()
+            .expect("timings should never be poisoned")
+            .push((phase.into(), duration));
+    }
+
+    /// Record the time elapsed since the given instant for the given phase.
+    #[inline]
+    pub fn record_since(&self, phase: impl Into<String>, start: std::time::Instant) {
+        self.record(phase, start.elapsed());
+    }
+
+    /// Print every recorded duration to the standard error.
+    #[inline]
+    pub fn report(&self) {
+        let entries = self
+            .entries
+            .lock()
+            .expect("timings should never be poisoned");
+        let width = entries
+            .iter()
+            .map(|(phase, _)| phase.len())
+            .max()
+            .unwrap_or_default();
+        for (phase, duration) in entries.iter() {
+            eprintln!("{phase:width$}  {duration:.3?}");
+        }
+    }
+}
@@ -0,0 +1,142 @@
+/// Streaming word wrapper.
+///
+/// Only the current word is buffered,
+/// so that content still appears progressively.
+/// ANSI escape sequences (e.g., from Markdown rendering) take no width,
+/// and words longer than the width are left unbroken.
+#[derive(Debug)]
+pub struct Wrapper {
+    width: usize,
+
+    /// Width of the current output line.
+    column: usize,
+
+    /// Spaces seen since the last word,
+    /// written only if the next word fits in the line.
+    spaces: usize,
+
+    /// Current word,
+    /// with its width.
+    word: String,
+    word_width: usize,
+
+    /// Whether an escape sequence is being read.
+    escape: bool,
+}
+
+impl Wrapper {
+    #[inline]
+    pub const fn new(width: usize) -> Self {
+        Self {
+            width,
+            column: 0,
+            spaces: 0,
+            word: String::new(),
+            word_width: 0,
+            escape: false,
+        }
+    }
+
+    /// Add a chunk of content,
+    /// returning the wrapped text that can be written already.
+    #[inline]
+    pub fn push(&mut self, chunk: &str) -> String {
+        let mut wrapped = String::new();
+        for c in chunk.chars() {
+            if self.escape {
+                self.word.push(c);
+                self.escape = !c.is_ascii_alphabetic();
+            } else if c == '\x1b' {
+                self.word.push(c);
+                self.escape = true;
+            } else if c == '\n' {
+                self.flush(&mut wrapped);
+                wrapped.push('\n');
+                self.column = 0;
+                self.spaces = 0;
+            } else if c == ' ' {
+                self.flush(&mut wrapped);
+                self.spaces += 1;
+            } else {
+                self.word.push(c);
+                self.word_width += 1;
+            }
+        }
+        wrapped
+    }
+
+    /// Write the last word,
+    /// returning it,
+    /// and start over at the beginning of a line.
+    #[inline]
+    pub fn finish(&mut self) -> String {
+        let mut wrapped = String::new();
+        self.flush(&mut wrapped);
+        self.column = 0;
+        self.spaces = 0;
+        self.escape = false;
+        wrapped
+    }
+
+    /// Write the current word,
+    /// breaking the line before it if it does not fit.
+    #[inline]
+    fn flush(&mut self, wrapped: &mut String) {
+        if self.word_width == 0 {
+            // Escape sequences alone take no room.
+            wrapped.push_str(&self.word);
+            self.word.clear();
+            return;
+        }
+        if self.column > 0 && self.column + self.spaces + self.word_width > self.width {
+            wrapped.push('\n');
+            self.column = 0;
+        } else {
+            wrapped.extend(std::iter::repeat(' ').take(self.spaces));
+            self.column += self.spaces;
+        }
+        wrapped.push_str(&self.word);
+        self.column += self.word_width;
+        self.spaces = 0;
+        self.word.clear();
+        self.word_width = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn words_are_wrapped_across_chunks() {
+        let mut wrapper = Wrapper::new(10);
+        let mut wrapped = wrapper.push("Hello wo");
+        wrapped.push_str(&wrapper.push("rld, this wraps"));
+        wrapped.push_str(&wrapper.finish());
+        assert_eq!(wrapped, "Hello\nworld,\nthis wraps");
+    }
+
+    #[test]
+    fn only_the_current_word_is_buffered() {
+        let mut wrapper = Wrapper::new(80);
+        assert_eq!(wrapper.push("Hello wor"), "Hello");
+        assert_eq!(wrapper.push("ld\n"), " world\n");
+        assert_eq!(wrapper.finish(), "");
+    }
+
+    #[test]
+    fn escape_sequences_take_no_width() {
+        let mut wrapper = Wrapper::new(5);
+        let mut wrapped = wrapper.push("\x1b[1mbold\x1b[22m text");
+        wrapped.push_str(&wrapper.finish());
+        assert_eq!(wrapped, "\x1b[1mbold\x1b[22m\ntext");
+    }
+
+    #[test]
+    fn long_words_are_left_unbroken() {
+        let mut wrapper = Wrapper::new(4);
+        let mut wrapped = wrapper.push("a verylongword b");
+        wrapped.push_str(&wrapper.finish());
+        assert_eq!(wrapped, "a\nverylongword\nb");
+    }
+}